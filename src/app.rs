@@ -17,8 +17,8 @@ use gloo::utils::window;
 use web_sys::{Blob, BlobPropertyBag, Event, HtmlInputElement, ProgressEvent, Url};
 //use crate::backend::Backend;
 use crate::frontend::Frontend;
+use crate::structs::Cell;
 //use crate::structs::{Cell, Operand, OperandType, OperandData, CellData, Function, CellError};
-use crate::structs::CellError;
 /// ThemeType enum to track current theme
 #[derive(Clone, PartialEq)]
 pub enum ThemeType {
@@ -105,7 +105,8 @@ pub struct CommandBarProps {
     pub theme: ThemeType, // Add theme prop
 }
 /// Props for the Tab Bar component
-/// This component is responsible for displaying the tab bar with buttons for saving, loading, undoing, and redoing actions.
+/// This component is responsible for displaying the tab bar with buttons for saving, loading, undoing, redoing and recalculating.
+/// It also shows a tab per sheet and a button to add a sheet.
 #[derive(Properties, PartialEq)]
 pub struct TabBarProps {
     pub frontend: UseStateHandle<Rc<RefCell<Frontend>>>,
@@ -194,7 +195,18 @@ pub fn grid(props: &GridProps) -> Html {
     // Get the current relationships for the selected cell using backend function
     let (parent_cells, child_cells) = {
         let (row, col) = *selected_cell;
-        backend.get_cell_dependencies(row, col)
+        let cell = backend.on_active_sheet(Cell { row, col });
+        let (parents, children) = backend.get_cell_dependencies(cell.row, cell.col);
+        // Only cells of the shown sheet are highlighted
+        let sheet_rows = backend.sheet_rows().span(backend.get_active_sheet());
+        let on_sheet = |cells: Vec<(usize, usize)>| -> Vec<(usize, usize)> {
+            cells
+                .into_iter()
+                .filter(|(row, _)| sheet_rows.contains(row))
+                .map(|(row, col)| (backend.sheet_rows().local_row(row), col))
+                .collect()
+        };
+        (on_sheet(parents), on_sheet(children))
     };
 
     // Function to convert column index to letter (0 -> A, 1 -> B, etc.)
@@ -294,14 +306,13 @@ pub fn grid(props: &GridProps) -> Html {
                                 </td>
                                 {(0..props.cols).map(|col| {
                                     let key = format!("{}-{}", row, col);
+                                    let cell = backend.on_active_sheet(Cell { row, col });
                                     let celldata = unsafe {
-                                        backend.get_cell_value(row, col)
+                                        backend.get_cell_value(cell.row, cell.col)
                                     };
-                                     let val = unsafe{if (*celldata).error == CellError::NoError {
-                                            (*celldata).value.to_string()
-                                        } else {
-                                            "ERR".to_string()
-                                        }
+                                    let (val, text_align) = unsafe {
+                                        let value = &(*celldata).value;
+                                        (value.to_string(), if value.is_left_aligned() { "left" } else { "right" })
                                     };
                                     // let val = unsafe {
                                     //     backend.get_cell_value(row, col).value.to_string()
@@ -323,13 +334,13 @@ pub fn grid(props: &GridProps) -> Html {
                                         border: 1px solid {};
                                         padding: 2px;
                                         background-color: {};
-                                        text-align: left;
+                                        text-align: {};
                                         vertical-align: middle;
                                         overflow: hidden;
                                         text-overflow: ellipsis;
                                         white-space: nowrap;
                                         color: {};
-                                    ", colors.border, bg_color, colors.text);
+                                    ", colors.border, bg_color, text_align, colors.text);
 
                                     let onclick = {
                                         let selected_cell = selected_cell.clone();
@@ -372,7 +383,8 @@ pub fn formula_bar(props: &FormulaBarProps) -> Html {
     let formula = {
         let backend = frontend.get_backend_mut();
         let (row, col) = *selected_cell;
-        backend.formula_strings[row][col].clone()
+        let cell = backend.on_active_sheet(Cell { row, col });
+        backend.formula_string(cell)
     };
 
     html! {
@@ -396,7 +408,8 @@ pub fn formula_bar(props: &FormulaBarProps) -> Html {
 pub fn command_bar(props: &CommandBarProps) -> Html {
     //let input_value = use_state(|| String::new());
     let input_value = use_state(String::new);
-    let status = use_state(|| None::<bool>);
+    // Result of the last command, with the reason of a failure when there is one
+    let status = use_state(|| None::<Result<(), String>>);
     let input_ref = use_node_ref();
 
     // Get theme colors
@@ -422,10 +435,12 @@ pub fn command_bar(props: &CommandBarProps) -> Html {
 
                 if result {
                     update_trigger.set(*update_trigger + 1);
+                    status.set(Some(Ok(())));
+                } else {
+                    let reason = frontend.last_error().unwrap_or_default().to_string();
+                    status.set(Some(Err(reason)));
                 }
 
-                status.set(Some(result));
-
                 if let Some(input) = input_ref.cast::<web_sys::HtmlInputElement>() {
                     input.set_value("");
                     input_value.set(String::new());
@@ -443,11 +458,14 @@ pub fn command_bar(props: &CommandBarProps) -> Html {
         })
     };
 
-    let status_display = match *status {
-        Some(true) => {
+    let status_display = match &*status {
+        Some(Ok(())) => {
             html! { <span style="color: green; margin-right: 10px; min-width: 40px;">{ "OK" }</span> }
         }
-        Some(false) => {
+        Some(Err(reason)) if !reason.is_empty() => {
+            html! { <span style="color: red; margin-right: 10px; min-width: 40px;">{ format!("Error: {}", reason) }</span> }
+        }
+        Some(Err(_)) => {
             html! { <span style="color: red; margin-right: 10px; min-width: 40px;">{ "Error" }</span> }
         }
         None => html! { <span style="margin-right: 10px; min-width: 40px;"></span> },
@@ -497,8 +515,8 @@ pub fn download_csv(content: String, filename: &str) {
     Url::revoke_object_url(&url).unwrap();
 }
 //// Tab Bar component
-/// This component is responsible for displaying the tab bar with buttons for saving, loading, undoing, and redoing actions.
-/// It also includes theme toggle buttons for light and dark themes.
+/// This component is responsible for displaying the tab bar with buttons for saving, loading, undoing, redoing and recalculating.
+/// It also includes the sheet tabs and theme toggle buttons for light and dark themes.
 #[function_component(TabBar)]
 pub fn tab_bar(props: &TabBarProps) -> Html {
     let frontend = props.frontend.clone();
@@ -506,8 +524,6 @@ pub fn tab_bar(props: &TabBarProps) -> Html {
     // let status_message = use_state(|| String::new());
     let status_message = use_state(String::new);
     let file_input_ref = use_node_ref();
-    let rows = props.rows;
-    let cols = props.cols;
     let theme = props.theme.clone();
 
     // Get theme colors
@@ -567,6 +583,48 @@ pub fn tab_bar(props: &TabBarProps) -> Html {
         })
     };
 
+    // Recalculates the volatile cells (NOW, RAND, ...) and the cells depending on them
+    let recalc_onclick = {
+        let frontend = frontend.clone();
+        let update_trigger = update_trigger.clone();
+        let status_message = status_message.clone();
+
+        Callback::from(move |_: MouseEvent| {
+            let mut frontend = frontend.borrow_mut();
+            frontend.get_backend_mut().recalculate_volatile();
+            update_trigger.set(*update_trigger + 1);
+            status_message.set("Recalculated".to_string());
+
+            // Clear message after 3 seconds
+            let status_message = status_message.clone();
+            gloo::timers::callback::Timeout::new(3000, move || {
+                status_message.set(String::new());
+            })
+            .forget();
+        })
+    };
+
+    // Rebuilds the dependencies from the formulas and recalculates every cell
+    let recalc_all_onclick = {
+        let frontend = frontend.clone();
+        let update_trigger = update_trigger.clone();
+        let status_message = status_message.clone();
+
+        Callback::from(move |_: MouseEvent| {
+            let mut frontend = frontend.borrow_mut();
+            frontend.get_backend_mut().recalculate_all();
+            update_trigger.set(*update_trigger + 1);
+            status_message.set("Recalculated all cells".to_string());
+
+            // Clear message after 3 seconds
+            let status_message = status_message.clone();
+            gloo::timers::callback::Timeout::new(3000, move || {
+                status_message.set(String::new());
+            })
+            .forget();
+        })
+    };
+
     // Save functionality
     let save_onclick = {
         let frontend = frontend.clone();
@@ -576,29 +634,16 @@ pub fn tab_bar(props: &TabBarProps) -> Html {
             let mut frontend = frontend.borrow_mut();
             let backend = frontend.get_backend_mut();
 
-            // Generate CSV content
-            let mut csv = String::new();
-            for row in 0..rows {
-                let mut line = Vec::new();
-                for col in 0..cols {
-                    unsafe {
-                        let celldata = backend.get_cell_value(row, col);
-                        let val = if (*celldata).error == CellError::NoError {
-                            (*celldata).value.to_string()
-                        } else {
-                            "Error".to_string()
-                        };
-                        line.push(val);
-                    }
+            // Generate CSV content, formulas are saved as formulas
+            match backend.active_sheet_csv() {
+                Ok(csv) => {
+                    // Trigger download
+                    download_csv(csv, "spreadsheet.csv");
+                    status_message.set("File saved successfully".to_string());
                 }
-                csv.push_str(&line.join(","));
-                csv.push('\n');
+                Err(e) => status_message.set(format!("Save failed: {}", e)),
             }
 
-            // Trigger download
-            download_csv(csv, "spreadsheet.csv");
-            status_message.set("File saved successfully".to_string());
-
             // Clear message after 3 seconds
             let status_message = status_message.clone();
             gloo::timers::callback::Timeout::new(3000, move || {
@@ -670,6 +715,51 @@ pub fn tab_bar(props: &TabBarProps) -> Html {
         })
     };
 
+    // Sheet tabs, clicking one shows that sheet
+    let sheet_names = frontend.borrow_mut().get_backend_mut().get_sheet_names().to_vec();
+    let active_sheet = frontend.borrow_mut().get_backend_mut().get_active_sheet();
+    let sheet_tabs = sheet_names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let onclick = {
+                let frontend = frontend.clone();
+                let update_trigger = update_trigger.clone();
+                let name = name.clone();
+                Callback::from(move |_: MouseEvent| {
+                    let mut frontend = frontend.borrow_mut();
+                    if frontend.get_backend_mut().switch_sheet(&name) {
+                        update_trigger.set(*update_trigger + 1);
+                    }
+                })
+            };
+            let weight = if index == active_sheet { "bold" } else { "normal" };
+            html! {
+                <button key={name.clone()} {onclick} style={format!("font-weight: {};", weight)}>
+                    { name.clone() }
+                </button>
+            }
+        })
+        .collect::<Html>();
+
+    let add_sheet_onclick = {
+        let frontend = frontend.clone();
+        let update_trigger = update_trigger.clone();
+        Callback::from(move |_: MouseEvent| {
+            let mut frontend = frontend.borrow_mut();
+            let backend = frontend.get_backend_mut();
+            // First free SheetN name
+            let name = (1..)
+                .map(|n| format!("Sheet{}", n))
+                .find(|name| backend.sheet_index(name).is_none())
+                .unwrap();
+            backend.push_undo_state();
+            if backend.add_sheet(&name) && backend.switch_sheet(&name) {
+                update_trigger.set(*update_trigger + 1);
+            }
+        })
+    };
+
     // Button style based on current theme
     let button_style = format!(
         "padding: 5px 10px; margin: 0 2px; border: 1px solid {}; background-color: {}; color: {};",
@@ -694,6 +784,13 @@ pub fn tab_bar(props: &TabBarProps) -> Html {
             <button onclick={load_onclick}>{ "Load" }</button>
             <button onclick={undo_onclick}>{ "Undo" }</button>
             <button onclick={redo_onclick}>{ "Redo" }</button>
+            <button onclick={recalc_onclick}>{ "Recalc" }</button>
+            <button onclick={recalc_all_onclick}>{ "Recalc All" }</button>
+            </div>
+
+            <div style="display: flex; gap: 2px;">
+                { sheet_tabs }
+                <button onclick={add_sheet_onclick}>{ "+" }</button>
             </div>
 
             <div style="display: flex; gap: 5px;">
@@ -4,7 +4,8 @@
 //! including cell management, formula evaluation, and dependency tracking.
 use crate::structs::*;
 use std::cell::UnsafeCell;
-use std::cmp::{max, min};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::f64;
 use std::thread;
 use std::time::Duration;
@@ -15,7 +16,7 @@ use std::collections::VecDeque;
 #[cfg(feature = "gui")]
 use std::fs::File;
 #[cfg(feature = "gui")]
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 
 #[cfg(feature = "gui")]
 use csv::{ReaderBuilder, WriterBuilder};
@@ -30,20 +31,47 @@ pub struct Backend {
     rows: usize,
     /// Number of columns in the spreadsheet
     cols: usize,
+    /// Sheet names in tab order, stacked in the grid as `sheet_rows` describes
+    sheet_names: Vec<String>,
+    /// Sheet shown by the frontend, references without a sheet name point into it
+    active_sheet: usize,
+    /// Defined names and the cell, range or constant each one stands for
+    names: BTreeMap<String, Expr>,
+    /// Cells whose formula uses each name, rebuilt when the name is redefined
+    name_users: HashMap<String, Vec<Cell>>,
+    /// Cells whose formula calls a volatile function, refreshed by `recalculate_volatile`
+    volatile_cells: Vec<Cell>,
+    /// Ranges read by formulas with the cell reading them, kept once here instead of in the
+    /// `dependents` of every cell they cover, which only hold single cell references
+    range_users: RangeIndex,
+    /// Summaries of the columns read by SUM, MIN, MAX, AVG and COUNT, built on first use and
+    /// updated with every value written, so a range is aggregated in O(log rows) per column
+    column_summaries: std::cell::RefCell<HashMap<usize, SummaryTree>>,
+    /// State of the random number generator behind RAND and RANDBETWEEN
+    random_state: std::cell::Cell<u64>,
 
     #[cfg(feature = "gui")]
-    /// String representations of formulas for display
-    pub formula_strings: Vec<Vec<String>>,
-
+    /// Clipboard storage for copy/paste operations, holds the formulas of the copied cells
+    pub copy_stack: Vec<Vec<Function>>,
     #[cfg(feature = "gui")]
-    /// Clipboard storage for copy/paste operations
-    pub copy_stack: Vec<Vec<i32>>,
+    /// Top left cell of the copied range, pasted formulas are relocated relative to it
+    copy_origin: Cell,
     #[cfg(feature = "gui")]
     /// Undo stack for storing previous states of the spreadsheet
-    undo_stack: VecDeque<Vec<Vec<(CellData, String)>>>,
+    undo_stack: VecDeque<Snapshot>,
     #[cfg(feature = "gui")]
     /// Redo stack for storing states that can be redone
-    redo_stack: VecDeque<Vec<Vec<(CellData, String)>>>,
+    redo_stack: VecDeque<Snapshot>,
+}
+#[cfg(feature = "gui")]
+/// State restored by undo and redo: the cells of every sheet together with the sheets and
+/// the defined names, so undoing past `add_sheet` removes the sheet and its rows again
+#[derive(Debug)]
+pub struct Snapshot {
+    cells: Vec<Vec<CellData>>,
+    sheet_names: Vec<String>,
+    active_sheet: usize,
+    names: BTreeMap<String, Expr>,
 }
 #[cfg(feature = "gui")]
 type CellDependencies = (Vec<(usize, usize)>, Vec<(usize, usize)>);
@@ -58,33 +86,26 @@ impl Backend {
             let cell_data = self.get_cell_value(row, col);
 
             // Collect children (dependents)
-            for &(child_row, child_col) in &(*cell_data).dependents {
+            for (child_row, child_col) in self.cell_dependents(&Cell { row, col }) {
                 children.push((child_row as usize, child_col as usize));
             }
 
-            // Collect parents (cells this cell depends on)
-            match &(*cell_data).function.data {
-                FunctionData::RangeFunction(range) => {
-                    for r in range.top_left.row..=range.bottom_right.row {
-                        for c in range.top_left.col..=range.bottom_right.col {
-                            parents.push((r, c));
-                        }
-                    }
-                }
-                FunctionData::BinaryOp(bin_op) => {
-                    if let OperandData::Cell(dep) = bin_op.first.data {
-                        parents.push((dep.row, dep.col));
-                    }
-                    if let OperandData::Cell(dep) = bin_op.second.data {
-                        parents.push((dep.row, dep.col));
-                    }
-                }
-                FunctionData::SleepValue(operand) => {
-                    if let OperandData::Cell(dep) = operand.data {
-                        parents.push((dep.row, dep.col));
+            // Collect parents (cells this cell depends on), ranges cell by cell
+            let mut cells = Vec::new();
+            let mut ranges = Vec::new();
+            (*cell_data)
+                .function
+                .data
+                .collect_references(&mut cells, &mut ranges);
+            for dep in cells {
+                parents.push((dep.row, dep.col));
+            }
+            for range in ranges {
+                for r in range.top_left.row..=range.bottom_right.row {
+                    for c in range.top_left.col..=range.bottom_right.col {
+                        parents.push((r, c));
                     }
                 }
-                FunctionData::Value(_) => {} // No parents for constant values
             }
         }
 
@@ -97,10 +118,9 @@ impl Backend {
     /// Creates a new spreadsheet backend with the specified dimensions.
     ///
     /// Initializes all cells with:
-    /// - Value of 0
+    /// - Empty value
     /// - No dependencies
     /// - Constant function type
-    /// - No errors
     ///
     /// # Arguments
     /// * `rows` - Number of rows in the spreadsheet
@@ -121,13 +141,7 @@ impl Backend {
         for _row in 0..rows {
             let mut row_vec = Vec::with_capacity(cols);
             for _col in 0..cols {
-                row_vec.push(CellData {
-                    value: 0,
-                    dependents: Vec::new(),
-                    function: Function::new_constant(0),
-                    error: CellError::NoError,
-                    dirty_parents: 0,
-                });
+                row_vec.push(CellData::default());
             }
             grid.push(row_vec);
         }
@@ -140,17 +154,111 @@ impl Backend {
             redo_stack: VecDeque::with_capacity(100),
             rows,
             cols,
+            sheet_names: vec!["Sheet1".to_string()],
+            active_sheet: 0,
+            names: BTreeMap::new(),
+            name_users: HashMap::new(),
+            volatile_cells: Vec::new(),
+            range_users: RangeIndex::default(),
+            column_summaries: std::cell::RefCell::new(HashMap::new()),
+            // Seeded from the clock so every session draws different numbers
+            random_state: std::cell::Cell::new(now_serial().to_bits()),
             #[cfg(feature = "gui")]
-            formula_strings: vec![vec!["=0".to_string(); cols]; rows],
-
+            copy_stack: vec![vec![Function::new_literal(CellValue::Empty); 1]; 1],
             #[cfg(feature = "gui")]
-            copy_stack: vec![vec![0; 1]; 1],
+            copy_origin: Cell { row: 0, col: 0 },
         }
     }
 
+    /// Returns the sheet names in tab order
+    pub fn get_sheet_names(&self) -> &[String] {
+        &self.sheet_names
+    }
+    /// Returns the index of the active sheet
+    pub fn get_active_sheet(&self) -> usize {
+        self.active_sheet
+    }
+    /// Returns the index of the sheet with the given name
+    pub fn sheet_index(&self, name: &str) -> Option<usize> {
+        self.sheet_names
+            .iter()
+            .position(|sheet| sheet.eq_ignore_ascii_case(name))
+    }
+    /// Returns how the sheets are stacked in the grid, the one mapping between workbook
+    /// rows and sheets
+    pub fn sheet_rows(&self) -> SheetRows {
+        SheetRows { rows: self.rows }
+    }
+    /// Returns the number of grid rows, the rows of all sheets together
+    fn workbook_rows(&self) -> usize {
+        self.sheet_rows().workbook_row(self.sheet_names.len(), 0)
+    }
+    /// Returns the workbook cell of a cell given by its position on the active sheet
+    pub fn on_active_sheet(&self, cell: Cell) -> Cell {
+        Cell {
+            row: self.sheet_rows().workbook_row(self.active_sheet, cell.row),
+            col: cell.col,
+        }
+    }
+    /// Returns the layout used to print the formula of `cell`
+    pub fn sheet_layout(&self, cell: Cell) -> SheetLayout<'_> {
+        SheetLayout {
+            names: &self.sheet_names,
+            sheets: self.sheet_rows(),
+            home: self.sheet_rows().sheet(cell.row),
+        }
+    }
+    /// Adds an empty sheet after the last one
+    /// Returns false if the name is not a valid sheet name or is already taken
+    /// # Usage: add_sheet Sheet2
+    pub fn add_sheet(&mut self, name: &str) -> bool {
+        if !crate::parser::is_valid_sheet_name(name) || self.sheet_index(name).is_some() {
+            return false;
+        }
+        let grid = self.grid.get_mut();
+        for _row in 0..self.rows {
+            grid.push((0..self.cols).map(|_| CellData::default()).collect());
+        }
+        // The column summaries cover every sheet, they are built again for the new rows
+        self.column_summaries.get_mut().clear();
+        self.sheet_names.push(name.to_string());
+        true
+    }
+    /// Renames a sheet, formulas referring to it show the new name
+    /// Returns false if there is no sheet `old` or `new` is not a valid, free name
+    /// # Usage: rename_sheet Sheet2 Costs
+    pub fn rename_sheet(&mut self, old: &str, new: &str) -> bool {
+        let Some(sheet) = self.sheet_index(old) else {
+            return false;
+        };
+        // Changing only the case of the name is fine
+        let taken = self.sheet_index(new).is_some_and(|other| other != sheet);
+        if !crate::parser::is_valid_sheet_name(new) || taken {
+            return false;
+        }
+        self.sheet_names[sheet] = new.to_string();
+        true
+    }
+    /// Makes a sheet the active one, returns false if there is no such sheet
+    /// # Usage: switch_sheet Sheet2
+    pub fn switch_sheet(&mut self, name: &str) -> bool {
+        match self.sheet_index(name) {
+            Some(sheet) => {
+                self.active_sheet = sheet;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns the formula of a cell in canonical form, as shown in the formula bar
+    /// Printed from the stored function, so it follows renamed sheets, sorting and undo.
+    pub fn formula_string(&self, cell: Cell) -> String {
+        let function = unsafe { &(*self.get_cell_value(cell.row, cell.col)).function };
+        function.to_formula_string(&self.sheet_layout(cell))
+    }
     /// Gets a mutable pointer to a cell's data (unsafe)
     pub unsafe fn get_cell_value(&self, row: usize, col: usize) -> *mut CellData {
-        let grid_ptr = (*self.grid.get())[row].as_mut_ptr();
+        let grid_ptr = (&mut (*self.grid.get()))[row].as_mut_ptr();
         grid_ptr.add(col)
     }
     /// Resets the `dirty_parents` flag for a starting cell and all its dependent cells.
@@ -162,21 +270,32 @@ impl Backend {
         unsafe {
             let start_cell = self.get_cell_value(start.row, start.col);
             (*start_cell).dirty_parents = 0;
-            let mut stack = vec![start_cell];
+            let mut stack = vec![*start];
 
             while let Some(current) = stack.pop() {
-                let deps = &(*current).dependents; // Access the dependents vector
-                for &(row, col) in deps.iter() {
+                for (row, col) in self.cell_dependents(&current) {
                     let dep = self.get_cell_value(row as usize, col as usize); // Access the dependent cell
 
                     if (*dep).dirty_parents > 0 {
                         (*dep).dirty_parents = 0;
-                        stack.push(dep);
+                        stack.push(Cell {
+                            row: row as usize,
+                            col: col as usize,
+                        });
                     }
                 }
             }
         }
     }
+    /// Returns the cells whose formula reads `cell`, directly or through a range
+    /// Single cell references come from the `dependents` of the cell, ranges holding it are
+    /// looked up in the range index.
+    pub fn cell_dependents(&self, cell: &Cell) -> Vec<(i32, i32)> {
+        let cell_data = unsafe { &*self.get_cell_value(cell.row, cell.col) };
+        let mut dependents = cell_data.dependents.clone();
+        self.range_users.collect_users(*cell, &mut dependents);
+        dependents
+    }
 
     /// Checks for circular dependencies starting from a given cell using DFS.
     ///
@@ -207,13 +326,11 @@ impl Backend {
 
         unsafe {
             let start_cell = self.get_cell_value(start.row, start.col);
-            let start_cell_ptr = start_cell as *const CellData;
-            let mut stack = vec![start_cell_ptr];
+            let mut stack = vec![*start];
             (*start_cell).dirty_parents = 1;
 
-            while let Some(current_ptr) = stack.pop() {
-                let current = &*current_ptr;
-                let deps = &current.dependents;
+            while let Some(current) = stack.pop() {
+                let deps = self.cell_dependents(&current);
 
                 // First pass: check for cycles and collect new deps to process
                 let mut deps_to_check = Vec::new();
@@ -235,7 +352,10 @@ impl Backend {
                     let dep = self.get_cell_value(dep_ptr.0 as usize, dep_ptr.1 as usize);
                     if (*dep).dirty_parents == 0 {
                         (*dep).dirty_parents = 1;
-                        stack.push(dep);
+                        stack.push(Cell {
+                            row: dep_ptr.0 as usize,
+                            col: dep_ptr.1 as usize,
+                        });
                     }
                 }
             }
@@ -255,103 +375,270 @@ impl Backend {
     /// * `old_function` - The previous function/formula of the cell
     ///
     pub fn update_graph(&mut self, cell: &Cell, old_function: &Function) {
+        let dependent = (cell.row as i32, cell.col as i32);
         unsafe {
-            // Remove old dependencies
             let cell_data = self.get_cell_value(cell.row, cell.col);
 
-            match &old_function.data {
-                FunctionData::RangeFunction(range) => {
-                    for row in range.top_left.row..=range.bottom_right.row {
-                        for col in range.top_left.col..=range.bottom_right.col {
-                            let parent_data = self.get_cell_value(row, col);
-                            let deps = &mut (*parent_data).dependents;
-                            deps.retain(|&(r, c)| !(r == cell.row as i32 && c == cell.col as i32));
-                        }
-                    }
-                }
+            // Remove old dependencies
+            let mut cells = Vec::new();
+            let mut ranges = Vec::new();
+            old_function.data.collect_references(&mut cells, &mut ranges);
+            for parent in cells.drain(..) {
+                let deps = &mut (*self.get_cell_value(parent.row, parent.col)).dependents;
+                deps.retain(|&dep| dep != dependent);
+            }
+            for range in ranges.drain(..) {
+                self.range_users.remove(&range, *cell);
+            }
 
-                FunctionData::BinaryOp(bin_op) => {
-                    if let OperandData::Cell(dep) = bin_op.first.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.retain(|&(r, c)| !(r == cell.row as i32 && c == cell.col as i32));
-                    }
-                    if let OperandData::Cell(dep) = bin_op.second.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.retain(|&(r, c)| !(r == cell.row as i32 && c == cell.col as i32));
-                    }
-                }
+            // Add new dependencies, a range is kept once in the range index
+            (*cell_data)
+                .function
+                .data
+                .collect_references(&mut cells, &mut ranges);
+            for parent in cells {
+                let deps = &mut (*self.get_cell_value(parent.row, parent.col)).dependents;
+                deps.push(dependent);
+            }
+            for range in ranges {
+                self.range_users.insert(&range, *cell);
+            }
 
-                FunctionData::SleepValue(operand) => {
-                    if let OperandData::Cell(dep) = operand.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.retain(|&(r, c)| !(r == cell.row as i32 && c == cell.col as i32));
-                    }
+            // Track which cells use each name
+            let mut names = Vec::new();
+            for expr in old_function.data.trees() {
+                expr.collect_names(&mut names);
+            }
+            for name in names.drain(..) {
+                if let Some(users) = self.name_users.get_mut(&name) {
+                    users.retain(|user| user != cell);
+                }
+            }
+            for expr in (*cell_data).function.data.trees() {
+                expr.collect_names(&mut names);
+            }
+            for name in names {
+                let users = self.name_users.entry(name).or_default();
+                if !users.contains(cell) {
+                    users.push(*cell);
                 }
-
-                FunctionData::Value(_) => {} // No dependencies to remove
             }
 
-            // Add new dependencies
-            match &(*cell_data).function.data {
-                FunctionData::RangeFunction(range) => {
-                    for row in range.top_left.row..=range.bottom_right.row {
-                        for col in range.top_left.col..=range.bottom_right.col {
-                            let parent_data = self.get_cell_value(row, col);
-                            let deps = &mut (*parent_data).dependents;
-                            deps.push((cell.row as i32, cell.col as i32));
-                        }
+            // Track the cells to refresh on recalculation
+            let volatile = (*cell_data).function.data.trees().iter().any(Expr::is_volatile);
+            if !volatile {
+                self.volatile_cells.retain(|volatile_cell| volatile_cell != cell);
+            } else if !self.volatile_cells.contains(cell) {
+                self.volatile_cells.push(*cell);
+            }
+        }
+    }
+    /// Evaluates the cells calling a volatile function again and updates the cells depending
+    /// on them
+    /// # Usage: recalc
+    pub fn recalculate_volatile(&mut self) {
+        let cells = self.volatile_cells.clone();
+        self.recalculate_cells(&cells);
+    }
+    /// Rebuilds the dependency graph from the formulas and evaluates every formula again
+    /// # Usage: recalc_all
+    pub fn recalculate_all(&mut self) {
+        let formulas = self.rebuild_graph();
+        self.recalculate_cells(&formulas);
+    }
+    /// Rebuilds the dependency graph from the formulas of the cells, returns the formula cells
+    fn rebuild_graph(&mut self) -> Vec<Cell> {
+        let empty = Function::new_literal(CellValue::Empty);
+        let mut formulas = Vec::new();
+        unsafe {
+            for row in &mut *self.grid.get() {
+                for cell_data in row {
+                    cell_data.dependents.clear();
+                    cell_data.dirty_parents = 0;
+                }
+            }
+        }
+        self.name_users.clear();
+        self.volatile_cells.clear();
+        self.range_users = RangeIndex::default();
+        self.column_summaries.get_mut().clear();
+        for row in 0..self.workbook_rows() {
+            for col in 0..self.cols {
+                let cell = Cell { row, col };
+                let is_formula = unsafe {
+                    (*self.get_cell_value(row, col)).function.type_ != FunctionType::Constant
+                };
+                if is_formula {
+                    // Against an empty old formula only the edges of the current one are added
+                    self.update_graph(&cell, &empty);
+                    formulas.push(cell);
+                }
+            }
+        }
+        formulas
+    }
+    /// Evaluates `cells` and every cell depending on them again in topological order, so each
+    /// cell is evaluated once, after all the cells it reads
+    fn recalculate_cells(&mut self, cells: &[Cell]) {
+        let mut reached = HashSet::new();
+        let mut order = Vec::new();
+        for cell in cells {
+            if reached.insert((cell.row as i32, cell.col as i32)) {
+                order.push((cell.row as i32, cell.col as i32));
+            }
+        }
+        unsafe {
+            // Count for every reached cell how many reached cells it reads
+            let mut stack = order.clone();
+            while let Some((row, col)) = stack.pop() {
+                let current = Cell {
+                    row: row as usize,
+                    col: col as usize,
+                };
+                for (dep_row, dep_col) in self.cell_dependents(&current) {
+                    let dependent_data = self.get_cell_value(dep_row as usize, dep_col as usize);
+                    (*dependent_data).dirty_parents += 1;
+                    let dependent = (dep_row, dep_col);
+                    if reached.insert(dependent) {
+                        order.push(dependent);
+                        stack.push(dependent);
                     }
                 }
+            }
 
-                FunctionData::BinaryOp(bin_op) => {
-                    if let OperandData::Cell(dep) = bin_op.first.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.push((cell.row as i32, cell.col as i32));
-                    }
-                    if let OperandData::Cell(dep) = bin_op.second.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.push((cell.row as i32, cell.col as i32));
+            // Evaluate a cell once every cell it reads is up to date
+            let mut process_stack: Vec<(i32, i32)> = order
+                .into_iter()
+                .filter(|&(row, col)| {
+                    (*self.get_cell_value(row as usize, col as usize)).dirty_parents == 0
+                })
+                .collect();
+            while let Some((row, col)) = process_stack.pop() {
+                let current = Cell {
+                    row: row as usize,
+                    col: col as usize,
+                };
+                let current_data = self.get_cell_value(current.row, current.col);
+                self.store_value(current, self.evaluate_expression(&(*current_data).function));
+
+                for (dep_row, dep_col) in self.cell_dependents(&current) {
+                    let dependent_data = self.get_cell_value(dep_row as usize, dep_col as usize);
+                    (*dependent_data).dirty_parents -= 1;
+                    if (*dependent_data).dirty_parents == 0 {
+                        process_stack.push((dep_row, dep_col));
                     }
                 }
-
-                FunctionData::SleepValue(operand) => {
-                    if let OperandData::Cell(dep) = operand.data {
-                        let parent_data = self.get_cell_value(dep.row, dep.col);
-                        let deps = &mut (*parent_data).dependents;
-                        deps.push((cell.row as i32, cell.col as i32));
+            }
+        }
+    }
+    /// Restarts the random number generator, the same seed draws the same numbers again
+    /// # Usage: seed 42
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random_state.set(seed);
+    }
+    /// Draws the next random number (SplitMix64), uniform in [0, 1)
+    fn next_random(&self) -> f64 {
+        let state = self.random_state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.random_state.set(state);
+        // The top 53 bits fill the mantissa of an f64
+        (mix_bits(state) >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// Returns what a name currently stands for, #NAME? if it is not defined
+    pub fn resolve_name(&self, name: &str) -> Expr {
+        self.names
+            .get(name)
+            .cloned()
+            .unwrap_or(Expr::Error(CellError::UnknownName))
+    }
+    /// Returns the defined names in alphabetical order with what they stand for
+    pub fn get_names(&self) -> &BTreeMap<String, Expr> {
+        &self.names
+    }
+    /// Defines (or redefines) a name for a cell, a range or a constant
+    /// Every formula using the name is recalculated with the new definition
+    /// # Usage: define TAX_RATE = B1
+    /// # Usage: define SALES = C2:C480
+    ///
+    /// # Errors
+    /// - `ExpressionError::CouldNotParse` for an invalid name or definition
+    /// - `ExpressionError::CircularDependency` if a formula using the name would refer to
+    ///   itself, the old definition is kept
+    pub fn define_name(&mut self, name: &str, definition: &str) -> Result<(), ExpressionError> {
+        // Names are case-insensitive and kept in upper case like the formulas using them
+        let name = &name.to_ascii_uppercase();
+        if !crate::parser::is_valid_name(name) {
+            return Err(ExpressionError::CouldNotParse(ParseError {
+                offset: 0,
+                token: name.to_string(),
+                reason: format!("invalid name {}", name),
+            }));
+        }
+        let target = crate::parser::parse_name_definition(definition, self)
+            .map_err(ExpressionError::CouldNotParse)?;
+        let old_target = self.names.insert(name.to_string(), target);
+        if let Err(err) = self.rebind_name(name) {
+            match old_target {
+                Some(old_target) => self.names.insert(name.to_string(), old_target),
+                None => self.names.remove(name),
+            };
+            // The old definition was consistent, so restoring it can not fail
+            let _ = self.rebind_name(name);
+            return Err(err);
+        }
+        Ok(())
+    }
+    /// Deletes a name, formulas using it show #NAME? until it is defined again
+    /// Returns false if the name was not defined
+    /// # Usage: delete_name TAX_RATE
+    pub fn delete_name(&mut self, name: &str) -> bool {
+        let name = &name.to_ascii_uppercase();
+        if self.names.remove(name).is_none() {
+            return false;
+        }
+        // Pointing formulas at #NAME? removes edges only, so no cycle can appear
+        let _ = self.rebind_name(name);
+        true
+    }
+    /// Rebuilds every formula using `name` with its current definition
+    fn rebind_name(&mut self, name: &str) -> Result<(), ExpressionError> {
+        let target = self.resolve_name(name);
+        let users = self.name_users.get(name).cloned().unwrap_or_default();
+        for cell in users {
+            let mut function = unsafe { (*self.get_cell_value(cell.row, cell.col)).function.clone() };
+            match &mut function.data {
+                FunctionData::Expression(expr) => expr.rebind_name(name, &target),
+                FunctionData::Arguments(args) => {
+                    for arg in args {
+                        arg.rebind_name(name, &target);
                     }
                 }
-
-                FunctionData::Value(_) => {} // No dependencies to add
+                _ => continue,
             }
+            self.set_cell_function(cell, function)?;
         }
+        Ok(())
     }
 
     /// Sets dirty parent counts for topological sorting
     /// This function is used to mark cells that need to be updated
-    pub fn set_dirty_parents(&mut self, cell: &Cell, stack: &mut Vec<*mut CellData>) {
+    pub fn set_dirty_parents(&mut self, cell: &Cell, stack: &mut Vec<Cell>) {
         unsafe {
             let root_data = self.get_cell_value(cell.row, cell.col);
             let root_ptr = root_data;
 
             (*root_ptr).dirty_parents = 0;
-            stack.push(root_ptr);
-
-            while let Some(current_ptr) = stack.pop() {
-                let current = &*current_ptr;
-                let deps = &current.dependents; // Access the dependents vector
+            stack.push(*cell);
 
-                for &(row, col) in deps.iter() {
+            while let Some(current) = stack.pop() {
+                for (row, col) in self.cell_dependents(&current) {
                     let child_data = self.get_cell_value(row as usize, col as usize);
                     let child_ptr = child_data;
 
                     if (*child_ptr).dirty_parents == 0 {
-                        stack.push(child_ptr);
+                        stack.push(Cell {
+                            row: row as usize,
+                            col: col as usize,
+                        });
                     }
                     (*child_ptr).dirty_parents += 1;
                 }
@@ -369,10 +656,8 @@ impl Backend {
         let mut process_stack = Vec::new();
 
         unsafe {
-            let cell_data = self.get_cell_value(cell.row, cell.col);
-
             // Process the dependents of the initial cell
-            for &(row, col) in (*cell_data).dependents.iter() {
+            for (row, col) in self.cell_dependents(cell) {
                 let child_data = self.get_cell_value(row as usize, col as usize);
                 (*child_data).dirty_parents -= 1;
                 if (*child_data).dirty_parents == 0 {
@@ -383,11 +668,10 @@ impl Backend {
             // Process the stack of dependent cells
             while let Some((row, col)) = process_stack.pop() {
                 let current_data = self.get_cell_value(row, col);
-                let (new_value, error) = self.evaluate_expression(&(*current_data).function);
-                (*current_data).value = new_value;
-                (*current_data).error = error;
+                let value = self.evaluate_expression(&(*current_data).function);
+                self.store_value(Cell { row, col }, value);
 
-                for &(dep_row, dep_col) in (*current_data).dependents.iter() {
+                for (dep_row, dep_col) in self.cell_dependents(&Cell { row, col }) {
                     let dependent_data = self.get_cell_value(dep_row as usize, dep_col as usize);
                     (*dependent_data).dirty_parents -= 1;
                     if (*dependent_data).dirty_parents == 0 {
@@ -398,58 +682,346 @@ impl Backend {
         }
     }
 
-    /// Evaluates a function and returns (value, error)
+    /// Evaluates a function and returns its value, failures are returned as `CellValue::Error`
     /// This function is used to evaluate the result of a formula
     /// It handles different types of functions (binary operations, range functions, etc.)
-    pub fn evaluate_expression(&self, func: &Function) -> (i32, CellError) {
+    pub fn evaluate_expression(&self, func: &Function) -> CellValue {
         match func.data {
-            FunctionData::BinaryOp(bin_op) => match func.type_ {
-                FunctionType::Plus => match self.plus_op(&bin_op) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Minus => match self.minus_op(&bin_op) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Multiply => match self.multiply_op(&bin_op) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Divide => match self.divide_op(&bin_op) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                _ => (0, CellError::DependencyError),
-            },
+            FunctionData::BinaryOp(bin_op) => {
+                let result = match func.type_ {
+                    FunctionType::Plus => self.plus_op(&bin_op).into(),
+                    FunctionType::Minus => self.minus_op(&bin_op).into(),
+                    FunctionType::Multiply => self.multiply_op(&bin_op).into(),
+                    FunctionType::Divide => self.divide_op(&bin_op).into(),
+                    _ => CellValue::Error(CellError::TypeMismatch),
+                };
+                let is_date = |operand: &Operand| match operand.data {
+                    OperandData::Cell(cell) => unsafe {
+                        matches!((*self.get_cell_value(cell.row, cell.col)).value, CellValue::Date(_))
+                    },
+                    OperandData::Value(_) => false,
+                };
+                date_arithmetic(func.type_, is_date(&bin_op.first), is_date(&bin_op.second), result)
+            }
             FunctionData::RangeFunction(range) => match func.type_ {
-                FunctionType::Min => match self.min_function(&range) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Max => match self.max_function(&range) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Avg => match self.avg_function(&range) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Sum => match self.sum_function(&range) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                FunctionType::Stdev => match self.stdev_function(&range) {
-                    Ok(value) => (value, CellError::NoError),
-                    Err(error) => (0, error),
-                },
-                _ => (0, CellError::DependencyError),
+                FunctionType::Min => self.min_function(&range).into(),
+                FunctionType::Max => self.max_function(&range).into(),
+                FunctionType::Avg => self.avg_function(&range).into(),
+                FunctionType::Sum => self.sum_function(&range).into(),
+                FunctionType::Stdev => self.stdev_function(&range).into(),
+                FunctionType::Count => self.count_function(&range).into(),
+                FunctionType::CountA => self.counta_function(&range).into(),
+                FunctionType::CountBlank => self.countblank_function(&range).into(),
+                FunctionType::Median => self.median_function(&range).into(),
+                FunctionType::Mode => self.mode_function(&range).into(),
+                FunctionType::VarP => self.var_p_function(&range).into(),
+                FunctionType::VarS => self.var_s_function(&range).into(),
+                FunctionType::StdevP => self.stdev_p_function(&range).into(),
+                FunctionType::StdevS => self.stdev_s_function(&range).into(),
+                _ => CellValue::Error(CellError::TypeMismatch),
+            },
+            FunctionData::SleepValue(operand) => self.sleep_function(&operand).into(),
+            FunctionData::Value(ref value) => value.clone(),
+            FunctionData::Expression(ref expr) => match self.evaluate_tree(expr) {
+                // A formula reading an empty cell shows 0, like spreadsheets do
+                Ok(CellValue::Empty) => CellValue::Number(0.0),
+                Ok(value) => value,
+                Err(error) => CellValue::Error(error),
+            },
+            FunctionData::Arguments(ref args) => self.aggregate_function(func.type_, args).into(),
+        }
+    }
+    /// Evaluates a node of an expression tree recursively
+    /// Binary operations and function calls are delegated to the same `*_op` and
+    /// `*_function` methods used by flat formulas, so error handling is shared.
+    pub fn evaluate_tree(&self, expr: &Expr) -> Result<CellValue, CellError> {
+        match expr {
+            Expr::Value(value) => Ok(CellValue::Number(*value)),
+            Expr::Text(text) => Ok(CellValue::Text(text.clone())),
+            Expr::Bool(value) => Ok(CellValue::Bool(*value)),
+            Expr::Error(error) => Err(*error),
+            Expr::Cell(cell, _) => self.read_cell(cell.row, cell.col),
+            // Ranges only appear as range function arguments
+            Expr::Range(_) => Err(CellError::TypeMismatch),
+            Expr::Negate(inner) => Ok(CellValue::Number(
+                -self.evaluate_tree(inner)?.as_number()?,
+            )),
+            Expr::BinaryOp(
+                comparison @ (FunctionType::Equal
+                | FunctionType::NotEqual
+                | FunctionType::Less
+                | FunctionType::LessEqual
+                | FunctionType::Greater
+                | FunctionType::GreaterEqual),
+                first,
+                second,
+            ) => {
+                let ordering = compare_values(&self.evaluate_tree(first)?, &self.evaluate_tree(second)?);
+                Ok(CellValue::Bool(match comparison {
+                    FunctionType::Equal => ordering == Ordering::Equal,
+                    FunctionType::NotEqual => ordering != Ordering::Equal,
+                    FunctionType::Less => ordering == Ordering::Less,
+                    FunctionType::LessEqual => ordering != Ordering::Greater,
+                    FunctionType::Greater => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }))
+            }
+            Expr::BinaryOp(FunctionType::Concatenate, first, second) => {
+                let mut text = self.evaluate_tree(first)?.as_text()?;
+                text.push_str(&self.evaluate_tree(second)?.as_text()?);
+                Ok(CellValue::Text(text))
+            }
+            Expr::BinaryOp(FunctionType::Exponent, first, second) => {
+                let base = self.evaluate_tree(first)?.as_number()?;
+                let exponent = self.evaluate_tree(second)?.as_number()?;
+                power(base, exponent).map(CellValue::Number)
+            }
+            Expr::BinaryOp(function_type, first, second) => {
+                let (first, second) = (self.evaluate_tree(first)?, self.evaluate_tree(second)?);
+                let bin_op = BinaryOp {
+                    first: Operand {
+                        type_: OperandType::Number,
+                        data: OperandData::Value(first.as_number()?),
+                        anchor: Anchor::default(),
+                    },
+                    second: Operand {
+                        type_: OperandType::Number,
+                        data: OperandData::Value(second.as_number()?),
+                        anchor: Anchor::default(),
+                    },
+                };
+                let result = self.evaluate_result(&Function::new_binary_op(*function_type, bin_op))?;
+                let is_date = |value: &CellValue| matches!(value, CellValue::Date(_));
+                Ok(date_arithmetic(*function_type, is_date(&first), is_date(&second), result))
+            }
+            Expr::Call(
+                function_type @ (FunctionType::Abs
+                | FunctionType::Mod
+                | FunctionType::Power
+                | FunctionType::Sqrt
+                | FunctionType::Round
+                | FunctionType::Floor
+                | FunctionType::Ceiling
+                | FunctionType::Sign),
+                args,
+            ) => {
+                let mut numbers = Vec::with_capacity(args.len());
+                for arg in args {
+                    numbers.push(self.evaluate_tree(arg)?.as_number()?);
+                }
+                math_function(*function_type, &numbers).map(CellValue::Number)
+            }
+            Expr::Call(FunctionType::Rand, _) => Ok(CellValue::Number(self.next_random())),
+            // A whole number between the bounds, both included
+            Expr::Call(FunctionType::RandBetween, args) => {
+                let low = self.evaluate_tree(&args[0])?.as_number()?.ceil();
+                let high = self.evaluate_tree(&args[1])?.as_number()?.floor();
+                if low > high {
+                    return Err(CellError::Num);
+                }
+                let value = low + (self.next_random() * (high - low + 1.0)).floor();
+                check_overflow(value.min(high)).map(CellValue::Number)
+            }
+            Expr::Call(FunctionType::Concat, args) => {
+                let mut text = String::new();
+                for arg in args {
+                    let Some(range) = arg.as_range() else {
+                        text.push_str(&self.evaluate_tree(arg)?.as_text()?);
+                        continue;
+                    };
+                    for row in range.top_left.row..=range.bottom_right.row {
+                        for col in range.top_left.col..=range.bottom_right.col {
+                            text.push_str(&self.read_cell(row, col)?.as_text()?);
+                        }
+                    }
+                }
+                Ok(CellValue::Text(text))
+            }
+            Expr::Call(
+                function_type @ (FunctionType::Len
+                | FunctionType::Left
+                | FunctionType::Right
+                | FunctionType::Mid
+                | FunctionType::Upper
+                | FunctionType::Lower
+                | FunctionType::Trim
+                | FunctionType::Text
+                | FunctionType::Value),
+                args,
+            ) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate_tree(arg)?);
+                }
+                text_function(*function_type, &values)
+            }
+            Expr::Call(FunctionType::Today, _) => Ok(CellValue::Date(now_serial().floor())),
+            Expr::Call(FunctionType::Now, _) => Ok(CellValue::Date(now_serial())),
+            Expr::Call(
+                function_type @ (FunctionType::Date
+                | FunctionType::Year
+                | FunctionType::Month
+                | FunctionType::Day
+                | FunctionType::DateDif),
+                args,
+            ) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate_tree(arg)?);
+                }
+                date_function(*function_type, &values)
+            }
+            Expr::Call(FunctionType::Sleep, args) => {
+                let value = self.evaluate_tree(&args[0])?.as_number()?;
+                self.sleep_function(&Operand {
+                    type_: OperandType::Number,
+                    data: OperandData::Value(value),
+                    anchor: Anchor::default(),
+                })
+                .map(CellValue::Number)
+            }
+            // Only the branch that is taken is evaluated
+            Expr::Call(FunctionType::If, args) => {
+                if self.evaluate_tree(&args[0])?.as_bool()? {
+                    self.evaluate_tree(&args[1])
+                } else if let Some(otherwise) = args.get(2) {
+                    self.evaluate_tree(otherwise)
+                } else {
+                    Ok(CellValue::Bool(false))
+                }
+            }
+            Expr::Call(FunctionType::And, args) => {
+                let mut result = true;
+                for arg in args {
+                    result &= self.evaluate_tree(arg)?.as_bool()?;
+                }
+                Ok(CellValue::Bool(result))
+            }
+            Expr::Call(FunctionType::Or, args) => {
+                let mut result = false;
+                for arg in args {
+                    result |= self.evaluate_tree(arg)?.as_bool()?;
+                }
+                Ok(CellValue::Bool(result))
+            }
+            Expr::Call(FunctionType::Not, args) => {
+                Ok(CellValue::Bool(!self.evaluate_tree(&args[0])?.as_bool()?))
+            }
+            Expr::Call(FunctionType::IfError, args) => match self.evaluate_tree(&args[0]) {
+                Ok(value) => Ok(value),
+                Err(_) => self.evaluate_tree(&args[1]),
+            },
+            Expr::Name(_, target) => self.evaluate_tree(target),
+            Expr::Call(
+                function_type @ (FunctionType::CountIf | FunctionType::SumIf | FunctionType::AverageIf),
+                args,
+            ) => {
+                let range = self.range_argument(&args[0])?;
+                let criterion = self.evaluate_tree(&args[1])?;
+                let sum_range = match args.get(2) {
+                    Some(arg) => self.range_argument(arg)?,
+                    None => range,
+                };
+                match function_type {
+                    FunctionType::CountIf => self.countif_function(&range, &criterion),
+                    FunctionType::SumIf => self.sumif_function(&range, &criterion, &sum_range),
+                    _ => self.averageif_function(&range, &criterion, &sum_range),
+                }
+                .map(CellValue::Number)
+            }
+            Expr::Call(
+                function_type @ (FunctionType::Percentile
+                | FunctionType::Quartile
+                | FunctionType::Large
+                | FunctionType::Small),
+                args,
+            ) => {
+                let range = self.range_argument(&args[0])?;
+                let k = self.evaluate_tree(&args[1])?.as_number()?;
+                match function_type {
+                    FunctionType::Percentile => self.percentile_function(&range, k),
+                    FunctionType::Quartile => self.quartile_function(&range, k),
+                    FunctionType::Large => self.large_function(&range, k),
+                    _ => self.small_function(&range, k),
+                }
+                .map(CellValue::Number)
+            }
+            Expr::Call(FunctionType::Rank, args) => {
+                let number = self.evaluate_tree(&args[0])?.as_number()?;
+                let range = self.range_argument(&args[1])?;
+                let ascending = match args.get(2) {
+                    Some(order) => self.evaluate_tree(order)?.as_number()? != 0.0,
+                    None => false,
+                };
+                self.rank_function(number, &range, ascending)
+                    .map(CellValue::Number)
+            }
+            Expr::Call(function_type @ (FunctionType::VLookup | FunctionType::HLookup), args) => {
+                let value = self.evaluate_tree(&args[0])?;
+                let table = self.range_argument(&args[1])?;
+                let index = self.evaluate_tree(&args[2])?.as_number()?;
+                let approximate = match args.get(3) {
+                    Some(approximate) => self.evaluate_tree(approximate)?.as_bool()?,
+                    None => true,
+                };
+                if *function_type == FunctionType::VLookup {
+                    self.vlookup_function(&value, &table, index, approximate)
+                } else {
+                    self.hlookup_function(&value, &table, index, approximate)
+                }
+            }
+            Expr::Call(FunctionType::Index, args) => {
+                let range = self.range_argument(&args[0])?;
+                let row = self.evaluate_tree(&args[1])?.as_number()?;
+                let col = match args.get(2) {
+                    Some(col) => Some(self.evaluate_tree(col)?.as_number()?),
+                    None => None,
+                };
+                self.index_function(&range, row, col)
+            }
+            Expr::Call(FunctionType::Match, args) => {
+                let value = self.evaluate_tree(&args[0])?;
+                let range = self.range_argument(&args[1])?;
+                let mode = match args.get(2) {
+                    Some(mode) => self.evaluate_tree(mode)?.as_number()?,
+                    None => 1.0,
+                };
+                self.match_function(&value, &range, mode)
+                    .map(CellValue::Number)
+            }
+            Expr::Call(function_type, args) => match args.as_slice() {
+                [Expr::Range(range)] if function_type.is_aggregate() => {
+                    self.evaluate_result(&Function::new_range_function(*function_type, *range))
+                }
+                _ => self.aggregate_function(*function_type, args).map(CellValue::Number),
             },
-            FunctionData::SleepValue(operand) => match self.sleep_function(&operand) {
-                Ok(value) => (value, CellError::NoError),
-                Err(error) => (0, error),
+        }
+    }
+    /// Returns the value of a cell, an error value is returned as `Err`
+    fn read_cell(&self, row: usize, col: usize) -> Result<CellValue, CellError> {
+        unsafe {
+            match &(*self.get_cell_value(row, col)).value {
+                CellValue::Error(error) => Err(*error),
+                value => Ok(value.clone()),
+            }
+        }
+    }
+    /// Returns the cells of a range argument of a function call
+    /// An undefined name gives #NAME?, a name for a constant #VALUE!
+    fn range_argument(&self, arg: &Expr) -> Result<RangeFunction, CellError> {
+        match arg.as_range() {
+            Some(range) => Ok(range),
+            None => match arg.resolved() {
+                Expr::Error(error) => Err(*error),
+                _ => Err(CellError::TypeMismatch),
             },
-            FunctionData::Value(value) => (value, CellError::NoError),
+        }
+    }
+    /// Evaluates a flat function, turning an error value back into an `Err`
+    fn evaluate_result(&self, func: &Function) -> Result<CellValue, CellError> {
+        match self.evaluate_expression(func) {
+            CellValue::Error(error) => Err(error),
+            value => Ok(value),
         }
     }
     /// Sets a cell's value based on the provided expression
@@ -468,7 +1040,7 @@ impl Backend {
     /// `Result<(), ExpressionError>` indicating success or failure
     ///
     /// # Errors
-    /// - `ExpressionError::CouldNotParse` for invalid expressions
+    /// - `ExpressionError::CouldNotParse` for invalid expressions, with where and why
     /// - `ExpressionError::CircularDependency` for circular references
     ///
     /// # Example
@@ -489,11 +1061,20 @@ impl Backend {
     /// ```
     pub fn set_cell_value(&mut self, cell: Cell, expression: &str) -> Result<(), ExpressionError> {
         // Parse the expression
-        let (new_function, success) = self.parse_expression(expression);
-        if !success {
-            return Err(ExpressionError::CouldNotParse);
-        }
+        let new_function = self
+            .parse_expression(expression)
+            .map_err(ExpressionError::CouldNotParse)?;
 
+        self.set_cell_function(cell, new_function)
+    }
+    /// Sets a cell's formula to an already parsed function
+    /// Updates the dependency graph and the dependents, a formula that would create a
+    /// cycle is rejected and the cell keeps its old formula.
+    pub fn set_cell_function(
+        &mut self,
+        cell: Cell,
+        new_function: Function,
+    ) -> Result<(), ExpressionError> {
         // Get a mutable reference to the target cell
         unsafe {
             let cell_data = self.get_cell_value(cell.row, cell.col);
@@ -501,23 +1082,13 @@ impl Backend {
             let cell_ptr = cell_data;
 
             // Copy old state
-            let old_function = (*cell_ptr).function;
+            let old_function = (*cell_ptr).function.clone();
             //let old_value = (*cell_ptr).value;
 
             // Handle constant function early
             if new_function.type_ == FunctionType::Constant {
-                let (new_value, error) = self.evaluate_expression(&new_function);
-                (*cell_ptr).value = new_value;
-                (*cell_ptr).error = error;
-                (*cell_ptr).function = new_function;
-
-                self.update_graph(&cell, &old_function);
-                self.update_dependents(&cell);
-
-                #[cfg(feature = "gui")]
-                {
-                    self.formula_strings[cell.row][cell.col] = "=".to_owned() + expression;
-                }
+                let value = self.evaluate_expression(&new_function);
+                self.set_cell_literal(cell, value);
                 return Ok(());
             }
 
@@ -531,12 +1102,8 @@ impl Backend {
                     }
                 }
                 FunctionData::RangeFunction(range) => {
-                    for row in range.top_left.row..=range.bottom_right.row {
-                        for col in range.top_left.col..=range.bottom_right.col {
-                            if row == cell.row && col == cell.col {
-                                return Err(ExpressionError::CircularDependency);
-                            }
-                        }
+                    if range.contains(cell) {
+                        return Err(ExpressionError::CircularDependency);
                     }
                 }
                 FunctionData::SleepValue(operand) => {
@@ -545,10 +1112,18 @@ impl Backend {
                     }
                 }
                 FunctionData::Value(_) => {}
+                data @ (FunctionData::Expression(_) | FunctionData::Arguments(_)) => {
+                    let mut cells = Vec::new();
+                    let mut ranges = Vec::new();
+                    data.collect_references(&mut cells, &mut ranges);
+                    if cells.contains(&cell) || ranges.iter().any(|range| range.contains(cell)) {
+                        return Err(ExpressionError::CircularDependency);
+                    }
+                }
             }
 
             // Set new function
-            (*cell_ptr).function = new_function;
+            (*cell_ptr).function = new_function.clone();
 
             // Update graph (remove old edges)
             self.update_graph(&cell, &old_function);
@@ -562,178 +1137,508 @@ impl Backend {
             }
 
             // Evaluate and update value
-            let (new_value, error) = self.evaluate_expression(&new_function);
-            (*cell_ptr).value = if error == CellError::NoError {
-                new_value
-            } else {
-                0
-            };
-            (*cell_ptr).error = error;
+            self.store_value(cell, self.evaluate_expression(&new_function));
 
             // Propagate to dependents
             self.update_dependents(&cell);
         }
-        #[cfg(feature = "gui")]
-        {
-            self.formula_strings[cell.row][cell.col] = expression.to_string();
-        }
 
         Ok(())
     }
+    /// Stores a plain value (number, text, boolean or empty) in a cell without parsing it
+    /// Used for constants, CSV fields that are not formulas and pasted values
+    pub fn set_cell_literal(&mut self, cell: Cell, value: CellValue) {
+        unsafe {
+            let cell_ptr = self.get_cell_value(cell.row, cell.col);
+            let old_function = (*cell_ptr).function.clone();
+            self.store_value(cell, value.clone());
+            (*cell_ptr).function = Function::new_literal(value);
+
+            self.update_graph(&cell, &old_function);
+            self.update_dependents(&cell);
+        }
+    }
+    /// Writes the value of a cell and updates the summary of its column if there is one
+    fn store_value(&self, cell: Cell, value: CellValue) {
+        if let Some(tree) = self.column_summaries.borrow_mut().get_mut(&cell.col) {
+            tree.update(cell, &value);
+        }
+        unsafe {
+            (*self.get_cell_value(cell.row, cell.col)).value = value;
+        }
+    }
+    /// Returns the summary of the values of a range, combined from the summaries of its
+    /// columns, a column is summarised the first time a range reads it
+    fn range_summary(&self, range: &RangeFunction) -> Summary {
+        let mut trees = self.column_summaries.borrow_mut();
+        let mut summary = Summary::default();
+        for col in range.top_left.col..=range.bottom_right.col {
+            let tree = trees.entry(col).or_insert_with(|| {
+                let values = (0..self.workbook_rows()).map(|row| unsafe {
+                    Summary::of_value(Cell { row, col }, &(*self.get_cell_value(row, col)).value)
+                });
+                SummaryTree::new(values.collect())
+            });
+            summary = summary.join(tree.query(range.top_left.row, range.bottom_right.row));
+        }
+        summary
+    }
     /// In Range Functions  usage is CellName= FunctionName(TopLeftCell:BottomRightCell)
     ///Evaluates the minimum of the range
     /// This function calculates the minimum of the values in a given range of cells.
     /// # Usage: A1=MIN(A2:B3)
-    pub fn min_function(&self, range: &RangeFunction) -> Result<i32, CellError> {
-        let mut min_val = i32::MAX;
-        for row in range.top_left.row..=range.bottom_right.row {
-            for col in range.top_left.col..=range.bottom_right.col {
-                unsafe {
-                    let cell_data = self.get_cell_value(row, col);
-
-                    match (*cell_data).error {
-                        CellError::NoError => {
-                            min_val = min(min_val, (*cell_data).value);
-                        }
-                        CellError::DivideByZero => return Err(CellError::DivideByZero),
-                        CellError::DependencyError => return Err(CellError::DependencyError),
-                        CellError::Overflow => return Err(CellError::Overflow),
-                    }
-                }
-            }
-        }
-        Ok(min_val)
+    pub fn min_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        summary_result(FunctionType::Min, &self.range_summary(range).checked()?)
     }
     ///Evaluates the maximum of the range
     /// This function calculates the maximum of the values in a given range of cells.
     /// # Usage: A1=MAX(A2:B3)
-    pub fn max_function(&self, range: &RangeFunction) -> Result<i32, CellError> {
-        let mut max_val = i32::MIN;
-        for row in range.top_left.row..=range.bottom_right.row {
-            for col in range.top_left.col..=range.bottom_right.col {
-                unsafe {
-                    let cell_data = self.get_cell_value(row, col);
-                    match (*cell_data).error {
-                        CellError::NoError => {
-                            max_val = max(max_val, (*cell_data).value);
-                        }
-                        CellError::DivideByZero => return Err(CellError::DivideByZero),
-                        CellError::DependencyError => return Err(CellError::DependencyError),
-                        CellError::Overflow => return Err(CellError::Overflow),
-                    }
-                }
-            }
-        }
-        Ok(max_val)
+    pub fn max_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        summary_result(FunctionType::Max, &self.range_summary(range).checked()?)
     }
     ///Evaluates the average of the range
     /// This function calculates the average of the values in a given range of cells by summing them up and dividing by the count of valid cells.
     /// # Usage: A1=AVG(A2:B3)
-    pub fn avg_function(&self, range: &RangeFunction) -> Result<i32, CellError> {
-        let mut sum = 0;
-        let mut count = 0;
-        for row in range.top_left.row..=range.bottom_right.row {
-            for col in range.top_left.col..=range.bottom_right.col {
-                unsafe {
-                    let cell_data = self.get_cell_value(row, col);
-
-                    match (*cell_data).error {
-                        CellError::NoError => {
-                            sum += (*cell_data).value;
-                            count += 1;
-                        }
-                        CellError::DivideByZero => return Err(CellError::DivideByZero),
-                        CellError::DependencyError => return Err(CellError::DependencyError),
-                        CellError::Overflow => return Err(CellError::Overflow),
-                    }
-                }
-            }
-        }
-        if count == 0 {
-            return Err(CellError::DivideByZero);
-        }
-        Ok(sum / count)
+    pub fn avg_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        summary_result(FunctionType::Avg, &self.range_summary(range).checked()?)
     }
     ///Evaluates the sum of the range
     /// This function calculates the sum of the values in a given range of cells.
     /// # Usage: A1=SUM(A2:B3)
-    pub fn sum_function(&self, range: &RangeFunction) -> Result<i32, CellError> {
-        let mut sum = 0;
-        for row in range.top_left.row..=range.bottom_right.row {
-            for col in range.top_left.col..=range.bottom_right.col {
-                unsafe {
-                    let cell_data = self.get_cell_value(row, col);
-
-                    match (*cell_data).error {
-                        CellError::NoError => {
-                            sum += (*cell_data).value;
-                        }
-                        CellError::DivideByZero => return Err(CellError::DivideByZero),
-                        CellError::DependencyError => return Err(CellError::DependencyError),
-                        CellError::Overflow => return Err(CellError::Overflow),
-                    }
-                }
-            }
-        }
-        Ok(sum)
+    pub fn sum_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        summary_result(FunctionType::Sum, &self.range_summary(range).checked()?)
     }
     ///Evaluates the standard deviation of the range
-    /// This function calculates the standard deviation of the values in a given range of cells.
+    /// This function calculates the standard deviation of the values in a given range of cells,
+    /// taken as the whole population like STDEV.P.
     /// # Usage: A1=STDEV(A2:B3)
-    pub fn stdev_function(&self, range: &RangeFunction) -> Result<i32, CellError> {
-        let mut values = Vec::new();
-        let mut sum = 0;
-        let mut count = 0;
-
-        // First pass: collect values and calculate sum
+    pub fn stdev_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        self.stdev_p_function(range)
+    }
+    ///Evaluates the median of the range, #NUM! if it holds no numbers
+    /// # Usage: A1=MEDIAN(A2:B3)
+    pub fn median_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        self.percentile_function(range, 0.5)
+    }
+    ///Evaluates the most frequent number of the range, the first one in the range on a tie
+    /// #N/A if no number appears twice
+    /// # Usage: A1=MODE(A2:B3)
+    pub fn mode_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        mode(&self.range_numbers(range)?)
+    }
+    ///Evaluates the variance of the range taken as the whole population
+    /// # Usage: A1=VAR.P(A2:B3)
+    pub fn var_p_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        variance(&self.range_numbers(range)?, false)
+    }
+    ///Evaluates the variance of the range taken as a sample, #DIV/0! for fewer than two numbers
+    /// # Usage: A1=VAR.S(A2:B3)
+    pub fn var_s_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        variance(&self.range_numbers(range)?, true)
+    }
+    ///Evaluates the standard deviation of the range taken as the whole population
+    /// # Usage: A1=STDEV.P(A2:B3)
+    pub fn stdev_p_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        Ok(self.var_p_function(range)?.sqrt())
+    }
+    ///Evaluates the standard deviation of the range taken as a sample
+    /// # Usage: A1=STDEV.S(A2:B3)
+    pub fn stdev_s_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        Ok(self.var_s_function(range)?.sqrt())
+    }
+    ///Evaluates the k-th percentile of the range (0 <= k <= 1), interpolating between the
+    /// two closest numbers. #NUM! if k is out of bounds or the range holds no numbers
+    /// # Usage: A1=PERCENTILE(A2:A9, 0.9)
+    pub fn percentile_function(&self, range: &RangeFunction, k: f64) -> Result<f64, CellError> {
+        percentile(&self.sorted_numbers(range)?, k)
+    }
+    ///Evaluates a quartile of the range: 0 is the minimum, 2 the median and 4 the maximum
+    /// # Usage: A1=QUARTILE(A2:A9, 1)
+    pub fn quartile_function(&self, range: &RangeFunction, quart: f64) -> Result<f64, CellError> {
+        let quart = quart.trunc();
+        if !(0.0..=4.0).contains(&quart) {
+            return Err(CellError::Num);
+        }
+        self.percentile_function(range, quart / 4.0)
+    }
+    ///Evaluates the k-th largest number of the range, #NUM! unless 1 <= k <= count
+    /// # Usage: A1=LARGE(A2:A9, 2)
+    pub fn large_function(&self, range: &RangeFunction, k: f64) -> Result<f64, CellError> {
+        let values = self.sorted_numbers(range)?;
+        let index = kth_index(k, values.len())?;
+        Ok(values[values.len() - 1 - index])
+    }
+    ///Evaluates the k-th smallest number of the range, #NUM! unless 1 <= k <= count
+    /// # Usage: A1=SMALL(A2:A9, 2)
+    pub fn small_function(&self, range: &RangeFunction, k: f64) -> Result<f64, CellError> {
+        let values = self.sorted_numbers(range)?;
+        let index = kth_index(k, values.len())?;
+        Ok(values[index])
+    }
+    ///Evaluates the rank of a number among the numbers of the range, 1 for the largest
+    /// (or the smallest when `ascending`). Equal numbers share a rank, #N/A if the number
+    /// is not in the range
+    /// # Usage: A1=RANK(B1, B1:B9) or A1=RANK(B1, B1:B9, 1)
+    pub fn rank_function(
+        &self,
+        number: f64,
+        range: &RangeFunction,
+        ascending: bool,
+    ) -> Result<f64, CellError> {
+        let values = self.range_numbers(range)?;
+        if !values.contains(&number) {
+            return Err(CellError::NotAvailable);
+        }
+        let before = values
+            .iter()
+            .filter(|&&value| if ascending { value < number } else { value > number })
+            .count();
+        Ok((before + 1) as f64)
+    }
+    ///Looks a value up in the first column of the table and returns the cell of the
+    /// `col_index`-th column (from 1) on the row found
+    /// With `approximate` the first column must be sorted ascending and the row of the
+    /// largest value not above `value` is used, otherwise the value must be found as it is.
+    /// #N/A if no row matches, #REF! if the table has no such column
+    /// # Usage: A1=VLOOKUP(B1, D1:F20, 3, FALSE)
+    pub fn vlookup_function(
+        &self,
+        value: &CellValue,
+        table: &RangeFunction,
+        col_index: f64,
+        approximate: bool,
+    ) -> Result<CellValue, CellError> {
+        let offset = table_offset(col_index, table.bottom_right.col - table.top_left.col)?;
+        let keys = (table.top_left.row..=table.bottom_right.row).map(|row| Cell {
+            row,
+            col: table.top_left.col,
+        });
+        let found = self.find_position(value, keys, if approximate { 1 } else { 0 })?;
+        self.read_cell(table.top_left.row + found, table.top_left.col + offset)
+    }
+    ///Looks a value up in the first row of the table and returns the cell of the
+    /// `row_index`-th row (from 1) in the column found, like VLOOKUP turned sideways
+    /// # Usage: A1=HLOOKUP(B1, D1:K3, 2, FALSE)
+    pub fn hlookup_function(
+        &self,
+        value: &CellValue,
+        table: &RangeFunction,
+        row_index: f64,
+        approximate: bool,
+    ) -> Result<CellValue, CellError> {
+        let offset = table_offset(row_index, table.bottom_right.row - table.top_left.row)?;
+        let keys = (table.top_left.col..=table.bottom_right.col).map(|col| Cell {
+            row: table.top_left.row,
+            col,
+        });
+        let found = self.find_position(value, keys, if approximate { 1 } else { 0 })?;
+        self.read_cell(table.top_left.row + offset, table.top_left.col + found)
+    }
+    ///Returns the cell at row `row` and column `col` of the range, counted from 1
+    /// Without `col` a range of a single row is indexed by column.
+    /// #REF! if the position is outside the range
+    /// # Usage: A1=INDEX(B1:D9, 2, 3) or A1=INDEX(B1:B9, 4)
+    pub fn index_function(
+        &self,
+        range: &RangeFunction,
+        row: f64,
+        col: Option<f64>,
+    ) -> Result<CellValue, CellError> {
+        let height = range.bottom_right.row - range.top_left.row;
+        let width = range.bottom_right.col - range.top_left.col;
+        let (row, col) = match col {
+            Some(col) => (row, col),
+            None if height == 0 => (1.0, row),
+            None => (row, 1.0),
+        };
+        let row_offset = table_offset(row, height)?;
+        let col_offset = table_offset(col, width)?;
+        self.read_cell(range.top_left.row + row_offset, range.top_left.col + col_offset)
+    }
+    ///Returns the position (from 1) of a value in a single row or column
+    /// Mode 1 finds the largest value not above `value` in ascending data, mode -1 the
+    /// smallest value not below it in descending data and mode 0 the value itself.
+    /// #N/A if it is not found or the range is not a single row or column
+    /// # Usage: A1=MATCH(B1, D1:D20, 0)
+    pub fn match_function(
+        &self,
+        value: &CellValue,
+        range: &RangeFunction,
+        mode: f64,
+    ) -> Result<f64, CellError> {
+        let cells: Vec<Cell> = if range.top_left.col == range.bottom_right.col {
+            (range.top_left.row..=range.bottom_right.row)
+                .map(|row| Cell { row, col: range.top_left.col })
+                .collect()
+        } else if range.top_left.row == range.bottom_right.row {
+            (range.top_left.col..=range.bottom_right.col)
+                .map(|col| Cell { row: range.top_left.row, col })
+                .collect()
+        } else {
+            return Err(CellError::NotAvailable);
+        };
+        let mode = if mode > 0.0 { 1 } else if mode < 0.0 { -1 } else { 0 };
+        Ok((self.find_position(value, cells.into_iter(), mode)? + 1) as f64)
+    }
+    /// Returns the position of `value` among the values of `cells`, for MATCH modes
+    /// 1 (largest not above, ascending data), 0 (equal) and -1 (smallest not below,
+    /// descending data). Only values of the same kind are compared, text ignoring case.
+    fn find_position(
+        &self,
+        value: &CellValue,
+        cells: impl Iterator<Item = Cell>,
+        mode: i32,
+    ) -> Result<usize, CellError> {
+        let mut found = None;
+        for (position, cell) in cells.enumerate() {
+            let current = unsafe { &(*self.get_cell_value(cell.row, cell.col)).value };
+            if std::mem::discriminant(current) != std::mem::discriminant(value) {
+                continue;
+            }
+            let ordering = current.sort_cmp(value);
+            match mode {
+                0 if ordering == Ordering::Equal => return Ok(position),
+                0 => {}
+                // Sorted data: past the value nothing can match any more
+                1 if ordering == Ordering::Greater => break,
+                -1 if ordering == Ordering::Less => break,
+                _ => found = Some(position),
+            }
+        }
+        found.ok_or(CellError::NotAvailable)
+    }
+    /// Returns the numbers of a range in range order, text, booleans and empty cells are
+    /// skipped and the first error is returned
+    fn range_numbers(&self, range: &RangeFunction) -> Result<Vec<f64>, CellError> {
+        let mut values = Vec::new();
         for row in range.top_left.row..=range.bottom_right.row {
             for col in range.top_left.col..=range.bottom_right.col {
                 unsafe {
-                    let cell_data = self.get_cell_value(row, col);
-
-                    match (*cell_data).error {
-                        CellError::NoError => {
-                            let value = (*cell_data).value;
-                            values.push(value);
-                            sum += value;
-                            count += 1;
-                        }
-                        CellError::DivideByZero => return Err(CellError::DivideByZero),
-                        CellError::DependencyError => return Err(CellError::DependencyError),
-                        CellError::Overflow => return Err(CellError::Overflow),
+                    match &(*self.get_cell_value(row, col)).value {
+                        CellValue::Number(value) | CellValue::Date(value) => values.push(*value),
+                        CellValue::Error(error) => return Err(*error),
+                        _ => {}
                     }
                 }
             }
         }
-
+        Ok(values)
+    }
+    /// Returns the numbers of a range in ascending order
+    fn sorted_numbers(&self, range: &RangeFunction) -> Result<Vec<f64>, CellError> {
+        let mut values = self.range_numbers(range)?;
+        values.sort_by(f64::total_cmp);
+        Ok(values)
+    }
+    ///Evaluates an aggregate over a list of arguments, each a range, a cell or a value
+    /// Ranges and cells skip text, booleans and empty cells like a single range does, values
+    /// given directly are taken as numbers, so SUM(A1, TRUE) adds 1
+    /// # Usage: A1=SUM(A2:A5, C1, 10, D2:D4)
+    pub fn aggregate_function(
+        &self,
+        function_type: FunctionType,
+        args: &[Expr],
+    ) -> Result<f64, CellError> {
+        if function_type == FunctionType::Count {
+            // Ranges are counted from their summaries, values given directly one by one
+            let counts = args.iter().map(|arg| match arg.as_range() {
+                Some(range) => self.range_summary(&range).count,
+                None => {
+                    let value = self.evaluate_tree(arg).unwrap_or_else(CellValue::Error);
+                    usize::from(is_counted(function_type, &value))
+                }
+            });
+            return Ok(counts.sum::<usize>() as f64);
+        }
+        if matches!(function_type, FunctionType::CountA | FunctionType::CountBlank) {
+            let values = self.argument_values(args);
+            let count = values.iter().filter(|value| is_counted(function_type, value));
+            return Ok(count.count() as f64);
+        }
+        if matches!(
+            function_type,
+            FunctionType::Min | FunctionType::Max | FunctionType::Avg | FunctionType::Sum
+        ) {
+            return summary_result(function_type, &self.argument_summary(args)?);
+        }
+        let mut values = self.argument_numbers(args)?;
+        match function_type {
+            FunctionType::Stdev | FunctionType::StdevP => Ok(variance(&values, false)?.sqrt()),
+            FunctionType::StdevS => Ok(variance(&values, true)?.sqrt()),
+            FunctionType::VarP => variance(&values, false),
+            FunctionType::VarS => variance(&values, true),
+            FunctionType::Median => {
+                values.sort_by(f64::total_cmp);
+                percentile(&values, 0.5)
+            }
+            FunctionType::Mode => mode(&values),
+            _ => Err(CellError::TypeMismatch),
+        }
+    }
+    /// Returns the numbers of the arguments of an aggregate in argument order
+    /// The first error of a range or of a value is returned
+    fn argument_numbers(&self, args: &[Expr]) -> Result<Vec<f64>, CellError> {
+        let mut values = Vec::new();
+        for arg in args {
+            match arg.as_range() {
+                Some(range) => values.extend(self.range_numbers(&range)?),
+                None => values.push(self.evaluate_tree(arg)?.as_number()?),
+            }
+        }
+        Ok(values)
+    }
+    /// Returns the summary of the numbers of the arguments of an aggregate, in argument order
+    /// The first error of a range or of a value is returned
+    fn argument_summary(&self, args: &[Expr]) -> Result<Summary, CellError> {
+        let mut summary = Summary::default();
+        for arg in args {
+            let part = match arg.as_range() {
+                Some(range) => self.range_summary(&range).checked()?,
+                None => Summary::of_number(self.evaluate_tree(arg)?.as_number()?),
+            };
+            summary = summary.join(part);
+        }
+        Ok(summary)
+    }
+    /// Returns the values of the arguments of a counting aggregate, every cell of a range
+    /// gives one value and errors are kept as values since they are counted too
+    fn argument_values(&self, args: &[Expr]) -> Vec<CellValue> {
+        let mut values = Vec::new();
+        for arg in args {
+            let Some(range) = arg.as_range() else {
+                values.push(self.evaluate_tree(arg).unwrap_or_else(CellValue::Error));
+                continue;
+            };
+            for row in range.top_left.row..=range.bottom_right.row {
+                for col in range.top_left.col..=range.bottom_right.col {
+                    unsafe {
+                        values.push((*self.get_cell_value(row, col)).value.clone());
+                    }
+                }
+            }
+        }
+        values
+    }
+    ///Evaluates the number of cells of the range holding a number
+    /// # Usage: A1=COUNT(A2:B3)
+    pub fn count_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        summary_result(FunctionType::Count, &self.range_summary(range))
+    }
+    ///Evaluates the number of cells of the range that are not empty
+    /// # Usage: A1=COUNTA(A2:B3)
+    pub fn counta_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        Ok(self.count_cells(range, |value| is_counted(FunctionType::CountA, value)))
+    }
+    ///Evaluates the number of empty cells of the range, cells holding empty text count too
+    /// # Usage: A1=COUNTBLANK(A2:B3)
+    pub fn countblank_function(&self, range: &RangeFunction) -> Result<f64, CellError> {
+        Ok(self.count_cells(range, |value| is_counted(FunctionType::CountBlank, value)))
+    }
+    ///Evaluates the number of cells of the range meeting a criterion
+    /// The criterion is a value, or text with a comparison in front such as ">10" or "<>0"
+    /// # Usage: A1=COUNTIF(A2:A9, ">10")
+    pub fn countif_function(
+        &self,
+        range: &RangeFunction,
+        criterion: &CellValue,
+    ) -> Result<f64, CellError> {
+        let criterion = Criterion::new(criterion);
+        Ok(self.count_cells(range, |value| criterion.matches(value)))
+    }
+    ///Evaluates the sum of the cells of `sum_range` whose cell at the same place in `range`
+    /// meets the criterion, `sum_range` must have the size of `range`
+    /// # Usage: A1=SUMIF(A2:A9, ">10", B2:B9)
+    pub fn sumif_function(
+        &self,
+        range: &RangeFunction,
+        criterion: &CellValue,
+        sum_range: &RangeFunction,
+    ) -> Result<f64, CellError> {
+        let (sum, _) = self.sum_matching(range, criterion, sum_range)?;
+        check_overflow(sum)
+    }
+    ///Evaluates the average of the cells of `sum_range` whose cell at the same place in
+    /// `range` meets the criterion, #DIV/0! if no number does
+    /// # Usage: A1=AVERAGEIF(A2:A9, "<>0", B2:B9)
+    pub fn averageif_function(
+        &self,
+        range: &RangeFunction,
+        criterion: &CellValue,
+        sum_range: &RangeFunction,
+    ) -> Result<f64, CellError> {
+        let (sum, count) = self.sum_matching(range, criterion, sum_range)?;
         if count == 0 {
             return Err(CellError::DivideByZero);
         }
-
-        // Calculate mean
-        let mean = sum / count;
-
-        // Second pass: calculate variance
-        let mut variance_sum: f64 = 0.0;
-        for value in values {
-            variance_sum += ((value - mean) * (value - mean)) as f64;
+        Ok(check_overflow(sum)? / count as f64)
+    }
+    /// Counts the cells of a range whose value satisfies `predicate`
+    fn count_cells(&self, range: &RangeFunction, predicate: impl Fn(&CellValue) -> bool) -> f64 {
+        let mut count = 0;
+        for row in range.top_left.row..=range.bottom_right.row {
+            for col in range.top_left.col..=range.bottom_right.col {
+                unsafe {
+                    if predicate(&(*self.get_cell_value(row, col)).value) {
+                        count += 1;
+                    }
+                }
+            }
         }
-
-        let variance = variance_sum / count as f64;
-        // println!("stdev: {:?}", (variance as f64).sqrt() as i32);
-        // Return standard deviation as integer (floored)
-        Ok(variance.sqrt().round() as i32)
+        count as f64
+    }
+    /// Returns the sum and the count of the numbers in `sum_range` whose cell in `range`
+    /// meets the criterion, errors in the added cells are passed on
+    fn sum_matching(
+        &self,
+        range: &RangeFunction,
+        criterion: &CellValue,
+        sum_range: &RangeFunction,
+    ) -> Result<(f64, usize), CellError> {
+        let rows = range.bottom_right.row - range.top_left.row;
+        let cols = range.bottom_right.col - range.top_left.col;
+        if sum_range.bottom_right.row - sum_range.top_left.row != rows
+            || sum_range.bottom_right.col - sum_range.top_left.col != cols
+        {
+            return Err(CellError::TypeMismatch);
+        }
+        let criterion = Criterion::new(criterion);
+        let mut sum = 0.0;
+        let mut count = 0;
+        for row in 0..=rows {
+            for col in 0..=cols {
+                unsafe {
+                    let tested = self.get_cell_value(range.top_left.row + row, range.top_left.col + col);
+                    if !criterion.matches(&(*tested).value) {
+                        continue;
+                    }
+                    let added = self.get_cell_value(
+                        sum_range.top_left.row + row,
+                        sum_range.top_left.col + col,
+                    );
+                    match &(*added).value {
+                        CellValue::Number(value) | CellValue::Date(value) => {
+                            sum += value;
+                            count += 1;
+                        }
+                        CellValue::Error(error) => return Err(*error),
+                        // Text, booleans and empty cells are skipped
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok((sum, count))
     }
     /// Evaluates the sleep function
     /// This function is used to pause execution for a specified number of seconds
     /// # Usage: A1=SLEEP(4)
     /// or
     /// # Usage: A1=SLEEP(A2)
-    pub fn sleep_function(&self, operand: &Operand) -> Result<i32, CellError> {
+    pub fn sleep_function(&self, operand: &Operand) -> Result<f64, CellError> {
         let value = self.get_operand_value(operand)?;
         // println!("value: {:?}", value);
-        if value > 0 {
-            thread::sleep(Duration::from_secs(value as u64));
+        if value > 0.0 {
+            thread::sleep(Duration::from_secs_f64(value));
         }
         Ok(value)
     }
@@ -741,72 +1646,59 @@ impl Backend {
     /// Evaluates addition operation
     /// This function is used to add two operands together
     /// # Usage: A1=A2+A3
-    pub fn plus_op(&self, bin_op: &BinaryOp) -> Result<i32, CellError> {
+    pub fn plus_op(&self, bin_op: &BinaryOp) -> Result<f64, CellError> {
         let first = self.get_operand_value(&bin_op.first)?;
         let second = self.get_operand_value(&bin_op.second)?;
-        Ok(first + second)
+        check_overflow(first + second)
     }
     /// Evaluates subtraction operation
     /// This function is used to subtract two operands
     /// Usage: A1=A2-A3
-    pub fn minus_op(&self, bin_op: &BinaryOp) -> Result<i32, CellError> {
+    pub fn minus_op(&self, bin_op: &BinaryOp) -> Result<f64, CellError> {
         let first = self.get_operand_value(&bin_op.first)?;
         let second = self.get_operand_value(&bin_op.second)?;
-        Ok(first - second)
+        check_overflow(first - second)
     }
     /// Evaluates multiplication operation
     /// This function is used to multiply two operands
     /// # Usage: A1=A2*A3
-    pub fn multiply_op(&self, bin_op: &BinaryOp) -> Result<i32, CellError> {
+    pub fn multiply_op(&self, bin_op: &BinaryOp) -> Result<f64, CellError> {
         let first = self.get_operand_value(&bin_op.first)?;
         let second = self.get_operand_value(&bin_op.second)?;
-        if first != 0
-            && second != 0
-            && (first.abs() > 2_147_483_647 / second.abs()
-                || second.abs() > 2_147_483_647 / first.abs())
-        {
-            return Err(CellError::Overflow);
-        }
-
-        Ok(first * second)
+        check_overflow(first * second)
     }
     /// Evaluates division operation
     /// This function is used to divide two operands
     /// # Usage: A1=A2/A3
-    /// Division by zero is handled and gives ERR
-    pub fn divide_op(&self, bin_op: &BinaryOp) -> Result<i32, CellError> {
+    /// Division by zero is handled and gives #DIV/0!
+    pub fn divide_op(&self, bin_op: &BinaryOp) -> Result<f64, CellError> {
         let first = self.get_operand_value(&bin_op.first)?;
         let second = self.get_operand_value(&bin_op.second)?;
 
-        if second == 0 {
+        if second == 0.0 {
             return Err(CellError::DivideByZero);
         }
 
-        Ok(first / second)
+        check_overflow(first / second)
     }
 
     /// Gets the value of an operand (either a cell reference or literal value)
-    fn get_operand_value(&self, operand: &Operand) -> Result<i32, CellError> {
+    fn get_operand_value(&self, operand: &Operand) -> Result<f64, CellError> {
         match operand.data {
             OperandData::Cell(cell) => {
                 // Get the cell data
                 unsafe {
                     let cell_data = self.get_cell_value(cell.row, cell.col);
 
-                    // Check for errors in the cell
-                    match (*cell_data).error {
-                        CellError::NoError => Ok((*cell_data).value),
-                        CellError::DivideByZero => Err(CellError::DivideByZero),
-                        CellError::DependencyError => Err(CellError::DependencyError),
-                        CellError::Overflow => Err(CellError::Overflow),
-                    }
+                    // Errors in the cell propagate, text is a type mismatch
+                    (*cell_data).value.as_number()
                 }
             }
             OperandData::Value(value) => Ok(value),
         }
     }
     /// Parses a formula expression and returns the corresponding function
-    pub fn parse_expression(&self, expression: &str) -> (Function, bool) {
+    pub fn parse_expression(&self, expression: &str) -> Result<Function, ParseError> {
         crate::parser::parse_expression(expression, self)
     }
     #[cfg(feature = "gui")]
@@ -815,33 +1707,41 @@ impl Backend {
         crate::parser::parse_load_or_save_cmd(expression)
     }
     #[cfg(feature = "gui")]
-    /// Parses a cut or copy command from a string
+    /// Parses a cut or copy command from a string, the range is on the active sheet
     pub fn parse_cut_or_copy(
         &self,
         expression: &str,
     ) -> Result<(Cell, Cell), Box<dyn std::error::Error>> {
-        crate::parser::parse_cut_or_copy(self, expression)
+        let (tl, br) = crate::parser::parse_cut_or_copy(self, expression)?;
+        Ok((self.on_active_sheet(tl), self.on_active_sheet(br)))
     }
     #[cfg(feature = "gui")]
-    /// Parses a paste command from a string
+    /// Parses a paste command from a string, the cell is on the active sheet
     pub fn parse_paste(&self, expression: &str) -> Result<Cell, Box<dyn std::error::Error>> {
-        crate::parser::parse_paste(self, expression)
+        let cell = crate::parser::parse_paste(self, expression)?;
+        Ok(self.on_active_sheet(cell))
     }
     #[cfg(feature = "gui")]
-    /// Parses an autofill command from a string
+    /// Parses an autofill command from a string, the cells are on the active sheet
     pub fn parse_autofill(
         &self,
         expression: &str,
     ) -> Result<(Cell, Cell, Cell), Box<dyn std::error::Error>> {
-        crate::parser::parse_autofill(self, expression)
+        let (tl, br, dest) = crate::parser::parse_autofill(self, expression)?;
+        Ok((
+            self.on_active_sheet(tl),
+            self.on_active_sheet(br),
+            self.on_active_sheet(dest),
+        ))
     }
-    #[cfg(feature = "gui")]
-    /// Parses a sort command from a string
+    #[cfg(any(feature = "gui", test))]
+    /// Parses a sort command from a string, the range is on the active sheet
     pub fn parse_sort(
         &self,
         expression: &str,
     ) -> Result<(Cell, Cell, bool), Box<dyn std::error::Error>> {
-        crate::parser::parse_sort(self, expression)
+        let (tl, br, a_or_d) = crate::parser::parse_sort(self, expression)?;
+        Ok((self.on_active_sheet(tl), self.on_active_sheet(br), a_or_d))
     }
     /// Returns the number of rows in the spreadsheet
     pub fn get_rows(&self) -> usize {
@@ -852,7 +1752,7 @@ impl Backend {
         self.cols
     }
 
-    #[cfg(feature = "gui")]
+    #[cfg(any(feature = "gui", test))]
     /// Performs a sort operation on a range of cells
     /// Sorts the cells in ascending or descending order based on the specified column
     /// # Usage for sorting in ascending order is: sorta(TopLeftCell:BottomRightCell)
@@ -867,13 +1767,16 @@ impl Backend {
         let br = (br_cell.row, br_cell.col);
         let grid_ref = unsafe { &mut *self.grid.get() };
         grid_ref[tl.0..=br.0].sort_by(|a, b| {
-            let cmp_result = a[tl.1].value.cmp(&b[tl.1].value);
+            let cmp_result = a[tl.1].value.sort_cmp(&b[tl.1].value);
             if a_or_d {
                 cmp_result // Ascending order
             } else {
                 cmp_result.reverse() // Descending order
             }
         });
+        // The sorted rows carry their formulas to new cells, so the edges kept outside
+        // the cells (ranges, names, volatile cells) are rebuilt and every formula rerun
+        self.recalculate_all();
         Ok(())
     }
     #[cfg(feature = "gui")]
@@ -906,38 +1809,38 @@ impl Backend {
 
     #[cfg(feature = "gui")]
     /// Creates a snapshot of the current state for undo/redo
-    pub fn create_snapshot(&self) -> Vec<Vec<(CellData, String)>> {
-        let mut snapshot = Vec::with_capacity(self.rows);
-        for row in 0..self.rows {
+    pub fn create_snapshot(&self) -> Snapshot {
+        let rows = self.workbook_rows();
+        let mut cells = Vec::with_capacity(rows);
+        for row in 0..rows {
             let mut row_data = Vec::with_capacity(self.cols);
             for col in 0..self.cols {
                 unsafe {
                     let cell_data = self.get_cell_value(row, col);
-                    row_data.push(((*cell_data).clone(), self.formula_strings[row][col].clone()));
+                    row_data.push((*cell_data).clone());
                 }
             }
-            snapshot.push(row_data);
+            cells.push(row_data);
+        }
+        Snapshot {
+            cells,
+            sheet_names: self.sheet_names.clone(),
+            active_sheet: self.active_sheet,
+            names: self.names.clone(),
         }
-        snapshot
     }
 
     #[cfg(feature = "gui")]
     /// Applies a snapshot to restore state
-    pub fn apply_snapshot(&mut self, snapshot: Vec<Vec<(CellData, String)>>) {
-        for (row_idx, row) in snapshot.iter().enumerate() {
-            for (col_idx, value) in row.iter().enumerate() {
-                unsafe {
-                    let cell_data = self.get_cell_value(row_idx, col_idx);
-                    let cell_ptr = cell_data;
-                    (*cell_ptr).value = value.0.value;
-                    (*cell_ptr).error = value.0.error;
-                    (*cell_ptr).dependents = value.0.dependents.clone();
-                    (*cell_ptr).function = value.0.function;
-                    (*cell_ptr).dirty_parents = value.0.dirty_parents;
-                    self.formula_strings[row_idx][col_idx] = value.1.clone();
-                }
-            }
-        }
+    pub fn apply_snapshot(&mut self, snapshot: Snapshot) {
+        // The grid is replaced as a whole, a sheet added since is dropped with its rows
+        *self.grid.get_mut() = snapshot.cells;
+        self.sheet_names = snapshot.sheet_names;
+        self.active_sheet = snapshot.active_sheet;
+        self.names = snapshot.names;
+        // Edges kept outside the cells (ranges, names, volatile cells) are rebuilt
+        // from the restored formulas
+        self.rebuild_graph();
     }
 
     #[cfg(feature = "gui")]
@@ -950,9 +1853,11 @@ impl Backend {
     }
     #[cfg(feature = "gui")]
     /// Autofill a range of cells based on a given expression
-    /// Preference order is  - 1. constant, 2. GP, 3. AP
+    /// Preference order is  - 1. formulas, 2. constant, 3. GP, 4. AP
     /// # Usage: autofill(TopLeftCell:BottomRightCell, DestinationCell)
     /// It identifies the type of series (constant, GP, AP) in Range given and fills till the destination cells accordingly
+    /// If the range holds formulas they are repeated downwards, relative references move
+    /// with each row and `$` anchored parts stay fixed
     pub fn autofill(&mut self, expression: &str) -> Result<(), Box<dyn std::error::Error>> {
         println!("autofill: {:?}", expression);
         let tup = self.parse_autofill(expression);
@@ -963,20 +1868,45 @@ impl Backend {
         let tl = (tl_cell.row, tl_cell.col);
         let br = (br_cell.row, br_cell.col);
         let dest = (dest_cell.row, dest_cell.col);
-        let v = unsafe { (*(self.get_cell_value(tl.0, tl.1))).value };
+        let has_formula = (tl.0..=br.0).any(|row| {
+            (tl.1..=br.1).any(|col| unsafe {
+                (*(self.get_cell_value(row, col))).function.type_ != FunctionType::Constant
+            })
+        });
+        if has_formula {
+            let height = br.0 - tl.0 + 1;
+            for row in br.0 + 1..=dest.0 {
+                for col in br.1..=dest.1 {
+                    // The source rows repeat as a pattern
+                    let source = Cell {
+                        row: tl.0 + (row - tl.0) % height,
+                        col,
+                    };
+                    let function = unsafe { (*(self.get_cell_value(source.row, col))).function.clone() };
+                    self.set_relocated_function(source, Cell { row, col }, &function)?;
+                }
+            }
+            return Ok(());
+        }
+        // Series (GP, AP) only exist over numbers, anything else becomes NaN and never matches
+        let number = |value: &CellValue| match value {
+            CellValue::Number(value) | CellValue::Date(value) => *value,
+            _ => f64::NAN,
+        };
+        let v = unsafe { (*(self.get_cell_value(tl.0, tl.1))).value.clone() };
         let d = unsafe {
-            (*(self.get_cell_value(tl.0, tl.1))).value
-                - (*(self.get_cell_value(tl.0 + 1, tl.1))).value
+            number(&(*(self.get_cell_value(tl.0, tl.1))).value)
+                - number(&(*(self.get_cell_value(tl.0 + 1, tl.1))).value)
         };
         let r = unsafe {
-            ((*(self.get_cell_value(tl.0, tl.1))).value as f64)
-                / ((*(self.get_cell_value(tl.0 + 1, tl.1))).value as f64)
+            number(&(*(self.get_cell_value(tl.0, tl.1))).value)
+                / number(&(*(self.get_cell_value(tl.0 + 1, tl.1))).value)
         };
         println!("v: {:?}, d: {:?}, r: {:?}", v, d, r);
         println!(
             "tl_value: {:?}, br_value: {:?}",
-            unsafe { (*(self.get_cell_value(tl.0, tl.1))).value },
-            unsafe { (*(self.get_cell_value(tl.0 + 1, tl.1))).value }
+            unsafe { &(*(self.get_cell_value(tl.0, tl.1))).value },
+            unsafe { &(*(self.get_cell_value(tl.0 + 1, tl.1))).value }
         );
         let mut is_constant = true;
         let mut is_ap = true;
@@ -997,18 +1927,14 @@ impl Backend {
             for row in br.0 + 1..=dest.0 {
                 for col in br.1..=dest.1 {
                     let cell = Cell { row, col };
-                    let res = self.set_cell_value(cell, v.to_string().as_str());
-                    if let Err(err) = res {
-                        println!("Error autofilling value: {:?}", err);
-                    }
+                    self.set_cell_literal(cell, v.clone());
                 }
             }
             Ok(())
         } else {
             for row in tl.0..br.0 {
                 for col in tl.1..=br.1 {
-                    if (grid_ref[row][col].value as f64) / (grid_ref[row + 1][col].value as f64)
-                        != r
+                    if number(&grid_ref[row][col].value) / number(&grid_ref[row + 1][col].value) != r
                     {
                         is_gp = false;
                         break;
@@ -1023,7 +1949,7 @@ impl Backend {
                         let cell = Cell { row, col };
                         let res = self.set_cell_value(
                             cell,
-                            &((grid_ref[row - 1][col].value as f64 / r) as i32).to_string(),
+                            &(number(&grid_ref[row - 1][col].value) / r).to_string(),
                         );
                         if let Err(err) = res {
                             println!("Error autofilling value: {:?}", err);
@@ -1034,7 +1960,9 @@ impl Backend {
             } else {
                 for row in tl.0..br.0 {
                     for col in tl.1..=br.1 {
-                        if grid_ref[row][col].value - grid_ref[row + 1][col].value != d {
+                        if number(&grid_ref[row][col].value) - number(&grid_ref[row + 1][col].value)
+                            != d
+                        {
                             is_ap = false;
                             break;
                         }
@@ -1048,7 +1976,7 @@ impl Backend {
                             let cell = Cell { row, col };
                             let res = self.set_cell_value(
                                 cell,
-                                &(grid_ref[row - 1][col].value - d).to_string(),
+                                &(number(&grid_ref[row - 1][col].value) - d).to_string(),
                             );
                             if let Err(err) = res {
                                 println!("Error autofilling value: {:?}", err);
@@ -1067,7 +1995,7 @@ impl Backend {
     /// Cuts a range of cells and copies their values to the clipboard(copy stack)
     /// # Usage: cut(TopLeftCell:BottomRightCell)
     /// It removes the values from the original cells and stores them in the copy stack
-    /// It also sets the original cells to 0
+    /// It also clears the original cells
     /// Graph gets updated accordingly
     pub fn cut(&mut self, expression: &str) -> Result<(), Box<dyn std::error::Error>> {
         // println!("cut: {:?}", expression);
@@ -1083,22 +2011,15 @@ impl Backend {
             for col in tl.1..=br.1 {
                 // println!("im htregrseznrte");
                 let cell = Cell { row, col };
-                let res = self.set_cell_value(cell, "0");
-                println!("formula_strings: {:?}", self.formula_strings[row][col]);
-                // unsafe {(*self.grid.get().wrapping_add(row).wrapping_add(col)).value = 0;}
-                // unsafe {let cell = self.get_cell_value(row, col);
-                // cell.value = 0;}
-                if let Err(err) = res {
-                    println!("Error cutting value: {:?}", err);
-                }
+                self.set_cell_literal(cell, CellValue::Empty);
             }
         }
         Ok(())
     }
     #[cfg(feature = "gui")]
-    /// Copies a range of cells and stores their values to the clipboard(copy stack)
+    /// Copies a range of cells and stores their formulas to the clipboard(copy stack)
     /// # Usage: copy(TopLeftCell:BottomRightCell)
-    /// It copies the values from the original cells to the copy stack
+    /// It copies the formulas from the original cells to the copy stack
     /// It does not remove the values from the original cells
     pub fn copy(&mut self, expression: &str) -> Result<(), Box<dyn std::error::Error>> {
         let tup = self.parse_cut_or_copy(expression);
@@ -1112,17 +2033,19 @@ impl Backend {
         for row in tl.0..=br.0 {
             let mut row_data = Vec::new();
             for col in tl.1..=br.1 {
-                row_data.push(unsafe { (*(self.get_cell_value(row, col))).value });
+                row_data.push(unsafe { (*(self.get_cell_value(row, col))).function.clone() });
             }
             copied_data.push(row_data);
         }
         self.copy_stack = copied_data;
+        self.copy_origin = tl_cell;
         Ok(())
     }
     #[cfg(feature = "gui")]
     /// Pastes the selected cells from the clipboard(copy stack) to a specified location
     /// # Usage: paste(TopLeftCell)
-    /// It pastes the values from the copy stack to the specified location
+    /// It pastes the formulas from the copy stack to the specified location
+    /// Relative references move with the pasted cells, `$` anchored parts stay fixed
     /// If enough space is not available, it does not paste
     /// It also updates the graph accordingly
     pub fn paste(&mut self, expression: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -1136,50 +2059,71 @@ impl Backend {
         );
         // println!("br: {:?}", br);
 
-        if br.0 >= self.rows || br.1 >= self.cols {
+        // The pasted cells must stay on the sheet of `tl`
+        let sheets = self.sheet_rows();
+        let sheet_end = sheets.span(sheets.sheet(tl.0)).end;
+        if br.0 >= sheet_end || br.1 >= self.cols {
             return Err("Paste area exceeds grid size".to_string().into());
         }
         for row in tl.0..=br.0 {
             for col in tl.1..=br.1 {
-                if row < self.rows && col < self.cols {
+                if row < sheet_end && col < self.cols {
                     let cell = Cell { row, col };
                     // println!("row: {:?}, col: {:?}", row, col);
-                    let _res = self
-                        .set_cell_value(cell, &self.copy_stack[row - tl.0][col - tl.1].to_string());
-                    //let _col_header =
-                    self.formula_strings[row][col] =
-                        self.copy_stack[row - tl.0][col - tl.1].to_string();
+                    let source = Cell {
+                        row: self.copy_origin.row + row - tl.0,
+                        col: self.copy_origin.col + col - tl.1,
+                    };
+                    let function = self.copy_stack[row - tl.0][col - tl.1].clone();
+                    self.set_relocated_function(source, cell, &function)?;
                 }
             }
         }
         Ok(())
     }
+    #[cfg(any(feature = "gui", test))]
+    /// Stores in cell `to` a copy of `function`, the formula of cell `from`
+    /// Relative references are shifted by the distance between the two cells, anchored
+    /// parts stay and references that leave the grid become #REF!
+    fn set_relocated_function(
+        &mut self,
+        from: Cell,
+        to: Cell,
+        function: &Function,
+    ) -> Result<(), ExpressionError> {
+        let Some(expr) = function.to_expr() else {
+            self.set_cell_literal(to, CellValue::Empty);
+            return Ok(());
+        };
+        let relocated = expr.relocate(from, to, self.sheet_rows(), self.cols);
+        self.set_cell_function(to, crate::parser::lower_expression(relocated))
+    }
     #[cfg(feature = "gui")]
-    /// Saves the current state of the spreadsheet to a CSV file
+    /// Saves the active sheet of the spreadsheet to a CSV file
     /// # Usage: click on save button
     pub fn save_to_csv(&self, save_cmd: &str) -> Result<(), Box<dyn std::error::Error>> {
         let filename = match crate::backend::Backend::parse_load_or_save_cmd(save_cmd) {
             Some(path) => path,
             None => return Err("Invalid load command".to_string().into()),
         };
-        let file = File::create(filename)?;
-        let mut wtr = WriterBuilder::new().from_writer(BufWriter::new(file));
-        //let grid_ref = self.formula_strings.clone();
+        let mut file = BufWriter::new(File::create(filename)?);
+        file.write_all(self.active_sheet_csv()?.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+    #[cfg(feature = "gui")]
+    /// Returns the active sheet as CSV text, one field per cell
+    /// Formulas are written as `=` and their canonical text, constants as they are typed,
+    /// so loading the file gives back the same sheet.
+    pub fn active_sheet_csv(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut wtr = WriterBuilder::new().from_writer(Vec::new());
         for row in 0..self.rows {
-            let mut record = Vec::new();
-            for col in 0..self.cols {
-                unsafe { record.push((*(self.get_cell_value(row, col))).value.to_string()) };
-                //FIX KARNA HAI ISKO
-                // record.push(grid_ref[row][col].clone());
-                // unsafe {
-                //     record.push((*self.grid.get().wrapping_add(row).wrapping_add(col))[row][col].value.to_string());
-                // }
-                // println!("row: {}, col: {}, value: {}", row, col, grid_ref[row][col]);
-            }
+            let record: Vec<String> = (0..self.cols)
+                .map(|col| self.formula_string(self.on_active_sheet(Cell { row, col })))
+                .collect();
             wtr.write_record(&record)?;
         }
-        wtr.flush()?;
-        Ok(())
+        Ok(String::from_utf8(wtr.into_inner()?)?)
     }
     #[cfg(feature = "gui")]
     /// Loads a CSV file and populates the spreadsheet with its data
@@ -1233,7 +2177,7 @@ impl Backend {
                         row: row_idx,
                         col: col_idx,
                     };
-                    let res = self.set_cell_value(cell, field);
+                    let res = self.load_field(cell, field);
                     if let Err(_err) = res {
                         return Err("Invalid cell value".to_string().into());
                     }
@@ -1244,6 +2188,42 @@ impl Backend {
         Ok(())
     }
 
+    #[cfg(any(feature = "gui", test))]
+    /// Stores one CSV field in a cell
+    /// Fields starting with `=` are formulas, numbers, booleans, dates, quoted text and error
+    /// codes are read as values, empty fields stay empty and anything else (headers, names,
+    /// text shaped like a reference such as Q1) is kept as text.
+    fn load_field(&mut self, cell: Cell, field: &str) -> Result<(), ExpressionError> {
+        if field.is_empty() {
+            self.set_cell_literal(cell, CellValue::Empty);
+            return Ok(());
+        }
+        let result = match field.strip_prefix('=') {
+            Some(formula) => self.set_cell_value(cell, formula),
+            None => match self.parse_expression(field) {
+                Ok(function) if function.type_ == FunctionType::Constant => {
+                    self.set_cell_function(cell, function)
+                }
+                _ => {
+                    self.set_cell_literal(cell, CellValue::Text(field.to_string()));
+                    Ok(())
+                }
+            },
+        };
+        match result {
+            Err(ExpressionError::CouldNotParse(_)) => {
+                self.set_cell_literal(cell, CellValue::Text(field.to_string()));
+                Ok(())
+            }
+            // A file can not be rejected like typed input, the cell shows #CIRC! instead
+            Err(ExpressionError::CircularDependency) => {
+                self.set_cell_literal(cell, CellValue::Error(CellError::CircularReference));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
     #[cfg(feature = "gui")]
     /// Loads a CSV string and populates the spreadsheet with its data
     pub fn load_csv_from_str(&mut self, data: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -1276,7 +2256,7 @@ impl Backend {
                         row: row_idx,
                         col: col_idx,
                     };
-                    let _ = self.set_cell_value(cell, field);
+                    let _ = self.load_field(cell, field);
                 }
             }
         }
@@ -1284,119 +2264,836 @@ impl Backend {
         Ok(())
     }
 }
-#[cfg(feature = "cli")]
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::Instant;
-    //use crate::structs::*;
-    //use crate::structs::FunctionType::Sleep;
-
-    #[test]
-    fn test_sleep_function_positive_value() {
-        let backend = Backend::new(3, 3);
+/// Range edges of the dependency graph: every range read by a formula, kept once together
+/// with the cell whose formula reads it.
+///
+/// An interval tree over the rows of the ranges, a treap ordered by top row where every
+/// node knows the lowest bottom row of its subtree. Adding or removing a range takes
+/// O(log n) and finding the ranges holding a cell O(log n + k), for k ranges crossing its
+/// row.
+#[derive(Debug, Default)]
+struct RangeIndex {
+    root: Option<Box<RangeNode>>,
+    /// Number of ranges added so far, drives the priorities
+    added: u64,
+}
 
-        // Set up an operand with a positive value
-        let operand = Operand {
-            type_: OperandType::Int,
-            data: OperandData::Value(2), // Sleep for 2 seconds
-        };
+/// Node of the range index, a range with the cell reading it
+#[derive(Debug)]
+struct RangeNode {
+    range: RangeFunction,
+    user: Cell,
+    /// Heap order of the treap, random so the tree stays balanced on average
+    priority: u64,
+    /// Largest bottom row of the ranges in this subtree
+    max_bottom: usize,
+    left: Option<Box<RangeNode>>,
+    right: Option<Box<RangeNode>>,
+}
 
-        let start_time = Instant::now();
-        let result = backend.sleep_function(&operand);
-        let elapsed_time = start_time.elapsed();
+/// Sort key of a range edge: the range by top row, then the cell reading it
+type RangeKey = (usize, usize, usize, usize, usize, usize);
+
+/// Returns the sort key of the range `range` read by `user`
+fn range_key(range: &RangeFunction, user: Cell) -> RangeKey {
+    (
+        range.top_left.row,
+        range.bottom_right.row,
+        range.top_left.col,
+        range.bottom_right.col,
+        user.row,
+        user.col,
+    )
+}
 
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 2);
-        assert!(elapsed_time.as_secs() >= 2); // Ensure at least 2 seconds have passed
+impl RangeNode {
+    /// Recomputes `max_bottom` from the node and its children
+    fn update(&mut self) {
+        self.max_bottom = self.range.bottom_right.row;
+        for child in [&self.left, &self.right].into_iter().flatten() {
+            self.max_bottom = self.max_bottom.max(child.max_bottom);
+        }
     }
+}
 
-    #[test]
-    fn test_sleep_function_zero_value() {
-        let backend = Backend::new(3, 3);
-
-        // Set up an operand with a value of 0
-        let operand = Operand {
-            type_: OperandType::Int,
-            data: OperandData::Value(0), // No sleep
-        };
-
-        let start_time = Instant::now();
-        let result = backend.sleep_function(&operand);
-        let elapsed_time = start_time.elapsed();
-
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
-        assert!(elapsed_time.as_secs() < 1); // Ensure no significant delay
+impl RangeIndex {
+    /// Adds a range read by the formula of `user`, a range already read by it is kept once
+    fn insert(&mut self, range: &RangeFunction, user: Cell) {
+        let key = range_key(range, user);
+        let (less, rest) = Self::split(self.root.take(), &|node_key| node_key < key);
+        let (equal, greater) = Self::split(rest, &|node_key| node_key <= key);
+        let node = equal.or_else(|| {
+            self.added += 1;
+            Some(Box::new(RangeNode {
+                range: *range,
+                user,
+                priority: mix_bits(self.added),
+                max_bottom: range.bottom_right.row,
+                left: None,
+                right: None,
+            }))
+        });
+        self.root = Self::merge(Self::merge(less, node), greater);
     }
-
-    #[test]
-    fn test_sleep_function_negative_value() {
-        let backend = Backend::new(3, 3);
-
-        // Set up an operand with a negative value
-        let operand = Operand {
-            type_: OperandType::Int,
-            data: OperandData::Value(-5), // Negative value
+    /// Removes a range read by the formula of `user`
+    fn remove(&mut self, range: &RangeFunction, user: Cell) {
+        let key = range_key(range, user);
+        let (less, rest) = Self::split(self.root.take(), &|node_key| node_key < key);
+        let (_equal, greater) = Self::split(rest, &|node_key| node_key <= key);
+        self.root = Self::merge(less, greater);
+    }
+    /// Adds to `users` the cells reading a range that holds `cell`
+    fn collect_users(&self, cell: Cell, users: &mut Vec<(i32, i32)>) {
+        let mut stack: Vec<&RangeNode> = self.root.iter().map(|node| &**node).collect();
+        while let Some(node) = stack.pop() {
+            // No range below this node reaches down to the row
+            if node.max_bottom < cell.row {
+                continue;
+            }
+            stack.extend(node.left.as_deref());
+            // Ranges right of the node start at or below its top row
+            if node.range.top_left.row <= cell.row {
+                if node.range.contains(cell) {
+                    users.push((node.user.row as i32, node.user.col as i32));
+                }
+                stack.extend(node.right.as_deref());
+            }
+        }
+    }
+    /// Splits a subtree into the nodes whose key is `before` the split point and the rest
+    fn split(
+        node: Option<Box<RangeNode>>,
+        before: &dyn Fn(RangeKey) -> bool,
+    ) -> (Option<Box<RangeNode>>, Option<Box<RangeNode>>) {
+        let Some(mut node) = node else {
+            return (None, None);
         };
-
-        let result = backend.sleep_function(&operand);
-
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), -5); // Negative values should not cause sleep
+        if before(range_key(&node.range, node.user)) {
+            let (less, rest) = Self::split(node.right.take(), before);
+            node.right = less;
+            node.update();
+            (Some(node), rest)
+        } else {
+            let (less, rest) = Self::split(node.left.take(), before);
+            node.left = rest;
+            node.update();
+            (less, Some(node))
+        }
     }
-
-    #[test]
-    fn test_new_backend() {
-        let backend = Backend::new(5, 5);
-        assert_eq!(backend.get_rows_col(), (5, 5));
-
-        let backend = Backend::new(0, 0);
-        assert_eq!(backend.get_rows_col(), (0, 0));
-
-        let backend = Backend::new(100, 100);
-        assert_eq!(backend.get_rows_col(), (100, 100));
+    /// Joins two subtrees, every key of `left` sorts before every key of `right`
+    fn merge(
+        left: Option<Box<RangeNode>>,
+        right: Option<Box<RangeNode>>,
+    ) -> Option<Box<RangeNode>> {
+        match (left, right) {
+            (None, right) => right,
+            (left, None) => left,
+            (Some(mut left), Some(mut right)) => {
+                if left.priority > right.priority {
+                    left.right = Self::merge(left.right.take(), Some(right));
+                    left.update();
+                    Some(left)
+                } else {
+                    right.left = Self::merge(Some(left), right.left.take());
+                    right.update();
+                    Some(right)
+                }
+            }
+        }
     }
-
-    #[test]
-    fn test_get_rows_col() {
-        let backend = Backend::new(3, 4);
-        assert_eq!(backend.get_rows_col(), (3, 4));
-
-        let backend = Backend::new(1, 1);
-        assert_eq!(backend.get_rows_col(), (1, 1));
+}
+/// Scrambles the bits of a counter (the SplitMix64 finalizer), consecutive inputs give
+/// unrelated outputs
+fn mix_bits(mut bits: u64) -> u64 {
+    bits = (bits ^ (bits >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    bits = (bits ^ (bits >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    bits ^ (bits >> 31)
+}
+/// Sum, count, smallest and largest of the numbers of a group of cells, with the first error
+/// among them. Text, booleans and empty cells are skipped like the range functions do.
+#[derive(Debug, Clone, Copy)]
+struct Summary {
+    sum: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+    /// The error of the first cell in row order holding one, with that cell
+    error: Option<(Cell, CellError)>,
+}
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            sum: 0.0,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            error: None,
+        }
     }
-
-    #[test]
-    fn test_set_and_get_cell_value() {
-        let mut backend = Backend::new(3, 3);
-        let cell = Cell { row: 1, col: 1 };
-        let expression = "42";
-        backend.set_cell_value(cell, expression).unwrap();
-
-        unsafe {
-            let cell_data = backend.get_cell_value(1, 1);
-            assert_eq!((*cell_data).value, 42);
+}
+impl Summary {
+    /// Summary of a single number
+    fn of_number(value: f64) -> Self {
+        Summary {
+            sum: value,
+            count: 1,
+            min: value,
+            max: value,
+            error: None,
         }
     }
-
-    #[test]
-    fn test_set_cell_value_constant() {
-        // Lines 148, 150-152, 154-157
-        let mut backend = Backend::new(3, 3);
-        let cell = Cell { row: 1, col: 1 };
-        backend.set_cell_value(cell, "42").unwrap();
-
-        unsafe {
-            let cell_data = backend.get_cell_value(1, 1);
-            assert_eq!((*cell_data).value, 42);
-            assert_eq!((*cell_data).error, CellError::NoError);
+    /// Summary of the value of a cell
+    fn of_value(cell: Cell, value: &CellValue) -> Self {
+        match value {
+            CellValue::Number(value) | CellValue::Date(value) => Summary::of_number(*value),
+            CellValue::Error(error) => Summary {
+                error: Some((cell, *error)),
+                ..Summary::default()
+            },
+            _ => Summary::default(),
         }
     }
-
-    #[test]
-    fn test_set_cell_value_circular_dependency() {
-        // Lines 159-161, 167-168
+    /// Combines two summaries, the order does not matter since the first error is found by
+    /// its position
+    fn join(self, other: Summary) -> Summary {
+        let error = match (self.error, other.error) {
+            (Some(first), Some(second)) => {
+                Some(std::cmp::min_by_key(first, second, |(cell, _)| {
+                    (cell.row, cell.col)
+                }))
+            }
+            (first, second) => first.or(second),
+        };
+        Summary {
+            sum: self.sum + other.sum,
+            count: self.count + other.count,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            error,
+        }
+    }
+    /// Returns the summary, or its first error
+    fn checked(self) -> Result<Summary, CellError> {
+        match self.error {
+            Some((_, error)) => Err(error),
+            None => Ok(self),
+        }
+    }
+}
+/// Segment tree over the cells of a column, every node summarises the cells below it, so
+/// a change to one cell and the summary of a run of rows both take O(log rows)
+#[derive(Debug)]
+struct SummaryTree {
+    /// Node `i` joins nodes `2 * i` and `2 * i + 1`, the cells are the second half
+    nodes: Vec<Summary>,
+}
+impl SummaryTree {
+    /// Builds the tree over the summaries of the cells of a column, from the top row down
+    fn new(cells: Vec<Summary>) -> Self {
+        let leaves = cells.len();
+        let mut nodes = vec![Summary::default(); leaves];
+        nodes.extend(cells);
+        for node in (1..leaves).rev() {
+            nodes[node] = nodes[2 * node].join(nodes[2 * node + 1]);
+        }
+        SummaryTree { nodes }
+    }
+    /// Replaces the value of a cell and updates the nodes above it
+    fn update(&mut self, cell: Cell, value: &CellValue) {
+        let mut node = self.nodes.len() / 2 + cell.row;
+        self.nodes[node] = Summary::of_value(cell, value);
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node].join(self.nodes[2 * node + 1]);
+        }
+    }
+    /// Returns the summary of the rows `top..=bottom`
+    fn query(&self, top: usize, bottom: usize) -> Summary {
+        let leaves = self.nodes.len() / 2;
+        let (mut low, mut high) = (top + leaves, bottom + leaves + 1);
+        let mut summary = Summary::default();
+        while low < high {
+            if low % 2 == 1 {
+                summary = summary.join(self.nodes[low]);
+                low += 1;
+            }
+            if high % 2 == 1 {
+                high -= 1;
+                summary = summary.join(self.nodes[high]);
+            }
+            low /= 2;
+            high /= 2;
+        }
+        summary
+    }
+}
+/// Returns the SUM, MIN, MAX, AVG or COUNT of the numbers a summary was taken over
+fn summary_result(function_type: FunctionType, summary: &Summary) -> Result<f64, CellError> {
+    match function_type {
+        // No numbers gives 0, like spreadsheets do
+        FunctionType::Min | FunctionType::Max if summary.count == 0 => Ok(0.0),
+        FunctionType::Min => Ok(summary.min),
+        FunctionType::Max => Ok(summary.max),
+        FunctionType::Avg if summary.count == 0 => Err(CellError::DivideByZero),
+        FunctionType::Avg => Ok(check_overflow(summary.sum)? / summary.count as f64),
+        FunctionType::Sum => check_overflow(summary.sum),
+        FunctionType::Count => Ok(summary.count as f64),
+        _ => Err(CellError::TypeMismatch),
+    }
+}
+/// Criterion of COUNTIF, SUMIF and AVERAGEIF: a comparison and the value compared with
+struct Criterion {
+    /// Equal, NotEqual, Less, LessEqual, Greater or GreaterEqual
+    comparison: FunctionType,
+    value: CellValue,
+}
+
+impl Criterion {
+    /// Reads a criterion: text may start with a comparison (">10", "<>0", "=apple"), the
+    /// rest is read as a number or boolean when it is one. Any other value must be equal.
+    fn new(criterion: &CellValue) -> Self {
+        let CellValue::Text(text) = criterion else {
+            return Criterion {
+                comparison: FunctionType::Equal,
+                value: criterion.clone(),
+            };
+        };
+        let (comparison, rest) = [
+            ("<=", FunctionType::LessEqual),
+            (">=", FunctionType::GreaterEqual),
+            ("<>", FunctionType::NotEqual),
+            ("<", FunctionType::Less),
+            (">", FunctionType::Greater),
+            ("=", FunctionType::Equal),
+        ]
+        .into_iter()
+        .find_map(|(prefix, comparison)| Some((comparison, text.strip_prefix(prefix)?)))
+        .unwrap_or((FunctionType::Equal, text));
+        let value = if rest.is_empty() {
+            CellValue::Empty
+        } else if let Ok(number) = rest.trim().parse::<f64>() {
+            CellValue::Number(number)
+        } else if rest.eq_ignore_ascii_case("TRUE") || rest.eq_ignore_ascii_case("FALSE") {
+            CellValue::Bool(rest.eq_ignore_ascii_case("TRUE"))
+        } else {
+            CellValue::Text(rest.to_string())
+        };
+        Criterion { comparison, value }
+    }
+    /// Returns true if a cell value meets the criterion
+    /// Only values of the same kind are compared, so ">10" never counts text and "<>0"
+    /// counts everything but the number 0. Text is compared ignoring case.
+    fn matches(&self, value: &CellValue) -> bool {
+        // Dates are compared as the numbers they are
+        let kind = |value: &CellValue| match value {
+            CellValue::Date(serial) => std::mem::discriminant(&CellValue::Number(*serial)),
+            value => std::mem::discriminant(value),
+        };
+        let ordering = (kind(value) == kind(&self.value)).then(|| value.sort_cmp(&self.value));
+        match self.comparison {
+            FunctionType::Equal => ordering == Some(Ordering::Equal),
+            FunctionType::NotEqual => ordering != Some(Ordering::Equal),
+            FunctionType::Less => ordering == Some(Ordering::Less),
+            FunctionType::LessEqual => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            FunctionType::Greater => ordering == Some(Ordering::Greater),
+            _ => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+/// Evaluates a math function on its numeric arguments, a missing optional second argument
+/// is 0 digits for ROUND and a significance of 1 for FLOOR and CEILING.
+/// Out of domain arguments give #NUM! (SQRT(-1)) or #DIV/0! (MOD(1,0)).
+/// # Usage: A1=ROUND(B1*1.19,2)
+fn math_function(function_type: FunctionType, args: &[f64]) -> Result<f64, CellError> {
+    let value = args[0];
+    match function_type {
+        FunctionType::Abs => Ok(value.abs()),
+        FunctionType::Sign => Ok(if value == 0.0 { 0.0 } else { value.signum() }),
+        FunctionType::Sqrt if value < 0.0 => Err(CellError::Num),
+        FunctionType::Sqrt => Ok(value.sqrt()),
+        FunctionType::Power => power(value, args[1]),
+        FunctionType::Mod if args[1] == 0.0 => Err(CellError::DivideByZero),
+        // The result takes the sign of the divisor, MOD(-1,3) is 2
+        FunctionType::Mod => check_overflow(value - args[1] * (value / args[1]).floor()),
+        FunctionType::Round => {
+            let digits = args.get(1).copied().unwrap_or(0.0).trunc().clamp(-308.0, 308.0);
+            let factor = 10f64.powi(digits as i32);
+            let scaled = value * factor;
+            // A number too large to have that many digits is already rounded
+            if !scaled.is_finite() {
+                return Ok(value);
+            }
+            Ok(scaled.round() / factor)
+        }
+        _ => {
+            let significance = args.get(1).copied().unwrap_or(1.0);
+            if significance == 0.0 {
+                // Like spreadsheets: FLOOR(x,0) has no answer, CEILING(x,0) is 0
+                return match function_type {
+                    FunctionType::Floor => Err(CellError::DivideByZero),
+                    _ => Ok(0.0),
+                };
+            }
+            if value > 0.0 && significance < 0.0 {
+                return Err(CellError::Num);
+            }
+            let multiples = value / significance;
+            let multiples = match function_type {
+                FunctionType::Floor => multiples.floor(),
+                _ => multiples.ceil(),
+            };
+            check_overflow(multiples * significance)
+        }
+    }
+}
+/// Evaluates a text function on its arguments. Lengths and positions count characters,
+/// a negative length or a MID start below 1 is #VALUE!, as is text VALUE can not read.
+/// # Usage: A1=LEFT(B1,3)
+fn text_function(function_type: FunctionType, args: &[CellValue]) -> Result<CellValue, CellError> {
+    match function_type {
+        FunctionType::Value => return text_number(&args[0]).map(CellValue::Number),
+        FunctionType::Text => {
+            let text = format_pattern(args[0].as_number()?, &args[1].as_text()?);
+            return text.map(CellValue::Text).ok_or(CellError::TypeMismatch);
+        }
+        _ => {}
+    }
+    let text = args[0].as_text()?;
+    let length = |arg: Option<&CellValue>| -> Result<usize, CellError> {
+        let length = match arg {
+            Some(arg) => arg.as_number()?.trunc(),
+            None => 1.0,
+        };
+        if length < 0.0 {
+            return Err(CellError::TypeMismatch);
+        }
+        Ok(length as usize)
+    };
+    Ok(CellValue::Text(match function_type {
+        FunctionType::Len => return Ok(CellValue::Number(text.chars().count() as f64)),
+        FunctionType::Left => text.chars().take(length(args.get(1))?).collect(),
+        FunctionType::Right => {
+            let skipped = text.chars().count().saturating_sub(length(args.get(1))?);
+            text.chars().skip(skipped).collect()
+        }
+        FunctionType::Mid => {
+            let start = length(args.get(1))?;
+            if start < 1 {
+                return Err(CellError::TypeMismatch);
+            }
+            text.chars().skip(start - 1).take(length(args.get(2))?).collect()
+        }
+        FunctionType::Upper => text.to_uppercase(),
+        FunctionType::Lower => text.to_lowercase(),
+        _ => text.split_whitespace().collect::<Vec<_>>().join(" "),
+    }))
+}
+/// Reads a number for VALUE: numbers are kept, text may have spaces around it and a
+/// trailing `%`. Booleans and other text are #VALUE!.
+fn text_number(value: &CellValue) -> Result<f64, CellError> {
+    match value {
+        CellValue::Text(text) => {
+            let text = text.trim();
+            let (text, scale) = match text.strip_suffix('%') {
+                Some(text) => (text.trim_end(), 0.01),
+                None => (text, 1.0),
+            };
+            match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(number * scale),
+                _ => Err(CellError::TypeMismatch),
+            }
+        }
+        CellValue::Bool(_) => Err(CellError::TypeMismatch),
+        value => value.as_number(),
+    }
+}
+/// Formats a number with a TEXT pattern such as "0.00", "#,##0" or "0.0%".
+/// `0` is a digit always shown, `#` a digit shown when needed, `,` groups thousands and a
+/// trailing `%` shows the number as a percentage. None for any other pattern.
+fn format_pattern(value: f64, pattern: &str) -> Option<String> {
+    let (pattern, percent) = match pattern.strip_suffix('%') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let (integer, fraction) = pattern.split_once('.').unwrap_or((pattern, ""));
+    if pattern.is_empty()
+        || !integer.chars().all(|c| matches!(c, '0' | '#' | ','))
+        || !fraction.chars().all(|c| matches!(c, '0' | '#'))
+    {
+        return None;
+    }
+    let value = if percent { value * 100.0 } else { value };
+    let rounded = format!("{:.*}", fraction.len(), value.abs());
+    let (digits, decimals) = rounded.split_once('.').unwrap_or((&rounded, ""));
+
+    let mut decimals = decimals.trim_end_matches('0').to_string();
+    while decimals.len() < fraction.chars().take_while(|&c| c == '0').count() {
+        decimals.push('0');
+    }
+    let mut digits = digits.trim_start_matches('0').to_string();
+    while digits.len() < integer.chars().filter(|&c| c == '0').count() {
+        digits.insert(0, '0');
+    }
+    if integer.contains(',') {
+        let mut grouped = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        digits = grouped;
+    }
+
+    let negative = value < 0.0 && format!("{}{}", digits, decimals).contains(|c: char| ('1'..='9').contains(&c));
+    Some(format!(
+        "{}{}{}{}{}",
+        if negative { "-" } else { "" },
+        digits,
+        if decimals.is_empty() { "" } else { "." },
+        decimals,
+        if percent { "%" } else { "" }
+    ))
+}
+/// Keeps dates through date arithmetic: a date plus or minus a number of days is a date,
+/// the difference of two dates is a number of days.
+fn date_arithmetic(
+    function_type: FunctionType,
+    first_is_date: bool,
+    second_is_date: bool,
+    result: CellValue,
+) -> CellValue {
+    let is_date = match function_type {
+        FunctionType::Plus => first_is_date != second_is_date,
+        FunctionType::Minus => first_is_date && !second_is_date,
+        _ => false,
+    };
+    match result {
+        CellValue::Number(serial) if is_date => CellValue::Date(serial),
+        result => result,
+    }
+}
+/// Evaluates a date function on its arguments. Dates before 1899-12-30 and years past
+/// 9999 are #NUM!, as are a DATEDIF end before its start and an unknown DATEDIF unit.
+/// # Usage: A1=DATEDIF(B1,TODAY(),"M")
+fn date_function(function_type: FunctionType, args: &[CellValue]) -> Result<CellValue, CellError> {
+    let day_of = |value: &CellValue| -> Result<i64, CellError> {
+        let serial = value.as_number()?.floor();
+        if !(0.0..=date_serial(9999, 12, 31) as f64).contains(&serial) {
+            return Err(CellError::Num);
+        }
+        Ok(serial as i64)
+    };
+    match function_type {
+        FunctionType::Date => {
+            let mut year = args[0].as_number()?.trunc();
+            // Like spreadsheets, DATE(26,1,1) is in 1926
+            if (0.0..1900.0).contains(&year) {
+                year += 1900.0;
+            }
+            // Months and days past their end carry over, DATE(2026,13,1) is 2027-01-01
+            let months = year * 12.0 + args[1].as_number()?.trunc() - 1.0;
+            if !(0.0..=120_000.0).contains(&months) {
+                return Err(CellError::Num);
+            }
+            let first = date_serial((months / 12.0).floor() as i64, (months % 12.0) as u32 + 1, 1);
+            let serial = first as f64 + args[2].as_number()?.trunc() - 1.0;
+            Ok(CellValue::Date(day_of(&CellValue::Number(serial))? as f64))
+        }
+        FunctionType::Year => Ok(CellValue::Number(serial_date(day_of(&args[0])?).0 as f64)),
+        FunctionType::Month => Ok(CellValue::Number(serial_date(day_of(&args[0])?).1 as f64)),
+        FunctionType::Day => Ok(CellValue::Number(serial_date(day_of(&args[0])?).2 as f64)),
+        _ => {
+            let (start, end) = (day_of(&args[0])?, day_of(&args[1])?);
+            if start > end {
+                return Err(CellError::Num);
+            }
+            let (start_year, start_month, start_day) = serial_date(start);
+            let (end_year, end_month, end_day) = serial_date(end);
+            // Whole months, the last one only counts once its day is reached
+            let months = (end_year - start_year) * 12 + end_month as i64 - start_month as i64
+                - i64::from(end_day < start_day);
+            let difference = match args[2].as_text()?.to_uppercase().as_str() {
+                "D" => end - start,
+                "M" => months,
+                "Y" => months / 12,
+                "YM" => months % 12,
+                _ => return Err(CellError::Num),
+            };
+            Ok(CellValue::Number(difference as f64))
+        }
+    }
+}
+/// Returns the current date and time as a serial, in UTC on the command line and in local
+/// time in the browser.
+fn now_serial() -> f64 {
+    #[cfg(feature = "gui")]
+    let milliseconds = {
+        let now = js_sys::Date::new_0();
+        now.get_time() - now.get_timezone_offset() * 60_000.0
+    };
+    #[cfg(not(feature = "gui"))]
+    let milliseconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64() * 1000.0);
+    // 1970-01-01 is serial 25569
+    milliseconds / 86_400_000.0 + 25569.0
+}
+/// Raises `base` to `exponent` for `^` and POWER.
+/// #DIV/0! for 0 to a negative power, #NUM! for a negative base with a fractional exponent
+/// or a result too large.
+fn power(base: f64, exponent: f64) -> Result<f64, CellError> {
+    if base == 0.0 && exponent < 0.0 {
+        return Err(CellError::DivideByZero);
+    }
+    let result = base.powf(exponent);
+    if result.is_nan() {
+        return Err(CellError::Num);
+    }
+    check_overflow(result)
+}
+/// Returns the k-th percentile (0 <= k <= 1) of ascending `values`, interpolating between
+/// the two closest numbers. #NUM! if k is out of bounds or there are no values.
+fn percentile(values: &[f64], k: f64) -> Result<f64, CellError> {
+    if values.is_empty() || !(0.0..=1.0).contains(&k) {
+        return Err(CellError::Num);
+    }
+    let position = k * (values.len() - 1) as f64;
+    let below = position.floor() as usize;
+    let fraction = position - below as f64;
+    match values.get(below + 1) {
+        Some(above) => Ok(values[below] + fraction * (above - values[below])),
+        None => Ok(values[below]),
+    }
+}
+/// Returns the most frequent of `values`, the first one on a tie.
+/// #N/A if no value appears twice.
+fn mode(values: &[f64]) -> Result<f64, CellError> {
+    // Adding 0.0 turns -0 into 0 so both are counted together
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for value in values {
+        *counts.entry((value + 0.0).to_bits()).or_default() += 1;
+    }
+    let mut mode = None;
+    let mut mode_count = 1;
+    for &value in values {
+        let count = counts[&(value + 0.0).to_bits()];
+        if count > mode_count {
+            mode = Some(value);
+            mode_count = count;
+        }
+    }
+    mode.ok_or(CellError::NotAvailable)
+}
+/// Returns whether a value is counted by COUNT (numbers), COUNTA (anything but empty
+/// cells) or COUNTBLANK (empty cells and empty text).
+fn is_counted(function_type: FunctionType, value: &CellValue) -> bool {
+    match function_type {
+        FunctionType::Count => matches!(value, CellValue::Number(_) | CellValue::Date(_)),
+        FunctionType::CountA => *value != CellValue::Empty,
+        _ => matches!(value, CellValue::Empty) || *value == CellValue::Text(String::new()),
+    }
+}
+/// Returns the variance of `values`, of a sample (divided by n - 1) or of the whole
+/// population (divided by n). #DIV/0! if there are too few values.
+fn variance(values: &[f64], sample: bool) -> Result<f64, CellError> {
+    let divisor = if sample { values.len().saturating_sub(1) } else { values.len() };
+    if divisor == 0 {
+        return Err(CellError::DivideByZero);
+    }
+    let mean = check_overflow(values.iter().sum::<f64>())? / values.len() as f64;
+    let squares: f64 = values.iter().map(|value| (value - mean) * (value - mean)).sum();
+    Ok(check_overflow(squares)? / divisor as f64)
+}
+/// Returns the zero based offset of the `index`-th (from 1) row or column of a table whose
+/// last offset is `last`. #VALUE! below 1 and #REF! past the table.
+fn table_offset(index: f64, last: usize) -> Result<usize, CellError> {
+    let index = index.trunc();
+    if index.is_nan() || index < 1.0 {
+        return Err(CellError::TypeMismatch);
+    }
+    if index > (last + 1) as f64 {
+        return Err(CellError::InvalidReference);
+    }
+    Ok(index as usize - 1)
+}
+/// Returns the zero based index of the k-th of `count` sorted values, a fractional k is
+/// rounded up. #NUM! unless 1 <= k <= count.
+fn kth_index(k: f64, count: usize) -> Result<usize, CellError> {
+    let k = k.ceil();
+    if !(1.0..=count as f64).contains(&k) {
+        return Err(CellError::Num);
+    }
+    Ok(k as usize - 1)
+}
+/// Returns the result of an arithmetic operation, or `CellError::Overflow` when it is
+/// too large to represent (infinite) or undefined (NaN).
+fn check_overflow(value: f64) -> Result<f64, CellError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(CellError::Overflow)
+    }
+}
+/// Compares two values for the comparison operators.
+/// An empty cell equals 0 next to a number and "" next to text, text is compared
+/// case-insensitively and values of different types order numbers < text < booleans.
+fn compare_values(first: &CellValue, second: &CellValue) -> Ordering {
+    let blank_like = |other: &CellValue| match other {
+        CellValue::Text(_) => CellValue::Text(String::new()),
+        CellValue::Bool(_) => CellValue::Bool(false),
+        _ => CellValue::Number(0.0),
+    };
+    match (first, second) {
+        (CellValue::Empty, CellValue::Empty) => Ordering::Equal,
+        (CellValue::Empty, other) => blank_like(other).sort_cmp(other),
+        (value, CellValue::Empty) => value.sort_cmp(&blank_like(value)),
+        _ => first.sort_cmp(second),
+    }
+}
+#[cfg(feature = "cli")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    //use crate::structs::*;
+    //use crate::structs::FunctionType::Sleep;
+
+    /// Returns the value shown in a cell
+    fn value_at(backend: &Backend, row: usize, col: usize) -> CellValue {
+        unsafe { (*backend.get_cell_value(row, col)).value.clone() }
+    }
+    /// Returns the number shown in a cell, panics on any other value
+    fn number_at(backend: &Backend, row: usize, col: usize) -> f64 {
+        match value_at(backend, row, col) {
+            CellValue::Number(value) => value,
+            other => panic!("not a number: {:?}", other),
+        }
+    }
+    /// Returns the cell of a reference such as `B3`
+    fn cell(name: &str) -> Cell {
+        crate::parser::parse_cell_reference(name, usize::MAX, usize::MAX).unwrap()
+    }
+    /// Returns the value shown in the cell of a reference such as `B3`
+    fn value_in(backend: &Backend, name: &str) -> CellValue {
+        let cell = cell(name);
+        value_at(backend, cell.row, cell.col)
+    }
+    /// Evaluates a formula without storing it in a cell
+    fn evaluate(backend: &Backend, expression: &str) -> CellValue {
+        let function = backend
+            .parse_expression(expression)
+            .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+        backend.evaluate_expression(&function)
+    }
+
+    #[test]
+    fn test_sleep_function_positive_value() {
+        let backend = Backend::new(3, 3);
+
+        // Set up an operand with a positive value
+        let operand = Operand {
+            type_: OperandType::Number,
+            data: OperandData::Value(2.0), // Sleep for 2 seconds
+            anchor: Anchor::default(),
+        };
+
+        let start_time = Instant::now();
+        let result = backend.sleep_function(&operand);
+        let elapsed_time = start_time.elapsed();
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2.0);
+        assert!(elapsed_time.as_secs() >= 2); // Ensure at least 2 seconds have passed
+    }
+
+    #[test]
+    fn test_sleep_function_zero_value() {
+        let backend = Backend::new(3, 3);
+
+        // Set up an operand with a value of 0
+        let operand = Operand {
+            type_: OperandType::Number,
+            data: OperandData::Value(0.0), // No sleep
+            anchor: Anchor::default(),
+        };
+
+        let start_time = Instant::now();
+        let result = backend.sleep_function(&operand);
+        let elapsed_time = start_time.elapsed();
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0.0);
+        assert!(elapsed_time.as_secs() < 1); // Ensure no significant delay
+    }
+
+    #[test]
+    fn test_sleep_function_negative_value() {
+        let backend = Backend::new(3, 3);
+
+        // Set up an operand with a negative value
+        let operand = Operand {
+            type_: OperandType::Number,
+            data: OperandData::Value(-5.0), // Negative value
+            anchor: Anchor::default(),
+        };
+
+        let result = backend.sleep_function(&operand);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), -5.0); // Negative values should not cause sleep
+    }
+
+    #[test]
+    fn test_new_backend() {
+        let backend = Backend::new(5, 5);
+        assert_eq!(backend.get_rows_col(), (5, 5));
+
+        let backend = Backend::new(0, 0);
+        assert_eq!(backend.get_rows_col(), (0, 0));
+
+        let backend = Backend::new(100, 100);
+        assert_eq!(backend.get_rows_col(), (100, 100));
+    }
+
+    #[test]
+    fn test_get_rows_col() {
+        let backend = Backend::new(3, 4);
+        assert_eq!(backend.get_rows_col(), (3, 4));
+
+        let backend = Backend::new(1, 1);
+        assert_eq!(backend.get_rows_col(), (1, 1));
+    }
+
+    #[test]
+    fn test_set_and_get_cell_value() {
+        let mut backend = Backend::new(3, 3);
+        let cell = Cell { row: 1, col: 1 };
+        let expression = "42";
+        backend.set_cell_value(cell, expression).unwrap();
+
+        unsafe {
+            let cell_data = backend.get_cell_value(1, 1);
+            assert_eq!((*cell_data).value, CellValue::Number(42.0));
+        }
+    }
+
+    #[test]
+    fn test_set_cell_value_constant() {
+        // Lines 148, 150-152, 154-157
+        let mut backend = Backend::new(3, 3);
+        let cell = Cell { row: 1, col: 1 };
+        backend.set_cell_value(cell, "42").unwrap();
+
+        unsafe {
+            let cell_data = backend.get_cell_value(1, 1);
+            assert_eq!((*cell_data).value, CellValue::Number(42.0));
+        }
+    }
+
+    #[test]
+    fn test_set_cell_value_circular_dependency() {
+        // Lines 159-161, 167-168
         let mut backend = Backend::new(3, 3);
         let cell = Cell { row: 0, col: 0 };
         // backend.set_cell_value(cell, "=A1").unwrap();
@@ -1412,12 +3109,12 @@ mod tests {
         let cell = Cell { row: 0, col: 0 };
         backend.set_cell_value(cell, "B1").unwrap();
 
-        let old_function = Function::new_constant(5);
+        let old_function = Function::new_constant(5.0);
         backend.update_graph(&cell, &old_function);
 
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            assert_eq!((*cell_data).value, 0); // Old dependencies removed
+            assert_eq!((*cell_data).value, CellValue::Number(0.0)); // Old dependencies removed
         }
     }
 
@@ -1450,9 +3147,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.min_function(&range).unwrap();
-        assert_eq!(result, 5);
+        assert_eq!(result, 5.0);
     }
 
     #[test]
@@ -1489,12 +3189,14 @@ mod tests {
         // Set up operands that will cause overflow
         let bin_op = BinaryOp {
             first: Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(2_147_483_647), // Maximum i32 value
+                type_: OperandType::Number,
+                data: OperandData::Value(f64::MAX), // Maximum finite value
+                anchor: Anchor::default(),
             },
             second: Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(2), // Multiplying by 2 will overflow
+                type_: OperandType::Number,
+                data: OperandData::Value(2.0), // Multiplying by 2 will overflow
+                anchor: Anchor::default(),
             },
         };
 
@@ -1503,6 +3205,80 @@ mod tests {
         assert_eq!(result.unwrap_err(), CellError::Overflow);
     }
 
+    #[test]
+    fn test_plus_and_minus_op_overflow() {
+        let backend = Backend::new(3, 3);
+        let bin_op = BinaryOp {
+            first: Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(f64::MAX),
+                anchor: Anchor::default(),
+            },
+            second: Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(f64::MAX),
+                anchor: Anchor::default(),
+            },
+        };
+        assert_eq!(backend.plus_op(&bin_op).unwrap_err(), CellError::Overflow);
+
+        let bin_op = BinaryOp {
+            first: Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(-f64::MAX),
+                anchor: Anchor::default(),
+            },
+            ..bin_op
+        };
+        assert_eq!(backend.minus_op(&bin_op).unwrap_err(), CellError::Overflow);
+
+        // Dividing by a tiny number overflows as well
+        let bin_op = BinaryOp {
+            first: Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(1e300),
+                anchor: Anchor::default(),
+            },
+            second: Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(1e-300),
+                anchor: Anchor::default(),
+            },
+        };
+        assert_eq!(backend.divide_op(&bin_op).unwrap_err(), CellError::Overflow);
+    }
+
+    #[test]
+    fn test_range_functions_overflow() {
+        let mut backend = Backend::new(3, 3);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "1e308")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "1e308")
+            .unwrap();
+        let range = RangeFunction {
+            top_left: Cell { row: 0, col: 0 },
+            bottom_right: Cell { row: 1, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        };
+
+        assert_eq!(backend.sum_function(&range).unwrap_err(), CellError::Overflow);
+        assert_eq!(backend.avg_function(&range).unwrap_err(), CellError::Overflow);
+        assert_eq!(backend.stdev_function(&range).unwrap_err(), CellError::Overflow);
+        // MIN and MAX never compute anything new
+        assert_eq!(backend.max_function(&range).unwrap(), 1e308);
+
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "-1e308")
+            .unwrap();
+        // The squared deviations overflow even though the sum does not
+        assert_eq!(backend.sum_function(&range).unwrap(), 0.0);
+        assert_eq!(backend.stdev_function(&range).unwrap_err(), CellError::Overflow);
+    }
+
     #[test]
     fn test_divide_op_by_zero() {
         let backend = Backend::new(3, 3);
@@ -1510,12 +3286,14 @@ mod tests {
         // Set up operands where the second operand is zero
         let bin_op = BinaryOp {
             first: Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(42),
+                type_: OperandType::Number,
+                data: OperandData::Value(42.0),
+                anchor: Anchor::default(),
             },
             second: Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(0), // Division by zero
+                type_: OperandType::Number,
+                data: OperandData::Value(0.0), // Division by zero
+                anchor: Anchor::default(),
             },
         };
 
@@ -1548,7 +3326,7 @@ mod tests {
 
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            assert_eq!((*cell_data).value, 10);
+            assert_eq!((*cell_data).value, CellValue::Number(10.0));
         }
     }
 
@@ -1560,19 +3338,20 @@ mod tests {
             FunctionType::Plus,
             BinaryOp {
                 first: Operand {
-                    type_: OperandType::Int,
-                    data: OperandData::Value(10),
+                    type_: OperandType::Number,
+                    data: OperandData::Value(10.0),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
-                    type_: OperandType::Int,
-                    data: OperandData::Value(20),
+                    type_: OperandType::Number,
+                    data: OperandData::Value(20.0),
+                    anchor: Anchor::default(),
                 },
             },
         );
 
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 30);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(30.0));
     }
 
     // #[test]
@@ -1588,7 +3367,7 @@ mod tests {
     //         bottom_right: Cell { row: 0, col: 2 },
     //     };
     //     let result = backend.min_function(&range).unwrap();
-    //     assert_eq!(result, 5);
+    //     assert_eq!(result, 5.0);
     // }
 
     #[test]
@@ -1607,9 +3386,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.max_function(&range).unwrap();
-        assert_eq!(result, 20);
+        assert_eq!(result, 20.0);
 
         // Test with negative values
         backend
@@ -1621,9 +3403,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 1, col: 0 },
             bottom_right: Cell { row: 1, col: 1 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.max_function(&range).unwrap();
-        assert_eq!(result, -10);
+        assert_eq!(result, -10.0);
     }
 
     #[test]
@@ -1642,9 +3427,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.avg_function(&range).unwrap();
-        assert_eq!(result, 20);
+        assert_eq!(result, 20.0);
 
         // Test with zero values
         backend
@@ -1656,9 +3444,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 1, col: 0 },
             bottom_right: Cell { row: 1, col: 1 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.avg_function(&range).unwrap();
-        assert_eq!(result, 0);
+        assert_eq!(result, 0.0);
     }
 
     #[test]
@@ -1677,9 +3468,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.sum_function(&range).unwrap();
-        assert_eq!(result, 60);
+        assert_eq!(result, 60.0);
 
         // Test with negative values
         backend
@@ -1691,9 +3485,12 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 1, col: 0 },
             bottom_right: Cell { row: 1, col: 1 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.sum_function(&range).unwrap();
-        assert_eq!(result, -30);
+        assert_eq!(result, -30.0);
     }
 
     #[test]
@@ -1712,17 +3509,23 @@ mod tests {
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.stdev_function(&range).unwrap();
-        assert_eq!(result, 8); // Standard deviation of [10, 20, 30] is approximately 8.16, floored to 8
+        assert!((result - 8.164_965_809).abs() < 1e-9); // Standard deviation of [10, 20, 30] is approximately 8.16
 
         // Test with a single value
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
         let result = backend.stdev_function(&range).unwrap();
-        assert_eq!(result, 0);
+        assert_eq!(result, 0.0);
     }
 
     #[test]
@@ -1739,21 +3542,23 @@ mod tests {
             first: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             },
             second: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                anchor: Anchor::default(),
             },
         };
         let result = backend.plus_op(&bin_op).unwrap();
-        assert_eq!(result, 30);
+        assert_eq!(result, 30.0);
 
         // Test with negative values
         backend
             .set_cell_value(Cell { row: 0, col: 0 }, "-10")
             .unwrap();
         let result = backend.plus_op(&bin_op).unwrap();
-        assert_eq!(result, 10);
+        assert_eq!(result, 10.0);
     }
 
     #[test]
@@ -1770,21 +3575,23 @@ mod tests {
             first: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             },
             second: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                anchor: Anchor::default(),
             },
         };
         let result = backend.minus_op(&bin_op).unwrap();
-        assert_eq!(result, 10);
+        assert_eq!(result, 10.0);
 
         // Test with negative values
         backend
             .set_cell_value(Cell { row: 0, col: 1 }, "-10")
             .unwrap();
         let result = backend.minus_op(&bin_op).unwrap();
-        assert_eq!(result, 30);
+        assert_eq!(result, 30.0);
     }
 
     #[test]
@@ -1801,21 +3608,23 @@ mod tests {
             first: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             },
             second: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                anchor: Anchor::default(),
             },
         };
         let result = backend.multiply_op(&bin_op).unwrap();
-        assert_eq!(result, 20);
+        assert_eq!(result, 20.0);
 
         // Test with zero
         backend
             .set_cell_value(Cell { row: 0, col: 1 }, "0")
             .unwrap();
         let result = backend.multiply_op(&bin_op).unwrap();
-        assert_eq!(result, 0);
+        assert_eq!(result, 0.0);
     }
 
     #[test]
@@ -1832,14 +3641,16 @@ mod tests {
             first: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             },
             second: Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                anchor: Anchor::default(),
             },
         };
         let result = backend.divide_op(&bin_op).unwrap();
-        assert_eq!(result, 2);
+        assert_eq!(result, 2.0);
 
         // Test division by zero
         backend
@@ -1861,10 +3672,11 @@ mod tests {
         let operand = Operand {
             type_: OperandType::Cell,
             data: OperandData::Cell(Cell { row: 0, col: 0 }),
+            anchor: Anchor::default(),
         };
 
         let result = backend.get_operand_value(&operand).unwrap();
-        assert_eq!(result, 42);
+        assert_eq!(result, 42.0);
     }
 
     #[test]
@@ -1892,16 +3704,16 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 1, col: 1 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
 
         // Verify that the old dependencies are removed
-        unsafe {
-            for row in 0..=1 {
-                for col in 0..=1 {
-                    let parent_data = backend.get_cell_value(row, col);
-                    assert!(!(*parent_data).dependents.contains(&(2, 2)));
-                }
+        for row in 0..=1 {
+            for col in 0..=1 {
+                assert!(!backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
             }
         }
 
@@ -1912,12 +3724,9 @@ mod tests {
         // backend.update_graph(&cell, &old_function);
 
         // Verify that the new dependencies are added
-        unsafe {
-            for row in 0..=1 {
-                for col in 0..=1 {
-                    let parent_data = backend.get_cell_value(row, col);
-                    assert!((*parent_data).dependents.contains(&(2, 2)));
-                }
+        for row in 0..=1 {
+            for col in 0..=1 {
+                assert!(backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
             }
         }
     }
@@ -1934,10 +3743,12 @@ mod tests {
                 first: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 1, col: 1 }),
+                    anchor: Anchor::default(),
                 },
             },
         );
@@ -1978,6 +3789,7 @@ mod tests {
             data: FunctionData::SleepValue(Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             }),
         };
 
@@ -2009,8 +3821,9 @@ mod tests {
         let _old_function = Function {
             type_: FunctionType::Sleep,
             data: FunctionData::SleepValue(Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(0),
+                type_: OperandType::Number,
+                data: OperandData::Value(0.0),
+                anchor: Anchor::default(),
             }),
         };
 
@@ -2027,15 +3840,12 @@ mod tests {
         backend.set_cell_value(cell, "SUM(A1:B2)").unwrap();
 
         // Update the graph
-        // backend.update_graph(&cell, &Function::new_constant(0));
+        // backend.update_graph(&cell, &Function::new_constant(0.0));
 
         // Verify that the dependencies are added
-        unsafe {
-            for row in 0..=1 {
-                for col in 0..=1 {
-                    let parent_data = backend.get_cell_value(row, col);
-                    assert!((*parent_data).dependents.contains(&(2, 2)));
-                }
+        for row in 0..=1 {
+            for col in 0..=1 {
+                assert!(backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
             }
         }
     }
@@ -2052,12 +3862,13 @@ mod tests {
         let operand = Operand {
             type_: OperandType::Cell,
             data: OperandData::Cell(cell),
+            anchor: Anchor::default(),
         };
 
         // Simulate a division by zero error
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         // Call get_operand_value and verify the error
@@ -2078,18 +3889,19 @@ mod tests {
         let operand = Operand {
             type_: OperandType::Cell,
             data: OperandData::Cell(cell),
+            anchor: Anchor::default(),
         };
 
         // Simulate a dependency error
         unsafe {
             let cell_data = backend.get_cell_value(1, 1);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         // Call get_operand_value and verify the error
         let result = backend.get_operand_value(&operand);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2101,12 +3913,15 @@ mod tests {
         backend.set_cell_value(cell, "0").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.sum_function(&range);
@@ -2123,17 +3938,20 @@ mod tests {
         backend.set_cell_value(cell, "42").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.sum_function(&range);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2145,12 +3963,15 @@ mod tests {
         backend.set_cell_value(cell, "0").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.stdev_function(&range);
@@ -2167,17 +3988,20 @@ mod tests {
         backend.set_cell_value(cell, "42").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.stdev_function(&range);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2189,12 +4013,15 @@ mod tests {
         backend.set_cell_value(cell, "0").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.avg_function(&range);
@@ -2211,17 +4038,20 @@ mod tests {
         backend.set_cell_value(cell, "42").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.avg_function(&range);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2233,12 +4063,15 @@ mod tests {
         backend.set_cell_value(cell, "0").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.max_function(&range);
@@ -2255,17 +4088,20 @@ mod tests {
         backend.set_cell_value(cell, "42").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.max_function(&range);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2277,12 +4113,15 @@ mod tests {
         backend.set_cell_value(cell, "0").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DivideByZero;
+            (*cell_data).value = CellValue::Error(CellError::DivideByZero);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.min_function(&range);
@@ -2299,17 +4138,20 @@ mod tests {
         backend.set_cell_value(cell, "42").unwrap();
         unsafe {
             let cell_data = backend.get_cell_value(0, 0);
-            (*cell_data).error = CellError::DependencyError;
+            (*cell_data).value = CellValue::Error(CellError::InvalidReference);
         }
 
         let range = RangeFunction {
             top_left: Cell { row: 0, col: 0 },
             bottom_right: Cell { row: 0, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
         };
 
         let result = backend.min_function(&range);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), CellError::DependencyError);
+        assert_eq!(result.unwrap_err(), CellError::InvalidReference);
     }
 
     #[test]
@@ -2331,18 +4173,19 @@ mod tests {
                 first: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                    anchor: Anchor::default(),
                 },
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 10);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(10.0));
     }
 
     #[test]
@@ -2364,18 +4207,19 @@ mod tests {
                 first: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                    anchor: Anchor::default(),
                 },
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 20);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(20.0));
     }
 
     #[test]
@@ -2397,18 +4241,19 @@ mod tests {
                 first: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 1 }),
+                    anchor: Anchor::default(),
                 },
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 2);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(2.0));
     }
 
     #[test]
@@ -2432,13 +4277,15 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 0, col: 2 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 5);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(5.0));
     }
 
     #[test]
@@ -2462,13 +4309,15 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 0, col: 2 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 20);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(20.0));
     }
 
     #[test]
@@ -2492,13 +4341,15 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 0, col: 2 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 20);
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func);
+        assert_eq!(value, CellValue::Number(20.0));
     }
 
     #[test]
@@ -2522,13 +4373,15 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 0, col: 2 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
 
         // Evaluate the function
-        let (value, error) = backend.evaluate_expression(&func);
-        assert_eq!(value, 8); // Standard deviation of [10, 20, 30] is approximately 8.16, floored to 8
-        assert_eq!(error, CellError::NoError);
+        let value = backend.evaluate_expression(&func).as_number().unwrap();
+        assert!((value - 8.164_965_809).abs() < 1e-9); // Standard deviation of [10, 20, 30] is approximately 8.16
     }
 
     #[test]
@@ -2542,15 +4395,15 @@ mod tests {
             RangeFunction {
                 top_left: Cell { row: 0, col: 0 },
                 bottom_right: Cell { row: 1, col: 1 },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
             },
         );
         // Verify that the old dependencies are removed
-        unsafe {
-            for row in 0..=1 {
-                for col in 0..=1 {
-                    let parent_data = backend.get_cell_value(row, col);
-                    assert!(!(*parent_data).dependents.contains(&(2, 2)));
-                }
+        for row in 0..=1 {
+            for col in 0..=1 {
+                assert!(!backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
             }
         }
 
@@ -2561,12 +4414,9 @@ mod tests {
         backend.update_graph(&cell, &old_function);
 
         // Verify that the new dependencies are added
-        unsafe {
-            for row in 0..=1 {
-                for col in 0..=1 {
-                    let parent_data = backend.get_cell_value(row, col);
-                    assert!((*parent_data).dependents.contains(&(2, 2)));
-                }
+        for row in 0..=1 {
+            for col in 0..=1 {
+                assert!(backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
             }
         }
     }
@@ -2583,10 +4433,12 @@ mod tests {
                 first: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                    anchor: Anchor::default(),
                 },
                 second: Operand {
                     type_: OperandType::Cell,
                     data: OperandData::Cell(Cell { row: 1, col: 1 }),
+                    anchor: Anchor::default(),
                 },
             },
         );
@@ -2626,6 +4478,7 @@ mod tests {
             data: FunctionData::SleepValue(Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
             }),
         };
         // Verify that the old dependencies are removed
@@ -2646,4 +4499,1371 @@ mod tests {
             assert!((*parent_data).dependents.contains(&(2, 2)));
         }
     }
+
+    #[test]
+    fn test_evaluate_expression_tree() {
+        let mut backend = Backend::new(5, 5);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "10")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "4")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "A1+B1*2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "(A1+B1)/2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 2 }, "SUM(A1:B1)+1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 3 }, "-A1*-(B1-1)")
+            .unwrap();
+
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(18.0));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Number(7.0));
+            assert_eq!((*backend.get_cell_value(1, 2)).value, CellValue::Number(15.0));
+            assert_eq!((*backend.get_cell_value(1, 3)).value, CellValue::Number(30.0));
+        }
+
+        // Editing a parent recalculates every tree that reads it
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "6")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(22.0));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Number(8.0));
+            assert_eq!((*backend.get_cell_value(1, 2)).value, CellValue::Number(17.0));
+            assert_eq!((*backend.get_cell_value(1, 3)).value, CellValue::Number(50.0));
+        }
+    }
+
+    #[test]
+    fn test_evaluate_expression_tree_errors() {
+        let mut backend = Backend::new(5, 5);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "(1+2)/(B1-B1)")
+            .unwrap();
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(0, 0)).value,
+                CellValue::Error(CellError::DivideByZero)
+            );
+        }
+
+        // Self reference inside a tree
+        let result = backend.set_cell_value(Cell { row: 1, col: 0 }, "1+SUM(A1:A3)");
+        assert_eq!(result.unwrap_err(), ExpressionError::CircularDependency);
+
+        // Cycle through a tree
+        backend
+            .set_cell_value(Cell { row: 2, col: 0 }, "B3*2+1")
+            .unwrap();
+        let result = backend.set_cell_value(Cell { row: 2, col: 1 }, "(A3+1)*2");
+        assert_eq!(result.unwrap_err(), ExpressionError::CircularDependency);
+    }
+
+    #[test]
+    fn test_update_graph_with_expression_tree() {
+        let mut backend = Backend::new(5, 5);
+        let cell = Cell { row: 2, col: 2 };
+        backend.set_cell_value(cell, "A1+B2*MAX(A3:B3)").unwrap();
+
+        for (row, col) in [(0, 0), (1, 1), (2, 0), (2, 1)] {
+            assert!(backend.cell_dependents(&Cell { row, col }).contains(&(2, 2)));
+        }
+
+        // Replacing the tree removes every old edge
+        backend.set_cell_value(cell, "5").unwrap();
+        for (row, col) in [(0, 0), (1, 1), (2, 0), (2, 1)] {
+            assert!(backend.cell_dependents(&Cell { row, col }).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decimal_arithmetic() {
+        let mut backend = Backend::new(3, 3);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "7")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "0.25")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "A1/B1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "AVG(A1:B1)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 2 }, "C1*1e-3+1.5")
+            .unwrap();
+
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(3.5));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Number(4.5));
+            assert_eq!((*backend.get_cell_value(1, 2)).value, CellValue::Number(1.50025));
+        }
+    }
+
+    #[test]
+    fn test_text_and_bool_values() {
+        let mut backend = Backend::new(3, 3);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "\"total\"")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "4")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "TRUE")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "SUM(A1:C1)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "A1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 2 }, "A1+1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 2, col: 0 }, "C1*3")
+            .unwrap();
+
+        unsafe {
+            // Text and booleans are skipped by range functions
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(4.0));
+            // A plain reference copies text through
+            assert_eq!(
+                (*backend.get_cell_value(1, 1)).value,
+                CellValue::Text("total".to_string())
+            );
+            assert_eq!(
+                (*backend.get_cell_value(1, 2)).value,
+                CellValue::Error(CellError::TypeMismatch)
+            );
+            // Booleans count as 1 in arithmetic
+            assert_eq!((*backend.get_cell_value(2, 0)).value, CellValue::Number(3.0));
+            // Untouched cells are empty
+            assert_eq!((*backend.get_cell_value(2, 2)).value, CellValue::Empty);
+        }
+    }
+
+    #[test]
+    fn test_conditional_functions() {
+        let mut backend = Backend::new(4, 4);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "5")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "0")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "\"Apple\"")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "IF(A1>3,A1*2,B1)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "AND(A1>=5,OR(B1,C1=\"apple\"))")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 2 }, "NOT(A1<>5)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 3 }, "IFERROR(A1/B1,-1)")
+            .unwrap();
+        // The branch that is not taken does not raise its error
+        backend
+            .set_cell_value(Cell { row: 2, col: 0 }, "IF(B1=0,0,A1/B1)")
+            .unwrap();
+        // An empty cell equals both 0 and ""
+        backend
+            .set_cell_value(Cell { row: 2, col: 1 }, "AND(D4=0,D4=\"\")")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 2, col: 2 }, "IF(C1,1,2)")
+            .unwrap();
+
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(10.0));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Bool(true));
+            assert_eq!((*backend.get_cell_value(1, 2)).value, CellValue::Bool(true));
+            assert_eq!((*backend.get_cell_value(1, 3)).value, CellValue::Number(-1.0));
+            assert_eq!((*backend.get_cell_value(2, 0)).value, CellValue::Number(0.0));
+            assert_eq!((*backend.get_cell_value(2, 1)).value, CellValue::Bool(true));
+            assert_eq!(
+                (*backend.get_cell_value(2, 2)).value,
+                CellValue::Error(CellError::TypeMismatch)
+            );
+
+            // Every cell referenced by IF, in the condition or in a branch, is a parent
+            for (row, col) in [(0, 0), (0, 1)] {
+                assert!((*backend.get_cell_value(row, col)).dependents.contains(&(1, 0)));
+            }
+        }
+
+        // Changing the condition switches the branch
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "1")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(0.0));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Bool(false));
+        }
+
+        let result = backend.set_cell_value(Cell { row: 0, col: 1 }, "IF(A1>0,1,B2)");
+        assert_eq!(result.unwrap_err(), ExpressionError::CircularDependency);
+    }
+
+    #[test]
+    fn test_error_kinds_propagate() {
+        let mut backend = Backend::new(4, 4);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "1/0")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "\"text\"*2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "1e308*10")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 3 }, "#REF!")
+            .unwrap();
+        // Each dependent sees the original error, not a generic one
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "A1+1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "SUM(B1:B1)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 2 }, "(C1+1)*2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 3 }, "MAX(A3:D3)+D1")
+            .unwrap();
+
+        unsafe {
+            let shown: Vec<String> = (0..4)
+                .map(|col| (*backend.get_cell_value(1, col)).value.to_string())
+                .collect();
+            assert_eq!(shown, ["#DIV/0!", "#VALUE!", "#NUM!", "#REF!"]);
+            assert_eq!(
+                (*backend.get_cell_value(0, 3)).value,
+                CellValue::Error(CellError::InvalidReference)
+            );
+        }
+
+        // Error literals are read back from their codes, both #NUM! errors as `Num`
+        for error in CellError::ALL {
+            let function = backend.parse_expression(error.code())
+                .unwrap_or_else(|err| panic!("{}: {}", error, err));
+            let read = if error == CellError::Overflow { CellError::Num } else { error };
+            assert_eq!(backend.evaluate_expression(&function), CellValue::Error(read));
+        }
+        assert!(backend.parse_expression("#OOPS!").is_err());
+    }
+
+    #[test]
+    fn test_negative_operands() {
+        let mut backend = Backend::new(3, 3);
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "4")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "A1*-2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "-5+A1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 0 }, "-A1")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "-MIN(A1:C1)")
+            .unwrap();
+
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 1)).value, CellValue::Number(-8.0));
+            assert_eq!((*backend.get_cell_value(0, 2)).value, CellValue::Number(-1.0));
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Number(-4.0));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Number(8.0));
+        }
+    }
+
+    #[test]
+    fn test_defined_names() {
+        let mut backend = Backend::new(6, 6);
+        for (row, value) in ["1", "2", "3"].iter().enumerate() {
+            backend
+                .set_cell_value(Cell { row, col: 0 }, value)
+                .unwrap();
+        }
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "0.5")
+            .unwrap();
+        // Used before it is defined
+        backend
+            .set_cell_value(Cell { row: 3, col: 3 }, "SUM(ITEMS)*RATE")
+            .unwrap();
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(3, 3)).value,
+                CellValue::Error(CellError::UnknownName)
+            );
+        }
+
+        backend.define_name("ITEMS", "A1:A3").unwrap();
+        backend.define_name("RATE", "B1").unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(3, 3)).value, CellValue::Number(3.0));
+        }
+
+        // Dependencies go through the name
+        backend
+            .set_cell_value(Cell { row: 2, col: 0 }, "7")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(3, 3)).value, CellValue::Number(5.0));
+        }
+
+        // Redefining recalculates and moves the edges
+        backend.define_name("RATE", "2").unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "100")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(3, 3)).value, CellValue::Number(20.0));
+            assert!(!(*backend.get_cell_value(0, 1)).dependents.contains(&(3, 3)));
+        }
+
+        // A definition that would make a formula refer to itself is rejected
+        let result = backend.define_name("ITEMS", "A1:D4");
+        assert_eq!(result.unwrap_err(), ExpressionError::CircularDependency);
+        let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+        assert_eq!(backend.resolve_name("ITEMS").formula_text(&layout).to_string(), "A1:A3");
+        unsafe {
+            assert_eq!((*backend.get_cell_value(3, 3)).value, CellValue::Number(20.0));
+        }
+
+        assert!(backend.delete_name("ITEMS"));
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(3, 3)).value,
+                CellValue::Error(CellError::UnknownName)
+            );
+        }
+        assert!(!backend.cell_dependents(&Cell { row: 0, col: 0 }).contains(&(3, 3)));
+
+        let reason = |result: Result<(), ExpressionError>| match result {
+            Err(ExpressionError::CouldNotParse(error)) => error.reason,
+            other => panic!("not a parse error: {:?}", other),
+        };
+        assert_eq!(reason(backend.define_name("B2", "A1")), "invalid name B2");
+        assert_eq!(
+            reason(backend.define_name("RATE", "A1*2")),
+            "a name stands for a cell, a range or a constant"
+        );
+        assert_eq!(
+            backend.get_names().keys().collect::<Vec<_>>(),
+            ["RATE"]
+        );
+    }
+
+    #[test]
+    fn test_sheets() {
+        let mut backend = Backend::new(5, 5);
+        assert!(backend.add_sheet("Costs"));
+        assert!(!backend.add_sheet("Costs"));
+        assert!(!backend.add_sheet("2nd"));
+        assert!(!backend.add_sheet("My Sheet"));
+        assert_eq!(backend.get_sheet_names(), ["Sheet1", "Costs"]);
+
+        // Costs!A1 is row 5 of the workbook
+        backend.set_cell_value(Cell { row: 5, col: 0 }, "4").unwrap();
+        backend.set_cell_value(Cell { row: 6, col: 0 }, "6").unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "Costs!A1*2")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 1 }, "SUM(Costs!A1:A2)+A1")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 0)).value, CellValue::Number(8.0));
+            assert_eq!((*backend.get_cell_value(0, 1)).value, CellValue::Number(18.0));
+        }
+
+        // Changes on one sheet reach the formulas of the other
+        backend.set_cell_value(Cell { row: 5, col: 0 }, "1").unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 0)).value, CellValue::Number(2.0));
+            assert_eq!((*backend.get_cell_value(0, 1)).value, CellValue::Number(9.0));
+        }
+
+        // Cycles through another sheet are rejected
+        let result = backend.set_cell_value(Cell { row: 5, col: 0 }, "Sheet1!B1");
+        assert_eq!(result, Err(ExpressionError::CircularDependency));
+
+        // References without a sheet name are on the active sheet
+        assert!(backend.switch_sheet("Costs"));
+        assert!(!backend.switch_sheet("Missing"));
+        assert_eq!(backend.on_active_sheet(Cell { row: 2, col: 0 }), Cell { row: 7, col: 0 });
+        backend
+            .set_cell_value(Cell { row: 7, col: 0 }, "A1+A2+Sheet1!A1")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(7, 0)).value, CellValue::Number(9.0));
+        }
+
+        // Renamed sheets are printed with their new name
+        assert!(backend.rename_sheet("Costs", "Budget"));
+        assert!(!backend.rename_sheet("Costs", "Other"));
+        assert!(!backend.rename_sheet("Budget", "Sheet1"));
+        let function = unsafe { (*backend.get_cell_value(0, 1)).function.clone() };
+        let FunctionData::Expression(expr) = function.data else {
+            panic!("expected an expression");
+        };
+        let layout = backend.sheet_layout(Cell { row: 0, col: 1 });
+        assert_eq!(expr.formula_text(&layout).to_string(), "SUM(Budget!A1:A2)+A1");
+        assert!(backend.parse_expression("Costs!A1").is_err());
+    }
+
+    #[test]
+    fn test_count_and_conditional_aggregates() {
+        let mut backend = Backend::new(10, 10);
+        // A1:A6 = 5, 12, "apple", TRUE, (empty), 20 and B1:B6 = 1..6
+        for (row, value) in ["5", "12", "\"Apple\"", "TRUE", "", "20"].iter().enumerate() {
+            if !value.is_empty() {
+                backend.set_cell_value(Cell { row, col: 0 }, value).unwrap();
+            }
+            backend
+                .set_cell_value(Cell { row, col: 1 }, &(row + 1).to_string())
+                .unwrap();
+        }
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+
+        assert_eq!(value(&backend, "COUNT(A1:A6)"), CellValue::Number(3.0));
+        assert_eq!(value(&backend, "COUNTA(A1:A6)"), CellValue::Number(5.0));
+        assert_eq!(value(&backend, "COUNTBLANK(A1:B7)"), CellValue::Number(3.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,\">10\")"), CellValue::Number(2.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,\"<>5\")"), CellValue::Number(5.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,\"apple\")"), CellValue::Number(1.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,12)"), CellValue::Number(1.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,\"=\")"), CellValue::Number(1.0));
+        assert_eq!(value(&backend, "COUNTIF(A1:A6,TRUE)"), CellValue::Number(1.0));
+        assert_eq!(value(&backend, "SUMIF(A1:A6,\">=12\")"), CellValue::Number(32.0));
+        assert_eq!(value(&backend, "SUMIF(A1:A6,\">=12\",B1:B6)"), CellValue::Number(8.0));
+        assert_eq!(value(&backend, "AVERAGEIF(B1:B6,\"<4\")"), CellValue::Number(2.0));
+        assert_eq!(
+            value(&backend, "AVERAGEIF(A1:A6,\">100\",B1:B6)"),
+            CellValue::Error(CellError::DivideByZero)
+        );
+        assert_eq!(
+            value(&backend, "SUMIF(A1:A6,\">0\",B1:B5)"),
+            CellValue::Error(CellError::TypeMismatch)
+        );
+
+        // Every range and the criterion cell are dependencies
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "\">10\"")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 3 }, "SUMIF(A1:A6,C1,B1:B6)")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 3)).value, CellValue::Number(8.0));
+        }
+        backend
+            .set_cell_value(Cell { row: 5, col: 1 }, "100")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 3)).value, CellValue::Number(102.0));
+        }
+        backend
+            .set_cell_value(Cell { row: 0, col: 0 }, "50")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "\"<20\"")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 3)).value, CellValue::Number(2.0));
+        }
+
+        // Errors in the added cells are passed on
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "1/0")
+            .unwrap();
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(0, 3)).value,
+                CellValue::Error(CellError::DivideByZero)
+            );
+        }
+    }
+
+    #[test]
+    fn test_statistical_functions() {
+        let mut backend = Backend::new(10, 10);
+        for (row, value) in ["2", "4", "4", "4", "5", "5", "7", "9", "\"n/a\""].iter().enumerate() {
+            backend.set_cell_value(Cell { row, col: 0 }, value).unwrap();
+        }
+        for row in 0..3 {
+            backend
+                .set_cell_value(Cell { row, col: 1 }, &(row + 1).to_string())
+                .unwrap();
+        }
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+        let number = |backend: &Backend, expression: &str| match value(backend, expression) {
+            CellValue::Number(number) => number,
+            other => panic!("{} gave {:?}", expression, other),
+        };
+
+        assert_eq!(number(&backend, "MEDIAN(A1:A9)"), 4.5);
+        assert_eq!(number(&backend, "MEDIAN(B1:B3)"), 2.0);
+        assert_eq!(number(&backend, "MODE(A1:A9)"), 4.0);
+        assert_eq!(number(&backend, "VAR.P(A1:A9)"), 4.0);
+        assert!((number(&backend, "VAR.S(A1:A9)") - 32.0 / 7.0).abs() < 1e-12);
+        assert_eq!(number(&backend, "STDEV.P(A1:A9)"), 2.0);
+        assert_eq!(number(&backend, "STDEV(A1:A9)"), 2.0);
+        assert!((number(&backend, "STDEV.S(A1:A9)") - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert_eq!(number(&backend, "PERCENTILE(A1:A9,0.25)"), 4.0);
+        assert_eq!(number(&backend, "PERCENTILE(A1:A9,1)"), 9.0);
+        assert_eq!(number(&backend, "QUARTILE(A1:A9,3)"), 5.5);
+        assert_eq!(number(&backend, "QUARTILE(A1:A9,0)"), 2.0);
+        assert_eq!(number(&backend, "LARGE(A1:A9,2)"), 7.0);
+        assert_eq!(number(&backend, "SMALL(A1:A9,3)"), 4.0);
+        assert_eq!(number(&backend, "RANK(A5,A1:A9)"), 3.0);
+        assert_eq!(number(&backend, "RANK(5,A1:A9,1)"), 5.0);
+        assert_eq!(number(&backend, "RANK(4,A1:A9,0)"), 5.0);
+
+        let error = |error| CellValue::Error(error);
+        assert_eq!(value(&backend, "MODE(B1:B3)"), error(CellError::NotAvailable));
+        assert_eq!(value(&backend, "RANK(6,A1:A9)"), error(CellError::NotAvailable));
+        assert_eq!(value(&backend, "VAR.S(B1:B1)"), error(CellError::DivideByZero));
+        assert_eq!(value(&backend, "MEDIAN(C1:C3)"), error(CellError::Num));
+        assert_eq!(value(&backend, "PERCENTILE(A1:A9,1.5)"), error(CellError::Num));
+        assert_eq!(value(&backend, "QUARTILE(A1:A9,5)"), error(CellError::Num));
+        assert_eq!(value(&backend, "LARGE(A1:A9,9)"), error(CellError::Num));
+        assert_eq!(value(&backend, "SMALL(A1:A9,0)"), error(CellError::Num));
+        assert_eq!(value(&backend, "LARGE(A1:A9,A9)"), error(CellError::TypeMismatch));
+
+        // Results follow changes in the range
+        backend
+            .set_cell_value(Cell { row: 0, col: 2 }, "MEDIAN(B1:B3)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 2, col: 1 }, "30")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 2)).value, CellValue::Number(2.0));
+        }
+        backend
+            .set_cell_value(Cell { row: 1, col: 1 }, "1/0")
+            .unwrap();
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(0, 2)).value,
+                CellValue::Error(CellError::DivideByZero)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_functions() {
+        let mut backend = Backend::new(10, 10);
+        // A1:C4 holds ids, names and prices sorted by id
+        let table = [
+            ["10", "\"Apple\"", "1.5"],
+            ["20", "\"Pear\"", "2"],
+            ["30", "\"Plum\"", "0.5"],
+            ["40", "\"Kiwi\"", "1/0"],
+        ];
+        for (row, line) in table.iter().enumerate() {
+            for (col, value) in line.iter().enumerate() {
+                backend.set_cell_value(Cell { row, col }, value).unwrap();
+            }
+        }
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+        let text = |text: &str| CellValue::Text(text.to_string());
+        let error = |error| CellValue::Error(error);
+
+        assert_eq!(value(&backend, "VLOOKUP(20,A1:C4,2,FALSE)"), text("Pear"));
+        assert_eq!(value(&backend, "VLOOKUP(25,A1:C4,3)"), CellValue::Number(2.0));
+        assert_eq!(value(&backend, "VLOOKUP(99,A1:C4,2,TRUE)"), text("Kiwi"));
+        assert_eq!(value(&backend, "VLOOKUP(25,A1:C4,2,FALSE)"), error(CellError::NotAvailable));
+        assert_eq!(value(&backend, "VLOOKUP(5,A1:C4,2)"), error(CellError::NotAvailable));
+        assert_eq!(value(&backend, "VLOOKUP(20,A1:C4,4,FALSE)"), error(CellError::InvalidReference));
+        assert_eq!(value(&backend, "VLOOKUP(20,A1:C4,0,FALSE)"), error(CellError::TypeMismatch));
+        assert_eq!(value(&backend, "VLOOKUP(40,A1:C4,3,FALSE)"), error(CellError::DivideByZero));
+        assert_eq!(value(&backend, "HLOOKUP(\"plum\",B3:C3,2,FALSE)"), error(CellError::InvalidReference));
+        assert_eq!(value(&backend, "HLOOKUP(30,A3:C4,2,FALSE)"), CellValue::Number(40.0));
+        assert_eq!(value(&backend, "INDEX(A1:C4,3,2)"), text("Plum"));
+        assert_eq!(value(&backend, "INDEX(B1:B4,2)"), text("Pear"));
+        assert_eq!(value(&backend, "INDEX(A2:C2,3)"), CellValue::Number(2.0));
+        assert_eq!(value(&backend, "INDEX(A1:C4,5,1)"), error(CellError::InvalidReference));
+        assert_eq!(value(&backend, "MATCH(\"KIWI\",B1:B4,0)"), CellValue::Number(4.0));
+        assert_eq!(value(&backend, "MATCH(35,A1:A4)"), CellValue::Number(3.0));
+        assert_eq!(value(&backend, "MATCH(35,A1:A4,0)"), error(CellError::NotAvailable));
+        assert_eq!(value(&backend, "MATCH(20,A1:B4,0)"), error(CellError::NotAvailable));
+        assert_eq!(
+            value(&backend, "INDEX(C1:C4,MATCH(\"Plum\",B1:B4,0))"),
+            CellValue::Number(0.5)
+        );
+
+        // Descending data with mode -1
+        for (row, value) in ["9", "7", "3"].iter().enumerate() {
+            backend.set_cell_value(Cell { row, col: 5 }, value).unwrap();
+        }
+        assert_eq!(value(&backend, "MATCH(5,F1:F3,-1)"), CellValue::Number(2.0));
+
+        // A change anywhere in the table recalculates the lookup
+        backend
+            .set_cell_value(Cell { row: 0, col: 4 }, "VLOOKUP(30,A1:C4,3,FALSE)")
+            .unwrap();
+        backend
+            .set_cell_value(Cell { row: 2, col: 2 }, "0.75")
+            .unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 4)).value, CellValue::Number(0.75));
+        }
+        backend
+            .set_cell_value(Cell { row: 2, col: 0 }, "35")
+            .unwrap();
+        unsafe {
+            assert_eq!(
+                (*backend.get_cell_value(0, 4)).value,
+                CellValue::Error(CellError::NotAvailable)
+            );
+        }
+    }
+
+    #[test]
+    fn test_aggregate_argument_lists() {
+        let mut backend = Backend::new(10, 10);
+        for row in 0..5 {
+            backend
+                .set_cell_value(Cell { row, col: 0 }, &(row + 1).to_string())
+                .unwrap();
+        }
+        backend.set_cell_value(Cell { row: 0, col: 2 }, "100").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 3 }, "1000").unwrap();
+        backend.set_cell_value(Cell { row: 2, col: 3 }, "\"text\"").unwrap();
+        let value_at = |backend: &Backend, row, col| unsafe {
+            (*backend.get_cell_value(row, col)).value.clone()
+        };
+
+        // E1 = 15 + 100 + 10 + 1000
+        let sum = Cell { row: 0, col: 4 };
+        backend
+            .set_cell_value(sum, "SUM(A1:A5,C1,10,D2:D4)")
+            .unwrap();
+        assert_eq!(value_at(&backend, 0, 4), CellValue::Number(1125.0));
+        backend.set_cell_value(Cell { row: 3, col: 3 }, "5").unwrap();
+        assert_eq!(value_at(&backend, 0, 4), CellValue::Number(1130.0));
+        backend.set_cell_value(Cell { row: 0, col: 2 }, "C2*2").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 2 }, "1").unwrap();
+        assert_eq!(value_at(&backend, 0, 4), CellValue::Number(1032.0));
+
+        let cases = [
+            ("SUM(A1)", CellValue::Number(1.0)),
+            ("MAX(A1:A2,A5*10,3)", CellValue::Number(50.0)),
+            ("MIN(D2:D4,-1)", CellValue::Number(-1.0)),
+            ("AVG(A1:A5,D3,9)", CellValue::Number(4.0)),
+            ("SUM(A1,TRUE)", CellValue::Number(2.0)),
+            ("MEDIAN(A1:A2,A4:A5)", CellValue::Number(3.0)),
+            ("MODE(A1:A5,4,2,4)", CellValue::Number(4.0)),
+            ("COUNT(A1:A5,D2:D4,7,\"x\")", CellValue::Number(8.0)),
+            ("COUNTA(D1:D4,1/0)", CellValue::Number(4.0)),
+            ("COUNTBLANK(D1:D4,F1)", CellValue::Number(2.0)),
+            ("SUM(A1:A2)+MAX(A3,A4)", CellValue::Number(7.0)),
+            ("SUM(A1,\"x\")", CellValue::Error(CellError::TypeMismatch)),
+            ("MIN(A1,1/0)", CellValue::Error(CellError::DivideByZero)),
+        ];
+        for (expression, expected) in cases {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            assert_eq!(backend.evaluate_expression(&function), expected, "{}", expression);
+        }
+
+        assert_eq!(
+            backend.set_cell_value(Cell { row: 5, col: 0 }, "SUM(A1:A5,1,A6)"),
+            Err(ExpressionError::CircularDependency)
+        );
+        backend.set_cell_value(sum, "1").unwrap();
+        assert!(backend.cell_dependents(&Cell { row: 3, col: 3 }).is_empty());
+    }
+
+    #[test]
+    fn test_math_functions() {
+        let mut backend = Backend::new(10, 10);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "-7.25").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "3").unwrap();
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+
+        let number = CellValue::Number;
+        let error = CellValue::Error;
+        let cases = [
+            ("ABS(A1)", number(7.25)),
+            ("SIGN(A1)", number(-1.0)),
+            ("SIGN(0)", number(0.0)),
+            ("SQRT(A2*3)", number(3.0)),
+            ("POWER(A2,3)", number(27.0)),
+            ("A2^2", number(9.0)),
+            ("2^3^2", number(64.0)),
+            ("-2^2", number(4.0)),
+            ("2*A2^2", number(18.0)),
+            ("4^0.5", number(2.0)),
+            ("MOD(7,A2)", number(1.0)),
+            ("MOD(-1,3)", number(2.0)),
+            ("MOD(1,-3)", number(-2.0)),
+            ("ROUND(A1)", number(-7.0)),
+            ("ROUND(A1,1)", number(-7.3)),
+            ("ROUND(1234,-2)", number(1200.0)),
+            ("FLOOR(A1)", number(-8.0)),
+            ("FLOOR(7.25,0.5)", number(7.0)),
+            ("CEILING(7.25,2)", number(8.0)),
+            ("CEILING(A1,-2)", number(-8.0)),
+            ("CEILING(A1,0)", number(0.0)),
+            ("SQRT(A1)", error(CellError::Num)),
+            ("MOD(A2,0)", error(CellError::DivideByZero)),
+            ("0^-1", error(CellError::DivideByZero)),
+            ("POWER(A1,0.5)", error(CellError::Num)),
+            ("10^400", error(CellError::Overflow)),
+            ("FLOOR(A2,0)", error(CellError::DivideByZero)),
+            ("CEILING(A2,-1)", error(CellError::Num)),
+            ("ABS(\"x\")", error(CellError::TypeMismatch)),
+            ("ROUND(1/0,2)", error(CellError::DivideByZero)),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(&backend, expression), expected, "{}", expression);
+        }
+
+        // Math results follow their inputs like any formula
+        backend.set_cell_value(Cell { row: 2, col: 0 }, "ROUND(A2^0.5,3)").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "16").unwrap();
+        assert_eq!(
+            unsafe { (*backend.get_cell_value(2, 0)).value.clone() },
+            CellValue::Number(4.0)
+        );
+    }
+
+    #[test]
+    fn test_text_functions() {
+        let mut backend = Backend::new(10, 10);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "\"  Ada   Lovelace \"").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "\"AB-1234\"").unwrap();
+        backend.set_cell_value(Cell { row: 2, col: 0 }, "2.5").unwrap();
+        backend.set_cell_value(Cell { row: 3, col: 0 }, "TRUE").unwrap();
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+
+        let text = |text: &str| CellValue::Text(text.to_string());
+        let number = CellValue::Number;
+        let error = CellValue::Error;
+        let cases = [
+            ("TRIM(A1)", text("Ada Lovelace")),
+            ("UPPER(TRIM(A1))", text("ADA LOVELACE")),
+            ("LOWER(A2)", text("ab-1234")),
+            ("LEN(A2)", number(7.0)),
+            ("LEN(A3*2)", number(1.0)),
+            ("LEFT(A2,2)", text("AB")),
+            ("LEFT(A2)", text("A")),
+            ("RIGHT(A2,4)", text("1234")),
+            ("RIGHT(A2,20)", text("AB-1234")),
+            ("MID(A2,4,2)", text("12")),
+            ("MID(A2,9,2)", text("")),
+            ("A3&\" kg\"", text("2.5 kg")),
+            ("A4&A5&1/4", text("TRUE0.25")),
+            ("CONCAT(A2:A4,\"!\")", text("AB-12342.5TRUE!")),
+            ("VALUE(RIGHT(A2,4))+1", number(1235.0)),
+            ("VALUE(\" 12.5% \")", number(0.125)),
+            ("VALUE(A3)", number(2.5)),
+            ("TEXT(1234.5,\"#,##0.00\")", text("1,234.50")),
+            ("TEXT(A3/100,\"0.0%\")", text("2.5%")),
+            ("TEXT(-0.004,\"0.##\")", text("0")),
+            ("TEXT(7,\"000\")", text("007")),
+            ("A2+1", error(CellError::TypeMismatch)),
+            ("ABS(A2)", error(CellError::TypeMismatch)),
+            ("VALUE(A2)", error(CellError::TypeMismatch)),
+            ("VALUE(A4)", error(CellError::TypeMismatch)),
+            ("LEFT(A2,-1)", error(CellError::TypeMismatch)),
+            ("MID(A2,0,1)", error(CellError::TypeMismatch)),
+            ("TEXT(A3,\"abc\")", error(CellError::TypeMismatch)),
+            ("TEXT(A2,\"0\")", error(CellError::TypeMismatch)),
+            ("1/0&\"x\"", error(CellError::DivideByZero)),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(&backend, expression), expected, "{}", expression);
+        }
+
+        // Text results are stored in the cell and follow their inputs
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "LEFT(A2,2)&\"/\"&A3").unwrap();
+        backend.set_cell_value(Cell { row: 2, col: 0 }, "7").unwrap();
+        assert_eq!(
+            unsafe { (*backend.get_cell_value(0, 1)).value.clone() },
+            CellValue::Text("AB/7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_text_function_over_one_range() {
+        let mut backend = Backend::new(3, 3);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "\"ab\"").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "\"cd\"").unwrap();
+        // A single range given to a function that is not an aggregate stays a tree
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "CONCAT(A1:B1)").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 1 }, "LEN(CONCAT(A1:B1))").unwrap();
+        unsafe {
+            assert_eq!((*backend.get_cell_value(1, 0)).value, CellValue::Text("abcd".to_string()));
+            assert_eq!((*backend.get_cell_value(1, 1)).value, CellValue::Number(4.0));
+        }
+        assert!(matches!(
+            backend.set_cell_value(Cell { row: 0, col: 0 }, "CONCAT(A1:B1)"),
+            Err(ExpressionError::CircularDependency)
+        ));
+        assert!(matches!(
+            backend.set_cell_value(Cell { row: 2, col: 0 }, "LEN(A1:B1)"),
+            Err(ExpressionError::CouldNotParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_dates() {
+        let mut backend = Backend::new(10, 10);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "2026-01-31").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "2026-10-16").unwrap();
+        backend.set_cell_value(Cell { row: 2, col: 0 }, "2024-02-29 14:30").unwrap();
+        let value_at = |backend: &Backend, row, col| unsafe {
+            (*backend.get_cell_value(row, col)).value.clone()
+        };
+        assert_eq!(value_at(&backend, 1, 0), CellValue::Date(46311.0));
+        assert_eq!(value_at(&backend, 1, 0).to_string(), "2026-10-16");
+        assert_eq!(value_at(&backend, 2, 0).to_string(), "2024-02-29 14:30:00");
+        let value = |backend: &Backend, expression: &str| {
+            let function = backend.parse_expression(expression)
+                .unwrap_or_else(|err| panic!("{}: {}", expression, err));
+            backend.evaluate_expression(&function)
+        };
+
+        let date = |text| CellValue::Date(crate::parser::parse_date(text).unwrap());
+        let number = CellValue::Number;
+        let error = CellValue::Error;
+        let cases = [
+            ("A1+1", date("2026-02-01")),
+            ("A2-A1", number(258.0)),
+            ("A2-7", date("2026-10-09")),
+            ("(A2-A1)*1", number(258.0)),
+            ("A2*1", number(46311.0)),
+            ("MAX(A1:A3)", number(46311.0)),
+            ("IF(A2>A1,A2,A1)", date("2026-10-16")),
+            ("COUNTIF(A1:A3,\">46000\")", number(2.0)),
+            ("\"due \"&A2", CellValue::Text("due 2026-10-16".to_string())),
+            ("DATE(2026,10,16)", date("2026-10-16")),
+            ("DATE(2026,14,1)", date("2027-02-01")),
+            ("DATE(2026,3,0)", date("2026-02-28")),
+            ("DATE(26,1,1)", date("1926-01-01")),
+            ("YEAR(A3)", number(2024.0)),
+            ("MONTH(A3)", number(2.0)),
+            ("DAY(A3)", number(29.0)),
+            ("DAY(A1+1)", number(1.0)),
+            ("DATEDIF(A1,A2,\"d\")", number(258.0)),
+            ("DATEDIF(A1,A2,\"M\")", number(8.0)),
+            ("DATEDIF(A3,A2,\"Y\")", number(2.0)),
+            ("DATEDIF(A3,A2,\"YM\")", number(7.0)),
+            ("DATEDIF(A2,A1,\"D\")", error(CellError::Num)),
+            ("DATEDIF(A1,A2,\"W\")", error(CellError::Num)),
+            ("YEAR(-1)", error(CellError::Num)),
+            ("DATE(10000,1,1)", error(CellError::Num)),
+            ("YEAR(\"x\")", error(CellError::TypeMismatch)),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(value(&backend, expression), expected, "{}", expression);
+        }
+
+        // TODAY and NOW are refreshed on recalculation, with the cells depending on them
+        let CellValue::Date(now) = value(&backend, "NOW()") else {
+            panic!("NOW() is not a date");
+        };
+        assert_eq!(value(&backend, "TODAY()"), CellValue::Date(now.floor()));
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "TODAY()").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 1 }, "B1+1").unwrap();
+        unsafe {
+            (*backend.get_cell_value(0, 1)).value = CellValue::Date(0.0);
+        }
+        backend.recalculate_volatile();
+        assert_eq!(value_at(&backend, 0, 1), CellValue::Date(now.floor()));
+        assert_eq!(value_at(&backend, 1, 1), CellValue::Date(now.floor() + 1.0));
+        assert_eq!(backend.volatile_cells, vec![Cell { row: 0, col: 1 }]);
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "1").unwrap();
+        assert!(backend.volatile_cells.is_empty());
+    }
+
+    #[test]
+    fn test_rand_same_seed_same_numbers() {
+        let mut backend = Backend::new(5, 5);
+        backend.seed_random(42);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "RAND()").unwrap();
+        let first = number_at(&backend, 0, 0);
+        assert!((0.0..1.0).contains(&first));
+        backend.seed_random(42);
+        backend.recalculate_volatile();
+        assert_eq!(number_at(&backend, 0, 0), first);
+        backend.recalculate_volatile();
+        assert_ne!(number_at(&backend, 0, 0), first);
+    }
+
+    #[test]
+    fn test_recalculate_volatile_evaluates_dependents_once() {
+        let mut backend = Backend::new(5, 5);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "RAND()").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "A1*2").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 2 }, "A1+B1").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 3 }, "RANDBETWEEN(1,6)").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 4 }, "D1+C1").unwrap();
+        assert_eq!(
+            backend.volatile_cells,
+            vec![Cell { row: 0, col: 0 }, Cell { row: 0, col: 3 }]
+        );
+        for _ in 0..50 {
+            backend.recalculate_volatile();
+            let dice = number_at(&backend, 0, 3);
+            assert!((1.0..=6.0).contains(&dice) && dice.fract() == 0.0, "{}", dice);
+            assert_eq!(number_at(&backend, 0, 1), number_at(&backend, 0, 0) * 2.0);
+            assert_eq!(number_at(&backend, 0, 2), number_at(&backend, 0, 0) * 3.0);
+            assert_eq!(number_at(&backend, 0, 4), dice + number_at(&backend, 0, 2));
+            for row in backend.grid.get_mut().iter() {
+                assert!(row.iter().all(|cell_data| cell_data.dirty_parents == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_randbetween_bounds() {
+        let backend = Backend::new(5, 5);
+        assert_eq!(evaluate(&backend, "RANDBETWEEN(3,3)"), CellValue::Number(3.0));
+        assert_eq!(evaluate(&backend, "RANDBETWEEN(6,1)"), CellValue::Error(CellError::Num));
+        assert_eq!(
+            evaluate(&backend, "RANDBETWEEN(\"a\",2)"),
+            CellValue::Error(CellError::TypeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_recalculate_all_rebuilds_graph() {
+        let mut backend = Backend::new(5, 5);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "RAND()").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "RANDBETWEEN(1,6)").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 2 }, "A1*3").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "5").unwrap();
+        backend.set_cell_value(Cell { row: 2, col: 0 }, "A2+1").unwrap();
+        backend.set_cell_value(Cell { row: 3, col: 0 }, "SUM(A2:A3)").unwrap();
+        // Only the formulas are left, the edges and values are restored from them
+        unsafe {
+            for row in (*backend.grid.get()).iter_mut() {
+                for cell_data in row.iter_mut() {
+                    cell_data.dependents.clear();
+                    if cell_data.function.type_ != FunctionType::Constant {
+                        cell_data.value = CellValue::Empty;
+                    }
+                }
+            }
+        }
+        backend.volatile_cells.clear();
+        backend.recalculate_all();
+        assert_eq!(value_at(&backend, 2, 0), CellValue::Number(6.0));
+        assert_eq!(value_at(&backend, 3, 0), CellValue::Number(11.0));
+        assert_eq!(number_at(&backend, 0, 2), number_at(&backend, 0, 0) * 3.0);
+        assert_eq!(backend.volatile_cells.len(), 2);
+        backend.set_cell_value(Cell { row: 1, col: 0 }, "7").unwrap();
+        assert_eq!(value_at(&backend, 3, 0), CellValue::Number(15.0));
+    }
+
+    #[test]
+    fn test_formula_string() {
+        let mut backend = Backend::new(5, 5);
+        backend.add_sheet("Costs");
+        let a1 = Cell { row: 0, col: 0 };
+        let costs_a1 = Cell { row: 5, col: 0 };
+        backend.set_cell_value(a1, "costs!a1 * 2").unwrap();
+        backend.set_cell_value(costs_a1, "Sheet1!B1*2").unwrap();
+        assert_eq!(backend.formula_string(a1), "=Costs!A1*2");
+        assert_eq!(backend.formula_string(costs_a1), "=Sheet1!B1*2");
+
+        // Printed from the formula, so a renamed sheet shows its new name
+        backend.rename_sheet("Costs", "Budget");
+        assert_eq!(backend.formula_string(a1), "=Budget!A1*2");
+
+        backend.set_cell_literal(a1, CellValue::Text("total".to_string()));
+        assert_eq!(backend.formula_string(a1), "\"total\"");
+        backend.set_cell_value(a1, "12.5").unwrap();
+        assert_eq!(backend.formula_string(a1), "12.5");
+        assert_eq!(backend.formula_string(Cell { row: 4, col: 4 }), "");
+    }
+
+    #[test]
+    fn test_whole_column_sum() {
+        let mut backend = Backend::new(10, 5);
+        backend.set_cell_value(cell("B1"), "SUM(A:A)").unwrap();
+        backend.set_cell_value(cell("C1"), "B1*2").unwrap();
+        backend.set_cell_value(cell("A1"), "5").unwrap();
+        backend.set_cell_value(cell("A10"), "7").unwrap();
+        assert_eq!(value_in(&backend, "B1"), CellValue::Number(12.0));
+        assert_eq!(value_in(&backend, "C1"), CellValue::Number(24.0));
+        backend.set_cell_value(cell("A3"), "1").unwrap();
+        assert_eq!(value_in(&backend, "C1"), CellValue::Number(26.0));
+    }
+
+    #[test]
+    fn test_whole_row_count() {
+        let mut backend = Backend::new(10, 5);
+        backend.set_cell_value(cell("D1"), "COUNT(3:4)").unwrap();
+        backend.set_cell_value(cell("E4"), "1").unwrap();
+        backend.set_cell_value(cell("A3"), "1").unwrap();
+        backend.set_cell_value(cell("A5"), "1").unwrap();
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(2.0));
+        backend.recalculate_all();
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(2.0));
+    }
+
+    #[test]
+    fn test_whole_column_stored_once() {
+        let mut backend = Backend::new(10, 5);
+        backend.set_cell_value(cell("B1"), "SUM(A:A)").unwrap();
+        backend.set_cell_value(cell("C1"), "B1*2").unwrap();
+        // The column is kept in the range index, not in the dependents of each of its cells
+        let a5 = cell("A5");
+        assert!(unsafe { (*backend.get_cell_value(a5.row, a5.col)).dependents.is_empty() });
+        assert_eq!(backend.cell_dependents(&a5), vec![(0, 1)]);
+
+        // Replacing the formula removes the column
+        backend.set_cell_value(cell("A1"), "5").unwrap();
+        backend.set_cell_value(cell("B1"), "1").unwrap();
+        assert!(backend.cell_dependents(&a5).is_empty());
+        assert_eq!(value_in(&backend, "C1"), CellValue::Number(2.0));
+    }
+
+    #[test]
+    fn test_whole_column_and_row_self_reference() {
+        let mut backend = Backend::new(10, 5);
+        backend.set_cell_value(cell("B1"), "SUM(A:A)").unwrap();
+        backend.set_cell_value(cell("C1"), "B1*2").unwrap();
+        // A formula inside the column or row it reads refers to itself
+        assert_eq!(
+            backend.set_cell_value(cell("A2"), "SUM(A:A)"),
+            Err(ExpressionError::CircularDependency)
+        );
+        assert_eq!(
+            backend.set_cell_value(cell("B3"), "A1+MAX(3:3)"),
+            Err(ExpressionError::CircularDependency)
+        );
+        assert_eq!(
+            backend.set_cell_value(cell("A4"), "C1"),
+            Err(ExpressionError::CircularDependency)
+        );
+    }
+
+    #[test]
+    fn test_range_index() {
+        let mut backend = Backend::new(1000, 100);
+        let sum = Cell { row: 0, col: 99 };
+        backend.set_cell_value(sum, "SUM(A2:CU1000)").unwrap();
+
+        // One edge for the whole range, found from any cell inside it
+        for (row, col) in [(1, 0), (999, 98), (500, 40)] {
+            let cell = Cell { row, col };
+            assert!(unsafe { (*backend.get_cell_value(row, col)).dependents.is_empty() });
+            assert_eq!(backend.cell_dependents(&cell), vec![(0, 99)]);
+        }
+        for (row, col) in [(0, 0), (999, 99)] {
+            assert!(backend.cell_dependents(&Cell { row, col }).is_empty());
+        }
+
+        // Many overlapping ranges, each cell finds exactly the ones holding it
+        for i in 0..50 {
+            let formula = format!("SUM(A{}:B{})+COUNT({}:{})", i + 2, i + 11, i + 2, i + 2);
+            backend.set_cell_value(Cell { row: i, col: 98 }, &formula).unwrap();
+        }
+        for row in 0..70 {
+            let cell = Cell { row, col: 1 };
+            let mut found = backend.cell_dependents(&cell);
+            found.sort();
+            let mut expected: Vec<(i32, i32)> = (0..50)
+                .filter(|&i| i < row && row <= i + 10)
+                .map(|i| (i as i32, 98))
+                .chain((0..50).filter(|&i| i + 1 == row).map(|i| (i as i32, 98)))
+                .collect();
+            if row >= 1 {
+                expected.push((0, 99));
+            }
+            expected.sort();
+            assert_eq!(found, expected, "row {}", row + 1);
+        }
+
+        // Removing the formulas removes their ranges
+        for i in 0..50 {
+            backend.set_cell_value(Cell { row: i, col: 98 }, "0").unwrap();
+        }
+        backend.set_cell_value(sum, "0").unwrap();
+        assert!(backend.cell_dependents(&Cell { row: 5, col: 1 }).is_empty());
+
+        // Editing a cell under a large range still updates the formula reading it
+        backend.set_cell_value(sum, "SUM(A1:A1000)").unwrap();
+        backend.set_cell_value(Cell { row: 998, col: 0 }, "4").unwrap();
+        assert_eq!(
+            unsafe { (*backend.get_cell_value(0, 99)).value.clone() },
+            CellValue::Number(4.0)
+        );
+    }
+
+    #[test]
+    fn test_incremental_aggregates_large_column() {
+        let rows = 20_000;
+        let mut backend = Backend::new(rows, 2);
+        let formulas = ["SUM(A:A)", "MIN(A:A)", "MAX(A:A)", "AVG(A:A)", "COUNT(A:A)"];
+        for (row, formula) in formulas.iter().enumerate() {
+            backend.set_cell_value(Cell { row, col: 1 }, formula).unwrap();
+        }
+        // Every edit updates the aggregates from the column summary
+        for row in 0..rows {
+            backend.set_cell_literal(Cell { row, col: 0 }, CellValue::Number(row as f64 + 1.0));
+        }
+        let results = |backend: &Backend| -> Vec<String> {
+            (0..5).map(|row| value_at(backend, row, 1).to_string()).collect()
+        };
+        assert_eq!(results(&backend), ["200010000", "1", "20000", "10000.5", "20000"]);
+
+        // A cell edited in the middle of the column, then text which is skipped
+        backend.set_cell_value(Cell { row: 9_999, col: 0 }, "-5").unwrap();
+        assert_eq!(results(&backend)[..3], ["199999995", "-5", "20000"]);
+        backend.set_cell_value(Cell { row: 9_999, col: 0 }, "\"text\"").unwrap();
+        assert_eq!(results(&backend)[..3], ["200000000", "1", "20000"]);
+        assert_eq!(results(&backend)[4], "19999");
+    }
+
+    #[test]
+    fn test_incremental_aggregates_argument_lists() {
+        let mut backend = Backend::new(20, 4);
+        backend.set_cell_value(cell("D1"), "SUM(A1:B10, 5)+COUNT(A:A, 1)").unwrap();
+        for row in 0..20 {
+            backend.set_cell_literal(Cell { row, col: 0 }, CellValue::Number(row as f64 + 1.0));
+        }
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(55.0 + 5.0 + 21.0));
+        backend.set_cell_value(cell("B2"), "4").unwrap();
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(85.0));
+    }
+
+    #[test]
+    fn test_incremental_aggregates_first_error() {
+        let mut backend = Backend::new(10, 4);
+        backend.set_cell_value(cell("D1"), "SUM(A:A)").unwrap();
+        backend.set_cell_value(cell("D2"), "SUM(A1:B10)").unwrap();
+        backend.set_cell_value(cell("D3"), "COUNT(A:A)").unwrap();
+        backend.set_cell_value(cell("A1"), "1").unwrap();
+        // The first error in row order is shown, wherever it is in the columns
+        backend.set_cell_value(cell("A3"), "\"x\"*2").unwrap();
+        backend.set_cell_value(cell("B2"), "1/0").unwrap();
+        assert_eq!(value_in(&backend, "D2"), CellValue::Error(CellError::DivideByZero));
+        assert_eq!(value_in(&backend, "D1"), CellValue::Error(CellError::TypeMismatch));
+        // Errors are not numbers, COUNT skips them
+        assert_eq!(value_in(&backend, "D3"), CellValue::Number(1.0));
+        backend.set_cell_value(cell("B2"), "7").unwrap();
+        assert_eq!(value_in(&backend, "D2"), CellValue::Error(CellError::TypeMismatch));
+        backend.set_cell_value(cell("A3"), "3").unwrap();
+        assert_eq!(value_in(&backend, "D2"), CellValue::Number(11.0));
+    }
+
+    #[test]
+    fn test_column_summaries_built_on_use() {
+        let mut backend = Backend::new(10, 4);
+        backend.set_cell_value(cell("A1"), "2").unwrap();
+        assert!(backend.column_summaries.borrow().is_empty());
+        backend.set_cell_value(cell("D1"), "SUM(A1:B2)").unwrap();
+        assert_eq!(backend.column_summaries.borrow().len(), 2);
+        backend.set_cell_value(cell("B2"), "3").unwrap();
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(5.0));
+    }
+
+    #[test]
+    fn test_incremental_aggregates_after_add_sheet() {
+        let rows = 10;
+        let mut backend = Backend::new(rows, 4);
+        backend.set_cell_value(cell("D1"), "SUM(A:A)").unwrap();
+        backend.set_cell_value(cell("A2"), "5").unwrap();
+        // A new sheet rebuilds the summaries for the longer columns
+        assert!(backend.add_sheet("Sheet2"));
+        backend.set_cell_value(cell("A1"), "10").unwrap();
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(15.0));
+        assert!(backend.switch_sheet("Sheet2"));
+        backend.set_cell_value(Cell { row: rows, col: 3 }, "SUM(A:A)").unwrap();
+        backend.set_cell_value(Cell { row: rows + 1, col: 0 }, "4").unwrap();
+        assert_eq!(value_at(&backend, rows, 3), CellValue::Number(4.0));
+        assert_eq!(value_in(&backend, "D1"), CellValue::Number(15.0));
+    }
+
+    #[test]
+    fn test_load_fields_with_header_row() {
+        let csv = "Name,Price,Q1,FY2026,Total\n\
+                   Alice,12.5,TRUE,2026-10-16,=B2*2\n\
+                   \"quoted\",-3,#REF!,1+2,";
+        let mut backend = Backend::new(3, 5);
+        for (row, line) in csv.lines().enumerate() {
+            for (col, field) in line.split(',').enumerate() {
+                backend.load_field(Cell { row, col }, field.trim()).unwrap();
+            }
+        }
+        let shown: Vec<Vec<String>> = (0..3)
+            .map(|row| (0..5).map(|col| backend.formula_string(Cell { row, col })).collect())
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ["\"Name\"", "\"Price\"", "\"Q1\"", "\"FY2026\"", "\"Total\""],
+                ["\"Alice\"", "12.5", "TRUE", "2026-10-16", "=B2*2"],
+                ["\"quoted\"", "-3", "#REF!", "\"1+2\"", ""],
+            ]
+        );
+        unsafe {
+            assert_eq!((*backend.get_cell_value(0, 2)).value, CellValue::Text("Q1".to_string()));
+            assert_eq!((*backend.get_cell_value(1, 4)).value, CellValue::Number(25.0));
+        }
+    }
+
+    #[test]
+    fn test_datedif_end_before_start() {
+        let mut backend = Backend::new(2, 3);
+        backend.set_cell_value(Cell { row: 0, col: 0 }, "2026-10-16").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "2026-01-31").unwrap();
+        backend.set_cell_value(Cell { row: 0, col: 2 }, "DATEDIF(A1,B1,\"D\")").unwrap();
+        backend.set_cell_value(Cell { row: 1, col: 2 }, "DATEDIF(A1,B1,\"M\")").unwrap();
+        assert_eq!(value_at(&backend, 0, 2), CellValue::Error(CellError::Num));
+        assert_eq!(value_at(&backend, 1, 2), CellValue::Error(CellError::Num));
+        assert_eq!(value_at(&backend, 0, 2).to_string(), "#NUM!");
+
+        // The same day is 0, an end moved past the start gives the difference again
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "2026-10-16").unwrap();
+        assert_eq!(value_at(&backend, 0, 2), CellValue::Number(0.0));
+        backend.set_cell_value(Cell { row: 0, col: 1 }, "2026-12-16").unwrap();
+        assert_eq!(value_at(&backend, 0, 2), CellValue::Number(61.0));
+        assert_eq!(value_at(&backend, 1, 2), CellValue::Number(2.0));
+    }
+
+    #[test]
+    fn test_sort_rows_with_formulas() {
+        let mut backend = Backend::new(5, 5);
+        backend.set_cell_value(cell("A1"), "3").unwrap();
+        backend.set_cell_value(cell("B1"), "SUM(D4:D5)").unwrap();
+        backend.set_cell_value(cell("A2"), "1").unwrap();
+        backend.set_cell_value(cell("C2"), "RAND()").unwrap();
+        backend.set_cell_value(cell("A3"), "2").unwrap();
+        backend.define_name("RATE", "2").unwrap();
+        backend.set_cell_value(cell("E3"), "RATE*2").unwrap();
+        backend.set_cell_value(cell("D4"), "4").unwrap();
+        backend.sort("sorta(A1:A3)").unwrap();
+        assert_eq!(value_in(&backend, "A3"), CellValue::Number(3.0));
+        assert_eq!(value_in(&backend, "B3"), CellValue::Number(4.0));
+        // The range formula now lives on the last row and still follows its range
+        backend.set_cell_value(cell("D5"), "5").unwrap();
+        assert_eq!(value_in(&backend, "B3"), CellValue::Number(9.0));
+        assert_eq!(value_in(&backend, "B1"), CellValue::Empty);
+        // The volatile formula moved to the first row and is redrawn there
+        assert_eq!(backend.volatile_cells, vec![cell("C1")]);
+        let first = number_at(&backend, 0, 2);
+        backend.recalculate_volatile();
+        assert_ne!(number_at(&backend, 0, 2), first);
+        // The formula reading the name moved to the second row and follows a new definition
+        backend.define_name("RATE", "5").unwrap();
+        assert_eq!(value_in(&backend, "E2"), CellValue::Number(10.0));
+        assert_eq!(value_in(&backend, "E3"), CellValue::Empty);
+    }
+
+    /// Stores `formula` in cell `from`, copies it to cell `to` and returns the formula there
+    fn relocated(backend: &mut Backend, formula: &str, from: Cell, to: Cell) -> String {
+        backend.set_cell_value(from, formula).unwrap();
+        let function = unsafe { (*backend.get_cell_value(from.row, from.col)).function.clone() };
+        backend.set_relocated_function(from, to, &function).unwrap();
+        backend.formula_string(to)
+    }
+
+    #[test]
+    fn test_relocate_anchored_parts_stay() {
+        let mut backend = Backend::new(10, 10);
+        assert_eq!(
+            relocated(&mut backend, "B2+$B2+B$2+$B$2", cell("A1"), cell("C4")),
+            "=D5+$B5+D$2+$B$2"
+        );
+        assert_eq!(
+            relocated(&mut backend, "SUM($B$2:C3)", cell("A1"), cell("A5")),
+            "=SUM($B$2:C7)"
+        );
+    }
+
+    #[test]
+    fn test_relocate_invalid_references() {
+        let mut backend = Backend::new(10, 10);
+        // Past the edge of the sheet
+        assert_eq!(relocated(&mut backend, "B2+1", cell("A1"), cell("J10")), "=#REF!+1");
+        assert_eq!(relocated(&mut backend, "B2", cell("C3"), cell("A1")), "#REF!");
+        // The anchored corner stays while the other one moves past it
+        assert_eq!(
+            relocated(&mut backend, "SUM($C$3:D5)", cell("A5"), cell("A1")),
+            "=SUM(#REF!)"
+        );
+    }
+
+    #[test]
+    fn test_relocate_whole_columns() {
+        let mut backend = Backend::new(10, 10);
+        assert_eq!(relocated(&mut backend, "SUM(A:A)", cell("B1"), cell("B5")), "=SUM(A:A)");
+        assert_eq!(relocated(&mut backend, "SUM(A:B)", cell("C1"), cell("D5")), "=SUM(B:C)");
+        assert_eq!(relocated(&mut backend, "SUM(2:3)", cell("C1"), cell("D5")), "=SUM(6:7)");
+    }
+
+    #[test]
+    fn test_relocate_onto_another_sheet() {
+        let mut backend = Backend::new(10, 10);
+        assert!(backend.add_sheet("Sheet2"));
+        assert!(backend.add_sheet("Sheet3"));
+        // References to the copied sheet follow the copy, others stay on their sheet
+        // A2 of Sheet2
+        let to = Cell {
+            row: backend.sheet_rows().workbook_row(1, 1),
+            col: 0,
+        };
+        assert_eq!(
+            relocated(&mut backend, "B2+Sheet3!C3", cell("A1"), to),
+            "=B3+Sheet3!C4"
+        );
+        assert_eq!(
+            relocated(&mut backend, "SUM(Sheet1!$B$2:B3)", cell("A1"), to),
+            "=SUM($B$2:B4)"
+        );
+    }
 }
@@ -25,6 +25,8 @@ pub struct Frontend {
     cell_width: usize,
     do_print: bool,
     top_left: Cell,
+    /// Why the last command failed, when it is known (a formula that does not parse)
+    error: Option<String>,
 }
 /// PartialEq implementation for Frontend, used for GUI comparisons.
 #[cfg(feature = "gui")]
@@ -48,8 +50,14 @@ impl Frontend {
             cell_width: 12,
             do_print: true,
             top_left: Cell { row: 0, col: 0 },
+            error: None,
         }
     }
+    /// Returns why the last command failed, such as "unknown function FOO at offset 0",
+    /// None after a success or when there is no reason to give
+    pub fn last_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
     /// Returns mutable access to the backend.
     #[cfg(feature = "gui")]
     pub fn get_backend_mut(&mut self) -> &mut Backend {
@@ -76,6 +84,11 @@ impl Frontend {
         let row_width = min(MAX_WIDTH, self.rows - self.top_left.row);
         let col_width = min(MAX_WIDTH, self.cols - self.top_left.col);
 
+        let sheet_names = self.backend.get_sheet_names();
+        if sheet_names.len() > 1 {
+            println!("[{}]", sheet_names[self.backend.get_active_sheet()]);
+        }
+
         print!("{:<width$}", "", width = self.cell_width);
         for col in self.top_left.col..(self.top_left.col + col_width) {
             print!(
@@ -89,53 +102,27 @@ impl Frontend {
         for row in self.top_left.row..(self.top_left.row + row_width) {
             print!("{:<width$}", row + 1, width = self.cell_width);
             for col in self.top_left.col..(self.top_left.col + col_width) {
+                let cell = self.backend.on_active_sheet(Cell { row, col });
                 unsafe {
-                    let data = self.backend.get_cell_value(row, col);
-
-                    // println!("data.error: {:?}", data.error);
-                    match (*data).error {
-                        CellError::NoError => {
-                            print!("{:<width$}", (*data).value, width = self.cell_width);
-                        }
-                        _ => {
-                            // println!("in printing ERR");
-                            print!("{:<width$}", "ERR", width = self.cell_width);
-                        }
+                    let value = &(*self.backend.get_cell_value(cell.row, cell.col)).value;
+                    let shown = value.to_string();
+                    if value.is_left_aligned() {
+                        // Text is cut to the cell width so the columns stay aligned
+                        let shown: String = shown.chars().take(self.cell_width - 1).collect();
+                        print!("{:<width$}", shown, width = self.cell_width);
+                    } else {
+                        print!("{:>width$} ", shown, width = self.cell_width - 1);
                     }
                 }
             }
             println!();
         }
     }
-    /// Removes extra spaces from a string.
-    #[cfg(feature = "cli")]
-    fn remove_spaces(s: &mut String) {
-        let mut cleaned = String::new();
-        let chars: Vec<char> = s.chars().collect();
-        let mut i = 0;
-        while i < chars.len() {
-            if chars[i].is_whitespace() {
-                if i > 0
-                    && i + 1 < chars.len()
-                    && chars[i - 1].is_alphanumeric()
-                    && chars[i + 1].is_alphanumeric()
-                {
-                    cleaned.push(' ');
-                }
-                while i < chars.len() && chars[i].is_whitespace() {
-                    i += 1;
-                }
-            } else {
-                cleaned.push(chars[i]);
-                i += 1;
-            }
-        }
-        *s = cleaned;
-    }
     /// Handles frontend commands like navigation, some gui extensions and output control.
     /// This function interprets commands entered by the user and performs the corresponding actions.
     /// It supports commands for scrolling, loading, saving, copying, cutting, pasting, and autofilling.
     /// It also includes commands for enabling and disabling output.
+    /// Command names may be written in any case (`RECALC`, `Copy(A1:B2)`).
     /// #Usage:
     /// - `disable_output`: Disables output to the console.
     /// - `enable_output`: Enables output to the console.
@@ -145,8 +132,21 @@ impl Frontend {
     /// - `a`: Scrolls left.
     /// - `d`: Scrolls right.
     /// - `scroll_to <cell>`: Scrolls to a specific cell.
+    /// - `formula <cell>`: Prints the formula of a cell as the formula bar shows it.
+    /// - `define <NAME> = <cell, range or constant>`: Defines a name usable in formulas.
+    /// - `delete_name <NAME>`: Deletes a name.
+    /// - `list_names`: Prints the defined names.
+    /// - `add_sheet <name>`: Adds an empty sheet.
+    /// - `rename_sheet <old> <new>`: Renames a sheet.
+    /// - `switch_sheet <name>`: Shows and edits another sheet.
+    /// - `recalc`: Recalculates the cells using volatile functions (NOW, RAND, ...) and their dependents.
+    /// - `recalc_all`: Rebuilds the dependencies and recalculates every cell.
+    /// - `seed <n>`: Restarts the random numbers of RAND and RANDBETWEEN from a seed.
     fn run_frontend_command(&mut self, cmd: &str) -> bool {
-        match cmd {
+        // Command names are case-insensitive, their arguments (file and sheet names) are kept
+        let name_end = cmd.find([' ', '(']).unwrap_or(cmd.len());
+        let cmd = format!("{}{}", cmd[..name_end].to_ascii_lowercase(), &cmd[name_end..]);
+        match cmd.as_str() {
             "disable_output" => self.do_print = false,
             "enable_output" => self.do_print = true,
             "q" => std::process::exit(0),
@@ -195,6 +195,63 @@ impl Frontend {
                     return false;
                 }
             }
+            cmd if cmd.starts_with("formula ") => {
+                let cell_str = cmd.trim_start_matches("formula ").trim();
+                let (rows, cols) = self.backend.get_rows_col();
+                let Some(cell) = parse_cell_reference(cell_str, rows, cols) else {
+                    return false;
+                };
+                println!("{}", self.backend.formula_string(self.backend.on_active_sheet(cell)));
+            }
+            cmd if cmd.starts_with("define ") => {
+                let Some((name, definition)) = cmd["define ".len()..].split_once('=') else {
+                    return false;
+                };
+                if let Err(err) = self.backend.define_name(name.trim(), definition.trim()) {
+                    self.error = Some(err.to_string());
+                    return false;
+                }
+            }
+            cmd if cmd.starts_with("delete_name ") => {
+                let name = cmd.trim_start_matches("delete_name ").trim();
+                if !self.backend.delete_name(name) {
+                    return false;
+                }
+            }
+            "list_names" => {
+                let home = self.backend.on_active_sheet(Cell { row: 0, col: 0 });
+                let layout = self.backend.sheet_layout(home);
+                for (name, target) in self.backend.get_names() {
+                    println!("{} = {}", name, target.formula_text(&layout));
+                }
+            }
+            cmd if cmd.starts_with("add_sheet ") => {
+                let name = cmd.trim_start_matches("add_sheet ").trim();
+                #[cfg(feature = "gui")]
+                self.backend.push_undo_state();
+                return self.backend.add_sheet(name);
+            }
+            cmd if cmd.starts_with("rename_sheet ") => {
+                let args = cmd.trim_start_matches("rename_sheet ");
+                let Some((old, new)) = args.trim().split_once(' ') else {
+                    return false;
+                };
+                #[cfg(feature = "gui")]
+                self.backend.push_undo_state();
+                return self.backend.rename_sheet(old, new.trim());
+            }
+            cmd if cmd.starts_with("switch_sheet ") => {
+                let name = cmd.trim_start_matches("switch_sheet ").trim();
+                return self.backend.switch_sheet(name);
+            }
+            "recalc" => self.backend.recalculate_volatile(),
+            "recalc_all" => self.backend.recalculate_all(),
+            cmd if cmd.starts_with("seed ") => {
+                let Ok(seed) = cmd.trim_start_matches("seed ").trim().parse() else {
+                    return false;
+                };
+                self.backend.seed_random(seed);
+            }
             #[cfg(feature = "gui")]
             cmd if cmd.starts_with("load(") => {
                 let res = Backend::load_csv(&mut self.backend, cmd, false);
@@ -292,55 +349,36 @@ impl Frontend {
         true
     }
     /// Runs a command entered by the user.
+    /// Whitespace may be used freely between its parts, `A1 = SUM( B1 : B3 ) + 2` is `A1=SUM(B1:B3)+2`.
+    /// On failure `last_error` tells why, when the reason is known.
     pub fn run_command(&mut self, input: &str) -> bool {
-        if input
-            .chars()
-            .next()
-            .map(|c| c.is_ascii_uppercase())
-            .unwrap_or(false)
-        {
-            if let Some(eq_pos) = input.find('=') {
-                //#[cfg(feature = "gui")]
-                //let formula = input[eq_pos..].trim();
-                let (cell_str, expr_str) = input.split_at(eq_pos);
-                let (rows, cols) = self.backend.get_rows_col();
-                #[cfg(feature = "gui")]
-                self.backend.push_undo_state();
-                if let Some(cell) = parse_cell_reference(cell_str, rows, cols) {
-                    #[cfg(feature = "gui")]
-                    let row_num = cell.row;
-                    #[cfg(feature = "gui")]
-                    let col_num = cell.col;
-
-                    let expr = &expr_str[1..]; // skip '='
-
-                    match self.backend.set_cell_value(cell, expr) {
-                        Ok(_) => {
-                            #[cfg(feature = "gui")]
-                            {
-                                self.backend.formula_strings[row_num][col_num] =
-                                    expr_str.to_string();
-                            }
-                            true
-                        }
-                        Err(_) => false,
-                    }
-                } else {
-                    false
-                }
-            } else {
+        self.error = None;
+        // An assignment starts with a cell reference in any case, A1=... or a1=...
+        // The formula is parsed as typed, so the offset of an error points into it
+        let (rows, cols) = self.backend.get_rows_col();
+        let assignment = input.split_once('=').and_then(|(cell_str, expr)| {
+            Some((parse_cell_reference(cell_str.trim(), rows, cols)?, expr.trim()))
+        });
+        let Some((cell, expr)) = assignment else {
+            return self.run_frontend_command(&normalize_command(input));
+        };
+        #[cfg(feature = "gui")]
+        self.backend.push_undo_state();
+        let cell = self.backend.on_active_sheet(cell);
+        match self.backend.set_cell_value(cell, expr) {
+            Ok(()) => true,
+            Err(err) => {
+                self.error = Some(err.to_string());
                 false
             }
-        } else {
-            self.run_frontend_command(input)
         }
     }
     /// Processes a command entered by the user in the command line interface.
+    /// The status is "ok", or "err" followed by the reason when there is one.
     #[cfg(feature = "cli")]
     pub fn process_command(&mut self, input: &str) -> (String, f64) {
         let mut status = "ok".to_string();
         let start = Instant::now();
-        Self::remove_spaces(&mut input.to_string());
         let input = input.trim();
         if input.is_empty() {
             return (status, 0.0);
@@ -348,7 +386,10 @@ impl Frontend {
         if self.run_command(input) {
             status = "ok".to_string();
         } else {
-            status = "err".to_string();
+            status = match self.last_error() {
+                Some(reason) => format!("err: {}", reason),
+                None => "err".to_string(),
+            };
         }
         let time_taken = start.elapsed().as_secs_f64();
         self.print_board();
@@ -467,7 +508,7 @@ mod tests {
 
         unsafe {
             let cell_data = frontend.backend.get_cell_value(0, 0);
-            assert_eq!((*cell_data).value, 42);
+            assert_eq!((*cell_data).value, CellValue::Number(42.0));
         }
     }
 
@@ -541,6 +582,17 @@ mod tests {
         assert_eq!(Frontend::number_to_column_header(701), "ZZ");
     }
 
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(42.0), "42");
+        assert_eq!(format_number(-3.5), "-3.5");
+        assert_eq!(format_number(1.0 / 3.0), "0.333333");
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+        assert_eq!(format_number(-0.0), "0");
+        assert_eq!(format_number(1e20), "1e20");
+        assert_eq!(format_number(1e-9), "1e-9");
+    }
+
     #[test]
     fn test_print_board_no_output() {
         // Lines 77, 79-81
@@ -560,21 +612,6 @@ mod tests {
         frontend.print_board(); // Should print the board with "42" in cell A1
     }
 
-    #[test]
-    fn test_remove_spaces() {
-        let mut input = "  Hello   World  ".to_string();
-        Frontend::remove_spaces(&mut input);
-        assert_eq!(input, "HelloWorld");
-
-        let mut input = "  Rust   Programming  ".to_string();
-        Frontend::remove_spaces(&mut input);
-        assert_eq!(input, "RustProgramming");
-
-        let mut input = "   ".to_string();
-        Frontend::remove_spaces(&mut input);
-        assert_eq!(input, "");
-    }
-
     #[test]
     fn test_run_frontend_command_disable_output() {
         let mut frontend = Frontend::new(5, 5);
@@ -615,7 +652,7 @@ mod tests {
 
         unsafe {
             let cell_data = frontend.backend.get_cell_value(0, 0);
-            assert_eq!((*cell_data).value, 42);
+            assert_eq!((*cell_data).value, CellValue::Number(42.0));
         }
     }
 
@@ -650,4 +687,166 @@ mod tests {
         let result = frontend.run_command("scroll_to InvalidCell");
         assert!(!result);
     }
+
+    #[test]
+    fn test_run_command_names() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("A1=10"));
+        assert!(frontend.run_command("define RATE = A1"));
+        assert!(frontend.run_command("B1=RATE*2"));
+        assert!(frontend.run_command("list_names"));
+        unsafe {
+            let cell_data = frontend.backend.get_cell_value(0, 1);
+            assert_eq!((*cell_data).value, CellValue::Number(20.0));
+        }
+
+        assert!(frontend.run_command("delete_name RATE"));
+        unsafe {
+            let cell_data = frontend.backend.get_cell_value(0, 1);
+            assert_eq!((*cell_data).value, CellValue::Error(CellError::UnknownName));
+        }
+
+        assert!(!frontend.run_command("delete_name RATE"));
+        assert!(!frontend.run_command("define A2 = A1"));
+        assert!(!frontend.run_command("define RATE A1"));
+        assert!(!frontend.run_command("define RATE = A1+1"));
+    }
+
+    #[test]
+    fn test_run_command_sheets() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("A1=10"));
+        assert!(frontend.run_command("add_sheet Costs"));
+        assert!(!frontend.run_command("add_sheet Costs"));
+        assert!(frontend.run_command("switch_sheet Costs"));
+        assert!(frontend.run_command("A1=Sheet1!A1+1"));
+        unsafe {
+            let cell_data = frontend.backend.get_cell_value(5, 0);
+            assert_eq!((*cell_data).value, CellValue::Number(11.0));
+        }
+
+        assert!(frontend.run_command("rename_sheet Sheet1 Main"));
+        assert!(!frontend.run_command("rename_sheet Sheet1 Other"));
+        assert!(!frontend.run_command("rename_sheet Main"));
+        assert!(!frontend.run_command("switch_sheet Sheet1"));
+        assert!(frontend.run_command("switch_sheet Main"));
+        assert!(!frontend.run_command("A1=Costs!A1"));
+        assert!(frontend.run_command("B1=Costs!A1"));
+        unsafe {
+            let cell_data = frontend.backend.get_cell_value(0, 1);
+            assert_eq!((*cell_data).value, CellValue::Number(11.0));
+        }
+    }
+
+    #[test]
+    fn test_run_command_dates() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("A1=2026-10-16"));
+        assert!(frontend.run_command("A2=A1+30"));
+        assert!(frontend.run_command("A3=DATEDIF(A1,A2,\"D\")"));
+        unsafe {
+            let shown = |row| (*frontend.backend.get_cell_value(row, 0)).value.to_string();
+            assert_eq!(shown(0), "2026-10-16");
+            assert_eq!(shown(1), "2026-11-15");
+            assert_eq!(shown(2), "30");
+        }
+    }
+
+    #[test]
+    fn test_run_command_recalc() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("seed 7"));
+        assert!(frontend.run_command("A1=RAND()"));
+        assert!(frontend.run_command("A2=A1*10"));
+        let shown = |frontend: &Frontend, row| unsafe {
+            (*frontend.backend.get_cell_value(row, 0)).value.to_string()
+        };
+        let before = shown(&frontend, 0);
+        assert!(frontend.run_command("B1=1"));
+        assert_eq!(shown(&frontend, 0), before);
+        assert!(frontend.run_command("seed 7"));
+        assert!(frontend.run_command("recalc"));
+        assert_eq!(shown(&frontend, 0), before);
+        assert!(frontend.run_command("recalc"));
+        assert_ne!(shown(&frontend, 0), before);
+        assert!(frontend.run_command("recalc_all"));
+        assert!(!frontend.run_command("seed x"));
+    }
+
+    #[test]
+    fn test_run_command_any_case() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("a1=5"));
+        assert!(frontend.run_command("b1=sum(a1:a2)*2"));
+        assert!(frontend.run_command("DEFINE rate = a1"));
+        assert!(frontend.run_command("c1=Rate+1"));
+        assert!(frontend.run_command("ADD_SHEET Costs"));
+        assert!(!frontend.run_command("add_sheet COSTS"));
+        assert!(frontend.run_command("Switch_Sheet costs"));
+        assert!(frontend.run_command("a1=sheet1!b1"));
+        assert!(frontend.run_command("RECALC"));
+        unsafe {
+            let shown = |row, col| (*frontend.backend.get_cell_value(row, col)).value.to_string();
+            assert_eq!(shown(0, 1), "10");
+            assert_eq!(shown(0, 2), "6");
+            assert_eq!(shown(5, 0), "10");
+        }
+        assert!(frontend.run_command("Delete_Name RATE"));
+        assert!(!frontend.run_command("x1=5"));
+        assert!(!frontend.run_command("Unknown"));
+    }
+
+    #[test]
+    fn test_run_command_whitespace() {
+        let mut frontend = Frontend::new(5, 5);
+        assert!(frontend.run_command("  A1 = 5 "));
+        assert!(frontend.run_command("B1 = SUM( A1 : A2 ) * 2"));
+        assert!(frontend.run_command("define   RATE   =   A1"));
+        assert!(frontend.run_command("C1=RATE + 1"));
+        assert!(frontend.run_command("D1 = \"a  b\" & \" c\""));
+        assert!(frontend.run_command("E1 =  2026-10-16   14:30 "));
+        assert!(frontend.run_command("  recalc  "));
+        assert!(frontend.run_command("scroll_to   A1"));
+        unsafe {
+            let shown = |col| (*frontend.backend.get_cell_value(0, col)).value.to_string();
+            assert_eq!(shown(1), "10");
+            assert_eq!(shown(2), "6");
+            assert_eq!(shown(3), "a  b c");
+            assert_eq!(shown(4), "2026-10-16 14:30:00");
+        }
+        assert!(!frontend.run_command("A 1 = 5"));
+        assert!(!frontend.run_command("A1 = 1 2"));
+    }
+
+    #[test]
+    fn test_process_command_error_reason() {
+        let mut frontend = Frontend::new(5, 5);
+        frontend.process_command("disable_output");
+        let (status, _) = frontend.process_command("A1=FOO(1)");
+        assert_eq!(status, "err: unknown function FOO at offset 0");
+        let (status, _) = frontend.process_command("A1 = B1 +");
+        assert_eq!(status, "err: unexpected end of formula at offset 4");
+        // Offsets count from the start of the formula as typed, spaces included
+        let (status, _) = frontend.process_command("A1 = 1 + FOO(1)");
+        assert_eq!(status, "err: unknown function FOO at offset 4");
+        let (status, _) = frontend.process_command("A1=A1");
+        assert_eq!(status, "err: circular reference");
+        let (status, _) = frontend.process_command("define B2 = A1");
+        assert_eq!(status, "err: invalid name B2 at offset 0");
+        let (status, _) = frontend.process_command("bogus");
+        assert_eq!(status, "err");
+        assert!(frontend.run_command("A1=1"));
+        assert_eq!(frontend.last_error(), None);
+    }
+
+    #[test]
+    fn test_run_command_formula() {
+        let mut frontend = Frontend::new(5, 5);
+        frontend.run_command("disable_output");
+        assert!(frontend.run_command("A1=SUM(B1:B3)"));
+        assert!(frontend.run_command("formula A1"));
+        assert!(frontend.run_command("FORMULA b2"));
+        assert!(!frontend.run_command("formula A99"));
+        assert!(!frontend.run_command("formula"));
+    }
 }
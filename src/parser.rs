@@ -16,7 +16,7 @@ pub fn parse_load_or_save_cmd(expression: &str) -> Option<String> {
 
     Some(file_name.to_string())
 }
-#[cfg(feature = "gui")]
+#[cfg(any(feature = "gui", test))]
 /// Parses a command to sort a range of cells.
 pub fn parse_sort(
     backend: &Backend,
@@ -68,19 +68,24 @@ pub fn parse_sort(
     Err("Invalid command".to_string().into())
 }
 /// Parses a cell reference from a string and returns a Cell struct.
+/// The column letters may be in any case, `b7` is B7.
 pub fn parse_cell_reference(reference: &str, rows: usize, cols: usize) -> Option<Cell> {
     let mut cell = Cell { row: 0, col: 0 };
     let chars: Vec<char> = reference.chars().collect();
     let mut i = 0;
 
     // Must start with a letter
-    if chars.is_empty() || !chars[0].is_ascii_uppercase() {
+    if chars.is_empty() || !chars[0].is_ascii_alphabetic() {
         return None;
     }
 
     // Parse column (letters)
-    while i < chars.len() && chars[i].is_ascii_uppercase() {
-        cell.col = cell.col * 26 + (chars[i] as usize - 'A' as usize + 1);
+    while i < chars.len() && chars[i].is_ascii_alphabetic() {
+        let letter = chars[i].to_ascii_uppercase();
+        cell.col = cell
+            .col
+            .checked_mul(26)?
+            .checked_add(letter as usize - 'A' as usize + 1)?;
         i += 1;
     }
 
@@ -96,8 +101,8 @@ pub fn parse_cell_reference(reference: &str, rows: usize, cols: usize) -> Option
         Err(_) => return None,
     }
 
-    // Convert to 0-based indexing
-    cell.row -= 1;
+    // Convert to 0-based indexing, there is no row 0
+    cell.row = cell.row.checked_sub(1)?;
     cell.col -= 1;
 
     // Check if cell is within grid bounds
@@ -107,130 +112,26 @@ pub fn parse_cell_reference(reference: &str, rows: usize, cols: usize) -> Option
 
     Some(cell)
 }
-/// Parses a binary operation from two operands and returns a BinaryOp struct.
-pub fn parse_binary_op(
-    operand1: &str,
-    operand2: &str,
-    backend: &Backend,
-    success: &mut bool,
-) -> BinaryOp {
-    *success = true;
-    // Operand 1 processing
-    let first = if operand1.chars().next().is_some_and(|c| c.is_ascii_digit()) {
-        // Check if it's an integer
-        let mut value = 0;
-        for c in operand1.chars() {
-            if c.is_ascii_digit() {
-                value = value * 10 + (c as i32 - '0' as i32);
-            } else {
-                *success = false;
-                break;
-            }
-        }
-        Operand {
-            type_: OperandType::Int,
-            data: OperandData::Value(value),
-        }
-    } else {
-        // Assume it's a cell reference
-        match parse_cell_reference(operand1, backend.get_rows_col().0, backend.get_rows_col().1) {
-            Some(cell) => Operand {
-                type_: OperandType::Cell,
-                data: OperandData::Cell(cell),
-            },
-            None => {
-                *success = false;
-                Operand {
-                    type_: OperandType::Int,
-                    data: OperandData::Value(0),
-                }
-            }
-        }
-    };
-
-    // Operand 2 processing
-    let second = if operand2.chars().next().is_some_and(|c| c.is_ascii_digit()) {
-        // Check if it's an integer
-        let mut value = 0;
-        for c in operand2.chars() {
-            if c.is_ascii_digit() {
-                value = value * 10 + (c as i32 - '0' as i32);
-            } else {
-                *success = false;
-                break;
-            }
-        }
-        Operand {
-            type_: OperandType::Int,
-            data: OperandData::Value(value),
-        }
-    } else {
-        // Assume it's a cell reference
-        match parse_cell_reference(operand2, backend.get_rows_col().0, backend.get_rows_col().1) {
-            Some(cell) => Operand {
-                type_: OperandType::Cell,
-                data: OperandData::Cell(cell),
-            },
-            None => {
-                *success = false;
-                Operand {
-                    type_: OperandType::Int,
-                    data: OperandData::Value(0),
-                }
-            }
-        }
-    };
-
-    BinaryOp { first, second }
-}
-/// Parses a range function (MIN, MAX, AVG, SUM, STDEV) from a string and returns a Function struct.
-fn parse_range_function(
-    expression: &str,
-    function_type: FunctionType,
-    backend: &Backend,
-) -> (Function, bool) {
-    let start_pos = match function_type {
-        FunctionType::Stdev => 6, // "STDEV("
-        _ => 4,                   // "MIN(", "MAX(", "AVG(", "SUM("
-    };
-
-    let content = &expression[start_pos..];
-    let end_pos = match content.find(')') {
-        Some(pos) => pos,
-        None => return (Function::new_constant(0), false),
-    };
-    let range_str = &content[..end_pos];
-
-    if let Some(separator_pos) = range_str.find(':') {
-        let top_left_str = &range_str[..separator_pos];
-        let bottom_right_str = &range_str[separator_pos + 1..];
-
-        let top_left =
-            match parse_cell_reference(top_left_str, backend.get_rows(), backend.get_cols()) {
-                Some(cell) => cell,
-                None => return (Function::new_constant(0), false),
-            };
-        let bottom_right =
-            match parse_cell_reference(bottom_right_str, backend.get_rows(), backend.get_cols()) {
-                Some(cell) => cell,
-                None => return (Function::new_constant(0), false),
-            };
-
-        // Check if range is valid (top_left <= bottom_right)
-        if top_left.row > bottom_right.row || top_left.col > bottom_right.col {
-            return (Function::new_constant(0), false);
-        }
-
-        let range = RangeFunction {
-            top_left,
-            bottom_right,
-        };
-
-        return (Function::new_range_function(function_type, range), true);
+/// Parses a cell reference that may carry `$` anchors (`$A$1`, `A$1`, `$A1`) and returns
+/// the cell together with its anchoring.
+pub fn parse_anchored_reference(reference: &str, rows: usize, cols: usize) -> Option<(Cell, Anchor)> {
+    let anchor_col = reference.starts_with('$');
+    let rest = reference.strip_prefix('$').unwrap_or(reference);
+    let digits_start = rest.find(|c: char| !c.is_ascii_alphabetic())?;
+    if digits_start == 0 {
+        return None;
     }
-
-    // Default return if parsing fails
-    (Function::new_constant(0), false)
+    let (letters, digits) = rest.split_at(digits_start);
+    let anchor_row = digits.starts_with('$');
+    let digits = digits.strip_prefix('$').unwrap_or(digits);
+    let cell = parse_cell_reference(&format!("{}{}", letters, digits), rows, cols)?;
+    Some((
+        cell,
+        Anchor {
+            row: anchor_row,
+            col: anchor_col,
+        },
+    ))
 }
 #[cfg(feature = "gui")]
 /// Parses an autofill command from a string and returns the start, end, and destination cells.
@@ -341,306 +242,1602 @@ pub fn parse_paste(
     }
     // println!("Parsed cell: {:?}", cell);
 }
-/// Parses a function from a string and returns a Function struct.
-pub fn parse_expression(expression: &str, backend: &Backend) -> (Function, bool) {
-    let mut success = false;
-    // Check if it's possible to be a parenthesis function (>=4 is the size)
-    // println!("{}", expression.len());
-    if expression.is_empty() {
-        success = false;
-        return (Function::new_constant(0), success);
-    }
-    if expression.len() >= 4 {
-        // Check for range functions
-        if expression.starts_with("MIN(") {
-            return parse_range_function(expression, FunctionType::Min, backend);
-        } else if expression.starts_with("MAX(") {
-            return parse_range_function(expression, FunctionType::Max, backend);
-        } else if expression.starts_with("AVG(") {
-            return parse_range_function(expression, FunctionType::Avg, backend);
-        } else if expression.starts_with("SUM(") {
-            return parse_range_function(expression, FunctionType::Sum, backend);
-        } else if expression.starts_with("STDEV(") {
-            return parse_range_function(expression, FunctionType::Stdev, backend);
-        } else if let Some(content) = expression.strip_prefix("SLEEP(") {
-            // Parse sleep function
-            // println!("content: {:?}", content);
-            let end_pos = match content.find(')') {
-                Some(pos) => pos,
-                None => return (Function::new_constant(0), false),
-            };
-            // println!("end_pos: {:?}", end_pos);
-            let value_str = &content[..end_pos];
-            // println!("value_str: {:?}", value_str);
-            if value_str
-                .chars()
-                .next()
-                .is_some_and(|c| c.is_ascii_digit() || c == '-')
-            {
-                match value_str.parse::<i32>() {
-                    Ok(value) => return (Function::new_sleep(value), true),
-                    Err(_) => return (Function::new_constant(0), false),
-                }
-            } else {
-                let cell =
-                    match parse_cell_reference(value_str, backend.get_rows(), backend.get_cols()) {
-                        Some(cell) => cell,
-                        None => return (Function::new_constant(0), false),
-                    };
+/// Token represents a single lexical unit of a formula.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Numeric literal such as 42, 3.14 or 1e-3
+    Number(f64),
+    /// Quoted string literal, `""` inside the quotes stands for one `"`
+    Text(String),
+    /// Error literal such as #DIV/0!
+    Error(CellError),
+    /// Cell reference (A1, $B$2), function name (SUM), defined name (TAX_RATE) or boolean
+    /// (TRUE), in upper case
+    Ident(String),
+    /// Sheet name in front of a reference (`Sheet2!` in Sheet2!A1), without the `!`
+    Sheet(String),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Ampersand,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LParen,
+    RParen,
+    Colon,
+    Comma,
+}
+/// Byte range `start..end` of a token in the formula.
+type Span = (usize, usize);
+/// Splits a formula into tokens and the byte range each one covers, fails on an unexpected
+/// character, a malformed or overflowing number, an unknown error code or an unterminated
+/// string.
+/// Whitespace between tokens is skipped, so `SUM( A1 : A3 ) + 2` is `SUM(A1:A3)+2`.
+fn tokenize(expression: &str) -> Result<(Vec<Token>, Vec<Span>), ParseError> {
+    let chars: Vec<char> = expression.chars().collect();
+    // Byte offset of every char and of the end of the formula
+    let offsets: Vec<usize> = expression
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(expression.len()))
+        .collect();
+    let error = |start: usize, end: usize, reason: &str| {
+        let token = &expression[offsets[start]..offsets[end]];
+        ParseError {
+            offset: offsets[start],
+            token: token.to_string(),
+            reason: format!("{} {}", reason, token),
+        }
+    };
+    // End of the word starting at `start`, for the text of a malformed token
+    let word_end = |start: usize| {
+        (start + 1..chars.len())
+            .find(|&i| !(chars[i].is_ascii_alphanumeric() || "./!?".contains(chars[i])))
+            .unwrap_or(chars.len())
+    };
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut i = 0;
 
-                //let val = backend.get_cell_value(cell.row, cell.col);
-                return (Function::new_sleep_cell(cell), true);
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit() || c == '.' {
+            i = scan_number(&chars, i)
+                .ok_or_else(|| error(start, word_end(start), "malformed number"))?;
+            let literal: String = chars[start..i].iter().collect();
+            // Literals too large for an f64 (1e400) are rejected instead of becoming infinite
+            match literal.parse::<f64>() {
+                Ok(value) if value.is_finite() => Token::Number(value),
+                _ => return Err(error(start, i, "number too large")),
             }
-        }
+        } else if c == '"' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') if chars.get(i + 1) == Some(&'"') => {
+                        text.push('"');
+                        i += 2;
+                    }
+                    Some('"') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(other) => {
+                        text.push(*other);
+                        i += 1;
+                    }
+                    None => return Err(error(start, i, "unterminated text")),
+                }
+            }
+            Token::Text(text)
+        } else if c == '#' {
+            let rest: String = chars[i..].iter().collect();
+            let code = CellError::from_code_prefix(&rest.to_ascii_uppercase())
+                .ok_or_else(|| error(start, word_end(start), "unknown error"))?;
+            i += code.code().chars().count();
+            Token::Error(code)
+        } else if let Some(end) = scan_sheet_prefix(&chars, i) {
+            i = end + 1;
+            Token::Sheet(chars[start..end].iter().collect())
+        } else if c.is_ascii_alphabetic() || c == '$' || c == '_' {
+            // Cell references (A1, ZZ999, anchored as $A1, A$1, $A$1), function names
+            // (SUM, STDEV.S) and defined names (TAX_RATE, Q1_SALES), the parser tells them apart.
+            // They are case-insensitive and kept in upper case, so sum(a1:a3) is SUM(A1:A3)
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '$'
+                    || chars[i] == '_'
+                    || chars[i] == '.')
+            {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            Token::Ident(ident.to_ascii_uppercase())
+        } else if c == '<' || c == '>' {
+            // One or two character comparison operators: < <= <> > >=
+            let token = match (c, chars.get(i + 1)) {
+                ('<', Some('=')) => Token::LessEqual,
+                ('<', Some('>')) => Token::NotEqual,
+                ('>', Some('=')) => Token::GreaterEqual,
+                ('<', _) => Token::Less,
+                _ => Token::Greater,
+            };
+            i += match token {
+                Token::Less | Token::Greater => 1,
+                _ => 2,
+            };
+            token
+        } else {
+            let token = match c {
+                '+' => Token::Plus,
+                '-' => Token::Minus,
+                '*' => Token::Star,
+                '/' => Token::Slash,
+                '^' => Token::Caret,
+                '&' => Token::Ampersand,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                ':' => Token::Colon,
+                ',' => Token::Comma,
+                '=' => Token::Equal,
+                _ => return Err(error(start, start + 1, "unexpected character")),
+            };
+            i += 1;
+            token
+        };
+        tokens.push(token);
+        spans.push((offsets[start], offsets[i]));
     }
 
-    // Check for binary operations
-    let mut pos = None;
-    for (i, c) in expression.chars().enumerate() {
-        if (c == '+' || c == '-' || c == '*' || c == '/') && i != 0 {
-            pos = Some(i);
-            break;
+    Ok((tokens, spans))
+}
+/// Removes the whitespace around the punctuation of a command and at its ends, so
+/// ` A1 = SUM( B1 : B3 ) ` is `A1=SUM(B1:B3)` and `copy( A1 : B2 )` is `copy(A1:B2)`.
+/// Whitespace between two words (`define TAX_RATE`, `2026-10-16 14:30`) is kept as one
+/// space and quoted text is kept as it is.
+pub fn normalize_command(input: &str) -> String {
+    let is_punctuation = |c: char| "()=<>:,!+-*/^&".contains(c);
+    let mut normalized = String::with_capacity(input.len());
+    let mut in_text = false;
+    let mut space = false;
+    for c in input.trim().chars() {
+        if in_text {
+            in_text = c != '"';
+            normalized.push(c);
+            continue;
         }
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        let after_word = normalized.chars().last().is_some_and(|last| !is_punctuation(last));
+        if space && after_word && !is_punctuation(c) {
+            normalized.push(' ');
+        }
+        space = false;
+        in_text = c == '"';
+        normalized.push(c);
+    }
+    normalized
+}
+/// Returns the position of the `!` when a sheet name followed by `!` starts at `start`.
+fn scan_sheet_prefix(chars: &[char], start: usize) -> Option<usize> {
+    if !chars[start].is_ascii_alphabetic() {
+        return None;
     }
+    let end = (start..chars.len())
+        .find(|&i| !(chars[i].is_ascii_alphanumeric() || chars[i] == '_'))?;
+    (chars[end] == '!').then_some(end)
+}
+/// Returns the end of the numeric literal starting at `start`: digits, an optional
+/// fraction and an optional exponent (`12`, `3.14`, `.5`, `1e-3`, `2.5E+4`).
+fn scan_number(chars: &[char], start: usize) -> Option<usize> {
+    let digits_from = |mut i: usize| {
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
 
-    if let Some(i) = pos {
-        // This is a binary operation
-        let operator = match expression.chars().nth(i) {
-            Some(op) => op,
-            None => return (Function::new_constant(0), false),
-        };
-        let operand1 = &expression[..i];
-        let operand2 = &expression[i + 1..];
+    let mut i = digits_from(start);
+    let mut has_digits = i > start;
+    if i < chars.len() && chars[i] == '.' {
+        let fraction_start = i + 1;
+        i = digits_from(fraction_start);
+        has_digits |= i > fraction_start;
+    }
+    if !has_digits {
+        return None;
+    }
 
-        let binary_op = parse_binary_op(operand1, operand2, backend, &mut success);
-        if !success {
-            return (Function::new_constant(0), false);
+    if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+        let mut exponent_start = i + 1;
+        if exponent_start < chars.len() && (chars[exponent_start] == '+' || chars[exponent_start] == '-')
+        {
+            exponent_start += 1;
         }
+        let end = digits_from(exponent_start);
+        if end == exponent_start {
+            return None;
+        }
+        i = end;
+    }
+    Some(i)
+}
+/// One end of a range as written: a cell, a column or a row, with its anchoring
+enum RangeEnd {
+    Cell(Cell, Anchor),
+    Column(usize, bool),
+    Row(usize, bool),
+}
+/// Recursive-descent parser building an expression tree from the tokens of one formula.
+///
+/// Grammar, from lowest to highest precedence:
+/// - `compare := concat (('=' | '<>' | '<' | '<=' | '>' | '>=') concat)*`
+/// - `concat  := expr ('&' expr)*`
+/// - `expr    := term (('+' | '-') term)*`
+/// - `term    := power (('*' | '/') power)*`
+/// - `power   := unary ('^' unary)*`, left associative and below a leading minus like in
+///   spreadsheets, so `2^3^2` is 64 and `-2^2` is 4
+/// - `unary   := ('-' | '+') unary | primary`
+/// - `primary := NUMBER | STRING | ERROR | TRUE | FALSE | [SHEET '!'] CELL | NAME | FUNCTION '(' args ')' | '(' compare ')'`
+/// - `args    := arg (',' arg)*`, aggregates take any number of arguments, other functions
+///   take ranges or values at fixed positions
+/// - `arg     := range | compare`
+/// - `range   := [SHEET '!'] CELL ':' CELL | NAME`
+///
+/// References without a sheet name are on the active sheet of the backend.
+/// Errors point at the offending token by its byte offset in the formula.
+struct FormulaParser<'a> {
+    tokens: Vec<Token>,
+    /// Byte range of each token in `source`
+    spans: Vec<Span>,
+    source: &'a str,
+    pos: usize,
+    backend: &'a Backend,
+}
 
-        let function_type = match operator {
-            '+' => FunctionType::Plus,
-            '-' => FunctionType::Minus,
-            '*' => FunctionType::Multiply,
-            '/' => FunctionType::Divide,
-            _ => return (Function::new_constant(0), false),
-        };
-        success = true;
-        (Function::new_binary_op(function_type, binary_op), success)
-    } else {
-        // Not a binary op, could be a constant or a cell reference
-
-        if match expression.chars().next() {
-            Some(c) => c.is_ascii_digit() || c == '-',
-            None => false,
-        } {
-            // First char is a number or a minus sign, it's a constant
-            match expression.parse::<i32>() {
-                Ok(value) => (Function::new_constant(value), true),
-                Err(_) => (Function::new_constant(0), false),
+impl<'a> FormulaParser<'a> {
+    /// Splits a formula into tokens, ready to be parsed
+    fn new(source: &'a str, backend: &'a Backend) -> Result<Self, ParseError> {
+        let (tokens, spans) = tokenize(source)?;
+        Ok(FormulaParser {
+            tokens,
+            spans,
+            source,
+            pos: 0,
+            backend,
+        })
+    }
+    /// Returns an error about the token at `index`, or about the end of the formula
+    fn error_at(&self, index: usize, reason: String) -> ParseError {
+        match self.spans.get(index) {
+            Some(&(start, end)) => ParseError {
+                offset: start,
+                token: self.source[start..end].to_string(),
+                reason,
+            },
+            None => ParseError {
+                offset: self.source.len(),
+                token: String::new(),
+                reason,
+            },
+        }
+    }
+    /// Returns an error about the current token
+    fn error(&self, reason: String) -> ParseError {
+        self.error_at(self.pos, reason)
+    }
+    /// Returns the error for a token that does not fit where it is
+    fn unexpected(&self, index: usize) -> ParseError {
+        match self.spans.get(index) {
+            Some(&(start, end)) => {
+                self.error_at(index, format!("unexpected {}", &self.source[start..end]))
             }
-        } else {
-            // Parse as cell reference
-            let cell =
-                match parse_cell_reference(expression, backend.get_rows(), backend.get_cols()) {
-                    Some(cell) => cell,
-                    None => return (Function::new_constant(0), false),
-                };
-            let operand1 = Operand {
-                type_: OperandType::Cell,
-                data: OperandData::Cell(cell),
-            };
-            let operand2 = Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(0),
+            None => self.error_at(index, "unexpected end of formula".to_string()),
+        }
+    }
+    /// Returns the current token without consuming it
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    /// Consumes and returns the current token
+    fn next(&mut self) -> Result<Token, ParseError> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.unexpected(self.pos))?;
+        self.pos += 1;
+        Ok(token)
+    }
+    /// Consumes the current token if it is equal to `expected`
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        if self.peek() == Some(&expected) {
+            self.pos += 1;
+            return Ok(());
+        }
+        let symbol = match expected {
+            Token::RParen => ")",
+            Token::Comma => ",",
+            _ => ":",
+        };
+        Err(self.error(format!("expected {}", symbol)))
+    }
+    /// Parses a whole formula, all tokens must be consumed
+    fn parse(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_compare()?;
+        if self.pos != self.tokens.len() {
+            return Err(self.unexpected(self.pos));
+        }
+        Ok(expr)
+    }
+    /// Parses comparisons (lowest precedence, left associative)
+    fn parse_compare(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let function_type = match self.peek() {
+                Some(Token::Equal) => FunctionType::Equal,
+                Some(Token::NotEqual) => FunctionType::NotEqual,
+                Some(Token::Less) => FunctionType::Less,
+                Some(Token::LessEqual) => FunctionType::LessEqual,
+                Some(Token::Greater) => FunctionType::Greater,
+                Some(Token::GreaterEqual) => FunctionType::GreaterEqual,
+                _ => return Ok(left),
             };
-            let binary_op = BinaryOp {
-                first: operand1,
-                second: operand2,
+            self.pos += 1;
+            let right = self.parse_concat()?;
+            left = Expr::BinaryOp(function_type, Box::new(left), Box::new(right));
+        }
+    }
+    /// Parses text concatenations (left associative)
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_expr()?;
+        while self.peek() == Some(&Token::Ampersand) {
+            self.pos += 1;
+            let right = self.parse_expr()?;
+            left = Expr::BinaryOp(FunctionType::Concatenate, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+    /// Parses additions and subtractions (left associative)
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_term()?;
+        loop {
+            let function_type = match self.peek() {
+                Some(Token::Plus) => FunctionType::Plus,
+                Some(Token::Minus) => FunctionType::Minus,
+                _ => return Ok(left),
             };
-            success = true;
-            (
-                Function::new_binary_op(FunctionType::Plus, binary_op),
-                success,
-            )
+            self.pos += 1;
+            let right = self.parse_term()?;
+            left = Expr::BinaryOp(function_type, Box::new(left), Box::new(right));
         }
     }
-}
-#[cfg(feature = "cli")]
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::backend::Backend;
-    use crate::structs::{
-        Cell, Function, FunctionType, Operand, OperandData, OperandType, RangeFunction,
-    };
-
-    #[test]
-    fn test_parse_cell_reference_valid() {
-        let rows = 10;
-        let cols = 26;
-
-        assert_eq!(
-            parse_cell_reference("A1", rows, cols),
-            Some(Cell { row: 0, col: 0 })
-        );
-        assert_eq!(
-            parse_cell_reference("B2", rows, cols),
-            Some(Cell { row: 1, col: 1 })
-        );
-        assert_eq!(
-            parse_cell_reference("Z10", rows, cols),
-            Some(Cell { row: 9, col: 25 })
-        );
+    /// Parses multiplications and divisions (left associative)
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_power()?;
+        loop {
+            let function_type = match self.peek() {
+                Some(Token::Star) => FunctionType::Multiply,
+                Some(Token::Slash) => FunctionType::Divide,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_power()?;
+            left = Expr::BinaryOp(function_type, Box::new(left), Box::new(right));
+        }
     }
-
-    #[test]
-    fn test_parse_cell_reference_invalid() {
-        let rows = 10;
-        let cols = 10;
-
-        assert_eq!(parse_cell_reference("1A", rows, cols), None); // Invalid format
-        assert_eq!(parse_cell_reference("AA", rows, cols), None); // Missing row
-        assert_eq!(parse_cell_reference("A11", rows, cols), None); // Out of bounds
-        assert_eq!(parse_cell_reference("", rows, cols), None); // Empty string
+    /// Parses exponentiation (left associative)
+    fn parse_power(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::Caret) {
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Expr::BinaryOp(FunctionType::Exponent, Box::new(left), Box::new(right));
+        }
+        Ok(left)
     }
-
-    #[test]
-    fn test_parse_binary_op_valid() {
-        let backend = Backend::new(10, 10);
-        let mut success = false;
-
-        let binary_op = parse_binary_op("A1", "42", &backend, &mut success);
-        assert!(success);
-        assert_eq!(
-            binary_op.first,
-            Operand {
-                type_: OperandType::Cell,
-                data: OperandData::Cell(Cell { row: 0, col: 0 }),
+    /// Parses unary minus and plus
+    /// A sign in front of a number is folded into the literal, so `A1*-2` is still a
+    /// plain binary operation on a cell and a constant.
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.pos += 1;
+                match self.parse_unary()? {
+                    Expr::Value(value) => Ok(Expr::Value(-value)),
+                    inner => Ok(Expr::Negate(Box::new(inner))),
+                }
             }
-        );
-        assert_eq!(
-            binary_op.second,
-            Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(42),
+            Some(Token::Plus) => {
+                self.pos += 1;
+                self.parse_unary()
             }
-        );
-
-        let binary_op = parse_binary_op("10", "20", &backend, &mut success);
-        assert!(success);
-        assert_eq!(
-            binary_op.first,
-            Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(10),
+            _ => self.parse_primary(),
+        }
+    }
+    /// Parses literals, cell references, function calls and parenthesised expressions
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        match self.next()? {
+            Token::Number(value) => Ok(Expr::Value(value)),
+            Token::Text(text) => Ok(Expr::Text(text)),
+            Token::Error(error) => Ok(Expr::Error(error)),
+            Token::Sheet(sheet) => {
+                let sheet = self.sheet_at(start, &sheet)?;
+                match self.next()? {
+                    Token::Ident(name) => {
+                        let (cell, anchor) = self.parse_cell_on(sheet, &name)?;
+                        Ok(Expr::Cell(cell, anchor))
+                    }
+                    _ => Err(self.error_at(start + 1, "expected a cell reference".to_string())),
+                }
             }
-        );
-        assert_eq!(
-            binary_op.second,
-            Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(20),
+            Token::LParen => {
+                let expr = self.parse_compare()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
             }
-        );
+            Token::Ident(name) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    let call = self.parse_call(&name, start)?;
+                    self.expect(Token::RParen)?;
+                    Ok(call)
+                } else {
+                    match name.as_str() {
+                        "TRUE" => Ok(Expr::Bool(true)),
+                        "FALSE" => Ok(Expr::Bool(false)),
+                        _ if is_reference(&name) => {
+                            let (cell, anchor) = self.parse_cell(&name)?;
+                            Ok(Expr::Cell(cell, anchor))
+                        }
+                        _ => self.parse_name(name),
+                    }
+                }
+            }
+            _ => Err(self.unexpected(start)),
+        }
     }
-
-    #[test]
-    fn test_parse_binary_op_invalid() {
-        let backend = Backend::new(10, 10);
-        let mut success = false;
-
-        let binary_op = parse_binary_op("Invalid", "42", &backend, &mut success);
-        assert!(!success);
-        assert_eq!(
-            binary_op.first,
-            Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(0),
+    /// Parses the arguments of a function call, the opening parenthesis is already consumed
+    /// and the name is the token at `name_at`
+    fn parse_call(&mut self, name: &str, name_at: usize) -> Result<Expr, ParseError> {
+        let function_type = match name {
+            "MIN" => FunctionType::Min,
+            "MAX" => FunctionType::Max,
+            "AVG" => FunctionType::Avg,
+            "SUM" => FunctionType::Sum,
+            "STDEV" => FunctionType::Stdev,
+            "COUNT" => FunctionType::Count,
+            "COUNTA" => FunctionType::CountA,
+            "COUNTBLANK" => FunctionType::CountBlank,
+            "MEDIAN" => FunctionType::Median,
+            "MODE" => FunctionType::Mode,
+            "VAR.P" => FunctionType::VarP,
+            "VAR.S" => FunctionType::VarS,
+            "STDEV.P" => FunctionType::StdevP,
+            "STDEV.S" => FunctionType::StdevS,
+            "SLEEP" => return Ok(Expr::Call(FunctionType::Sleep, vec![self.parse_expr()?])),
+            "COUNTIF" | "SUMIF" | "AVERAGEIF" => return self.parse_conditional_call(name),
+            "PERCENTILE" | "QUARTILE" | "LARGE" | "SMALL" | "RANK" => {
+                return self.parse_order_call(name)
             }
-        );
-
-        let binary_op = parse_binary_op("A1", "Invalid", &backend, &mut success);
-        assert!(!success);
-        assert_eq!(
-            binary_op.second,
-            Operand {
-                type_: OperandType::Int,
-                data: OperandData::Value(0),
+            "VLOOKUP" | "HLOOKUP" | "INDEX" | "MATCH" => return self.parse_lookup_call(name),
+            "ABS" | "MOD" | "POWER" | "SQRT" | "ROUND" | "FLOOR" | "CEILING" | "SIGN" | "RAND"
+            | "RANDBETWEEN" => return self.parse_math_call(name),
+            "CONCAT" => return self.parse_concat_call(),
+            "TODAY" | "NOW" | "DATE" | "YEAR" | "MONTH" | "DAY" | "DATEDIF" => {
+                return self.parse_date_call(name)
             }
-        );
+            "LEN" | "LEFT" | "RIGHT" | "MID" | "UPPER" | "LOWER" | "TRIM" | "TEXT" | "VALUE" => {
+                return self.parse_text_call(name)
+            }
+            "IF" | "AND" | "OR" | "NOT" | "IFERROR" => return self.parse_logical_call(name),
+            _ => return Err(self.error_at(name_at, format!("unknown function {}", name))),
+        };
+        let mut args = vec![self.parse_aggregate_arg()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.parse_aggregate_arg()?);
+        }
+        Ok(Expr::Call(function_type, args))
     }
-
-    #[test]
-    fn test_parse_range_function_valid() {
-        let backend = Backend::new(10, 10);
-
-        let (function, success) = parse_range_function("SUM(A1:B2)", FunctionType::Sum, &backend);
-        assert!(success);
-        assert_eq!(
-            function.data,
-            Function::new_range_function(
-                FunctionType::Sum,
-                RangeFunction {
-                    top_left: Cell { row: 0, col: 0 },
-                    bottom_right: Cell { row: 1, col: 1 },
-                }
-            )
-            .data
-        );
-
-        let (function, success) = parse_range_function("AVG(A1:A10)", FunctionType::Avg, &backend);
-        assert!(success);
-        assert_eq!(
-            function.data,
-            Function::new_range_function(
-                FunctionType::Avg,
-                RangeFunction {
-                    top_left: Cell { row: 0, col: 0 },
-                    bottom_right: Cell { row: 9, col: 0 },
-                }
-            )
-            .data
-        );
+    /// Parses an argument of an aggregate or of CONCAT, a range or any value such as a cell, a number
+    /// or a nested expression
+    fn parse_aggregate_arg(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        if let Some(range) = self.parse_range()? {
+            if matches!(self.peek(), Some(Token::RParen | Token::Comma)) {
+                return Ok(Expr::Range(range));
+            }
+        }
+        self.pos = start;
+        self.parse_compare()
     }
-
-    #[test]
-    fn test_parse_range_function_invalid() {
-        let backend = Backend::new(10, 10);
-
-        let (function, success) =
-            parse_range_function("SUM(A1:Invalid)", FunctionType::Sum, &backend);
-        assert!(!success);
-        assert_eq!(function.data, Function::new_constant(0).data);
-
-        let (function, success) = parse_range_function("SUM(A1:A11)", FunctionType::Sum, &backend);
-        assert!(!success);
-        assert_eq!(function.data, Function::new_constant(0).data);
-
-        let (function, success) =
-            parse_range_function("SUM(A1:B1:C1)", FunctionType::Sum, &backend);
-        assert!(!success);
-        assert_eq!(function.data, Function::new_constant(0).data);
+    /// Parses a range argument, a defined name can stand for the range
+    fn parse_range_arg(&mut self) -> Result<Expr, ParseError> {
+        if let (Some(Token::Ident(name)), Some(Token::RParen | Token::Comma)) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            if is_valid_name(name) {
+                let name = name.clone();
+                self.pos += 1;
+                return self.parse_name(name);
+            }
+        }
+        match self.parse_range()? {
+            Some(range) => Ok(Expr::Range(range)),
+            None => Err(self.error("expected a range".to_string())),
+        }
     }
+    /// Parses the arguments of COUNTIF(range, criterion) and of SUMIF and AVERAGEIF,
+    /// which take an optional third range holding the values to add up
+    fn parse_conditional_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let function_type = match name {
+            "COUNTIF" => FunctionType::CountIf,
+            "SUMIF" => FunctionType::SumIf,
+            _ => FunctionType::AverageIf,
+        };
+        let mut args = vec![self.parse_range_arg()?];
+        self.expect(Token::Comma)?;
+        args.push(self.parse_compare()?);
+        if function_type != FunctionType::CountIf && self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.parse_range_arg()?);
+        }
+        Ok(Expr::Call(function_type, args))
+    }
+    /// Parses the arguments of PERCENTILE, QUARTILE, LARGE and SMALL (range, k) and of
+    /// RANK (number, range, optional order)
+    fn parse_order_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let function_type = match name {
+            "PERCENTILE" => FunctionType::Percentile,
+            "QUARTILE" => FunctionType::Quartile,
+            "LARGE" => FunctionType::Large,
+            "SMALL" => FunctionType::Small,
+            _ => FunctionType::Rank,
+        };
+        let args = if function_type == FunctionType::Rank {
+            let number = self.parse_compare()?;
+            self.expect(Token::Comma)?;
+            let mut args = vec![number, self.parse_range_arg()?];
+            if self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+                args.push(self.parse_compare()?);
+            }
+            args
+        } else {
+            let range = self.parse_range_arg()?;
+            self.expect(Token::Comma)?;
+            vec![range, self.parse_compare()?]
+        };
+        Ok(Expr::Call(function_type, args))
+    }
+    /// Parses the arguments of VLOOKUP and HLOOKUP (value, table, index, optional approximate),
+    /// INDEX (range, row, optional column) and MATCH (value, range, optional mode)
+    fn parse_lookup_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        // Position of the table argument and the argument count bounds
+        let (function_type, table_at, min_args, max_args) = match name {
+            "VLOOKUP" => (FunctionType::VLookup, 1, 3, 4),
+            "HLOOKUP" => (FunctionType::HLookup, 1, 3, 4),
+            "INDEX" => (FunctionType::Index, 0, 2, 3),
+            _ => (FunctionType::Match, 1, 2, 3),
+        };
+        let mut args = Vec::new();
+        while args.len() < max_args {
+            if !args.is_empty() {
+                if self.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            args.push(if args.len() == table_at {
+                self.parse_range_arg()?
+            } else {
+                self.parse_compare()?
+            });
+        }
+        if args.len() < min_args {
+            return Err(self.argument_count_error(function_type, min_args, max_args));
+        }
+        Ok(Expr::Call(function_type, args))
+    }
+    /// Parses the comma separated arguments of IF, AND, OR, NOT and IFERROR
+    /// and checks their count
+    fn parse_logical_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let (function_type, min_args, max_args) = match name {
+            "IF" => (FunctionType::If, 2, 3),
+            "AND" => (FunctionType::And, 1, usize::MAX),
+            "OR" => (FunctionType::Or, 1, usize::MAX),
+            "NOT" => (FunctionType::Not, 1, 1),
+            _ => (FunctionType::IfError, 2, 2),
+        };
+        self.parse_value_args(function_type, min_args, max_args)
+    }
+    /// Parses the arguments of the math functions, all of them numbers: RAND takes none, ABS,
+    /// SQRT and SIGN one, MOD, POWER and RANDBETWEEN two, ROUND, FLOOR and CEILING an optional
+    /// second one
+    fn parse_math_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let (function_type, min_args, max_args) = match name {
+            "RAND" => (FunctionType::Rand, 0, 0),
+            "RANDBETWEEN" => (FunctionType::RandBetween, 2, 2),
+            "ABS" => (FunctionType::Abs, 1, 1),
+            "MOD" => (FunctionType::Mod, 2, 2),
+            "POWER" => (FunctionType::Power, 2, 2),
+            "SQRT" => (FunctionType::Sqrt, 1, 1),
+            "ROUND" => (FunctionType::Round, 1, 2),
+            "FLOOR" => (FunctionType::Floor, 1, 2),
+            "CEILING" => (FunctionType::Ceiling, 1, 2),
+            _ => (FunctionType::Sign, 1, 1),
+        };
+        self.parse_value_args(function_type, min_args, max_args)
+    }
+    /// Parses the arguments of CONCAT, any number of ranges and values
+    fn parse_concat_call(&mut self) -> Result<Expr, ParseError> {
+        let mut args = vec![self.parse_aggregate_arg()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.parse_aggregate_arg()?);
+        }
+        Ok(Expr::Call(FunctionType::Concat, args))
+    }
+    /// Parses the arguments of the text functions: LEFT and RIGHT take the text and an
+    /// optional length, MID the text, a start and a length, TEXT a number and a format,
+    /// the others one value
+    fn parse_text_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let (function_type, min_args, max_args) = match name {
+            "LEN" => (FunctionType::Len, 1, 1),
+            "LEFT" => (FunctionType::Left, 1, 2),
+            "RIGHT" => (FunctionType::Right, 1, 2),
+            "MID" => (FunctionType::Mid, 3, 3),
+            "UPPER" => (FunctionType::Upper, 1, 1),
+            "LOWER" => (FunctionType::Lower, 1, 1),
+            "TRIM" => (FunctionType::Trim, 1, 1),
+            "TEXT" => (FunctionType::Text, 2, 2),
+            _ => (FunctionType::Value, 1, 1),
+        };
+        self.parse_value_args(function_type, min_args, max_args)
+    }
+    /// Parses the arguments of the date functions: TODAY and NOW take none, DATE a year, a
+    /// month and a day, DATEDIF a start, an end and a unit, the others one date
+    fn parse_date_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let (function_type, min_args, max_args) = match name {
+            "TODAY" => (FunctionType::Today, 0, 0),
+            "NOW" => (FunctionType::Now, 0, 0),
+            "DATE" => (FunctionType::Date, 3, 3),
+            "YEAR" => (FunctionType::Year, 1, 1),
+            "MONTH" => (FunctionType::Month, 1, 1),
+            "DAY" => (FunctionType::Day, 1, 1),
+            _ => (FunctionType::DateDif, 3, 3),
+        };
+        self.parse_value_args(function_type, min_args, max_args)
+    }
+    /// Parses `min_args` to `max_args` comma separated values as the arguments of a call,
+    /// the closing parenthesis is left for the caller
+    fn parse_value_args(
+        &mut self,
+        function_type: FunctionType,
+        min_args: usize,
+        max_args: usize,
+    ) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::RParen) {
+            if min_args > 0 {
+                return Err(self.argument_count_error(function_type, min_args, max_args));
+            }
+            return Ok(Expr::Call(function_type, Vec::new()));
+        }
+        let mut args = vec![self.parse_compare()?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.parse_compare()?);
+        }
+        if args.len() < min_args || args.len() > max_args {
+            return Err(self.argument_count_error(function_type, min_args, max_args));
+        }
+        Ok(Expr::Call(function_type, args))
+    }
+    /// Returns the error for a call with too few or too many arguments, at the token after
+    /// them, such as "ROUND takes 1 to 2 arguments"
+    fn argument_count_error(
+        &self,
+        function_type: FunctionType,
+        min_args: usize,
+        max_args: usize,
+    ) -> ParseError {
+        let plural = |count: usize| if count == 1 { "argument" } else { "arguments" };
+        let count = match (min_args, max_args) {
+            (0, 0) => "no arguments".to_string(),
+            (min, usize::MAX) => format!("at least {} {}", min, plural(min)),
+            (min, max) if min == max => format!("{} {}", min, plural(min)),
+            (min, max) => format!("{} to {} arguments", min, max),
+        };
+        self.error(format!("{} takes {}", function_type.name(), count))
+    }
+    /// Parses a `TopLeft:BottomRight` range, optionally on another sheet (`Sheet2!A1:B5`),
+    /// or a range of whole columns (`A:A`, `B:D`) or whole rows (`3:3`, `2:10`) that spans
+    /// the sheet
+    /// Nothing is consumed and None returned when the tokens do not start a range
+    fn parse_range(&mut self) -> Result<Option<RangeFunction>, ParseError> {
+        let start = self.pos;
+        let has_sheet = matches!(self.peek(), Some(Token::Sheet(_)));
+        let reference_at = start + usize::from(has_sheet);
+        if !matches!(
+            (
+                self.tokens.get(reference_at),
+                self.tokens.get(reference_at + 1)
+            ),
+            (Some(Token::Ident(_) | Token::Number(_)), Some(Token::Colon))
+        ) {
+            return Ok(None);
+        }
+        let sheet = match self.peek() {
+            Some(Token::Sheet(sheet)) => {
+                let sheet = self.sheet_at(start, sheet)?;
+                self.pos += 1;
+                sheet
+            }
+            _ => self.backend.get_active_sheet(),
+        };
+        let first = self.parse_range_end(sheet)?;
+        self.expect(Token::Colon)?;
+        let last = self.parse_range_end(sheet)?;
+
+        let cols = self.backend.get_cols();
+        let rows = self.backend.sheet_rows().span(sheet);
+        let (top, bottom) = (rows.start, rows.end - 1);
+        let cell = |row, col| Cell { row, col };
+        let anchor = |row, col| Anchor { row, col };
+        let range = match (first, last) {
+            (
+                RangeEnd::Cell(top_left, top_left_anchor),
+                RangeEnd::Cell(bottom_right, bottom_right_anchor),
+            ) => RangeFunction {
+                top_left,
+                bottom_right,
+                top_left_anchor,
+                bottom_right_anchor,
+                span: RangeSpan::Cells,
+            },
+            // Whole columns and rows reach from edge to edge of the sheet
+            (RangeEnd::Column(first, first_anchored), RangeEnd::Column(last, last_anchored)) => {
+                RangeFunction {
+                    top_left: cell(top, first),
+                    bottom_right: cell(bottom, last),
+                    top_left_anchor: anchor(true, first_anchored),
+                    bottom_right_anchor: anchor(true, last_anchored),
+                    span: RangeSpan::Columns,
+                }
+            }
+            (RangeEnd::Row(first, first_anchored), RangeEnd::Row(last, last_anchored)) => {
+                RangeFunction {
+                    top_left: cell(top + first, 0),
+                    bottom_right: cell(top + last, cols - 1),
+                    top_left_anchor: anchor(first_anchored, true),
+                    bottom_right_anchor: anchor(last_anchored, true),
+                    span: RangeSpan::Rows,
+                }
+            }
+            _ => {
+                let reason = "a range joins two cells, two columns or two rows";
+                return Err(self.error_at(reference_at, reason.to_string()));
+            }
+        };
+
+        // Check if range is valid (top_left <= bottom_right)
+        if range.top_left.row > range.bottom_right.row
+            || range.top_left.col > range.bottom_right.col
+        {
+            let reason = "a range goes from its top left to its bottom right cell";
+            return Err(self.error_at(reference_at, reason.to_string()));
+        }
+
+        Ok(Some(range))
+    }
+    /// Parses one end of a range on `sheet`: a cell (`B2`), a column (`B`, `$B`) or a row
+    /// (`3`, `$3`)
+    fn parse_range_end(&mut self, sheet: usize) -> Result<RangeEnd, ParseError> {
+        let (rows, cols) = (self.backend.get_rows(), self.backend.get_cols());
+        let error = |parser: &Self, reason| Err(parser.error_at(parser.pos - 1, reason));
+        match self.next()? {
+            Token::Ident(name) => {
+                let (anchored, rest) = match name.strip_prefix('$') {
+                    Some(rest) => (true, rest),
+                    None => (false, name.as_str()),
+                };
+                if !rest.is_empty() && rest.chars().all(|c| c.is_ascii_uppercase()) {
+                    match parse_cell_reference(&format!("{}1", rest), 1, cols) {
+                        Some(cell) => Ok(RangeEnd::Column(cell.col, anchored)),
+                        None => error(self, format!("column {} outside {} columns", rest, cols)),
+                    }
+                } else if anchored && !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()) {
+                    self.range_row(rest, true)
+                } else {
+                    let (cell, anchor) = self.parse_cell_on(sheet, &name)?;
+                    Ok(RangeEnd::Cell(cell, anchor))
+                }
+            }
+            Token::Number(number) if number.fract() == 0.0 => {
+                self.range_row(&number.to_string(), false)
+            }
+            Token::Number(_) => error(self, format!("expected a row of 1 to {}", rows)),
+            _ => error(self, "expected a cell reference".to_string()),
+        }
+    }
+    /// Returns the row end of a range from the row number as written, counted from 1
+    fn range_row(&self, number: &str, anchored: bool) -> Result<RangeEnd, ParseError> {
+        let rows = self.backend.get_rows();
+        match number.parse::<usize>() {
+            Ok(row) if (1..=rows).contains(&row) => Ok(RangeEnd::Row(row - 1, anchored)),
+            _ => {
+                let reason = format!("row {} outside {} rows", number, rows);
+                Err(self.error_at(self.pos - 1, reason))
+            }
+        }
+    }
+    /// Resolves a defined name against the backend name table, a name that is not defined
+    /// (yet) gives #NAME? until it is
+    /// The name is the token just consumed.
+    fn parse_name(&self, name: String) -> Result<Expr, ParseError> {
+        if !is_valid_name(&name) {
+            return Err(self.error_at(self.pos - 1, format!("invalid name {}", name)));
+        }
+        let target = self.backend.resolve_name(&name);
+        Ok(Expr::Name(name, Box::new(target)))
+    }
+    /// Looks up the sheet named by the `Sheet!` token at `index`
+    fn sheet_at(&self, index: usize, name: &str) -> Result<usize, ParseError> {
+        self.backend
+            .sheet_index(name)
+            .ok_or_else(|| self.error_at(index, format!("unknown sheet {}", name)))
+    }
+    /// Parses what a name stands for: a cell, a range or a constant
+    fn parse_name_target(&mut self) -> Result<Expr, ParseError> {
+        let target = match self.parse_range()? {
+            Some(range) => Expr::Range(range),
+            None => self.parse_compare()?,
+        };
+        if self.pos != self.tokens.len() {
+            return Err(self.unexpected(self.pos));
+        }
+        match target {
+            Expr::Range(_) | Expr::Cell(..) | Expr::Value(_) | Expr::Text(_) | Expr::Bool(_) => {
+                Ok(target)
+            }
+            _ => {
+                let reason = "a name stands for a cell, a range or a constant";
+                Err(self.error_at(0, reason.to_string()))
+            }
+        }
+    }
+    /// Parses a possibly anchored cell reference on the active sheet
+    fn parse_cell(&self, name: &str) -> Result<(Cell, Anchor), ParseError> {
+        self.parse_cell_on(self.backend.get_active_sheet(), name)
+    }
+    /// Parses a possibly anchored cell reference within the bounds of a sheet and returns
+    /// its workbook cell
+    /// The reference is the token just consumed.
+    fn parse_cell_on(&self, sheet: usize, name: &str) -> Result<(Cell, Anchor), ParseError> {
+        let rows = self.backend.get_rows();
+        let cols = self.backend.get_cols();
+        let error = |reason| Err(self.error_at(self.pos - 1, reason));
+        let Some((cell, anchor)) = parse_anchored_reference(name, usize::MAX, usize::MAX) else {
+            // Too many letters or digits to count, or row 0, is outside the sheet as well
+            let rest = name.strip_prefix('$').unwrap_or(name);
+            let letters = &rest[..rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(0)];
+            let column = parse_cell_reference(&format!("{}1", letters), 1, usize::MAX);
+            if !letters.is_empty() && column.is_none() {
+                return error(format!("reference {} outside {} columns", name, cols));
+            }
+            if is_reference(name) {
+                return error(format!("reference {} outside {} rows", name, rows));
+            }
+            return error(format!("{} is not a cell reference", name));
+        };
+        if cell.row >= rows {
+            return error(format!("reference {} outside {} rows", name, rows));
+        }
+        if cell.col >= cols {
+            return error(format!("reference {} outside {} columns", name, cols));
+        }
+        Ok((
+            Cell {
+                row: self.backend.sheet_rows().workbook_row(sheet, cell.row),
+                col: cell.col,
+            },
+            anchor,
+        ))
+    }
+}
+/// Returns true if `name` reads as a cell reference (A1, $B$2), letters and then digits
+/// with optional `$` anchors, whether or not the cell is on the sheet.
+fn is_reference(name: &str) -> bool {
+    let rest = name.strip_prefix('$').unwrap_or(name);
+    let letters = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    let digits = &rest[letters..];
+    let digits = digits.strip_prefix('$').unwrap_or(digits);
+    letters > 0 && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+/// Returns true if `name` can be defined as a name: letters, digits and underscores,
+/// starting with a letter or underscore, and not something that reads as a cell
+/// reference (TAX1) or a boolean.
+pub fn is_valid_name(name: &str) -> bool {
+    let starts_well = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_uppercase() || c == '_');
+    let chars_ok = name
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    let letters_end = name
+        .find(|c: char| !c.is_ascii_uppercase())
+        .unwrap_or(name.len());
+    let looks_like_cell = letters_end > 0
+        && letters_end < name.len()
+        && name[letters_end..].chars().all(|c| c.is_ascii_digit());
+    starts_well && chars_ok && !looks_like_cell && name != "TRUE" && name != "FALSE"
+}
+/// Reads a date literal, `2026-10-16` with an optional time `2026-10-16 14:30` or
+/// `2026-10-16 14:30:15`, and returns its serial.
+pub fn parse_date(text: &str) -> Option<f64> {
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time.trim_start())),
+        None => (text, None),
+    };
+    let number = |part: &str, digits: usize| -> Option<u32> {
+        if part.len() != digits || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        part.parse().ok()
+    };
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+    // Days past the end of the month do not read back as the same date
+    let serial = date_serial(year as i64, month, day);
+    if serial_date(serial) != (year as i64, month, day) {
+        return None;
+    }
+    let seconds = match time {
+        None => 0,
+        Some(time) => {
+            let mut parts = time.split(':');
+            let hour = number(parts.next()?, 2)?;
+            let minute = number(parts.next()?, 2)?;
+            let second = match parts.next() {
+                Some(part) => number(part, 2)?,
+                None => 0,
+            };
+            if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+                return None;
+            }
+            hour * 3600 + minute * 60 + second
+        }
+    };
+    Some(serial as f64 + seconds as f64 / 86400.0)
+}
+/// Returns true if `name` can name a sheet: letters, digits and underscores, starting
+/// with a letter.
+pub fn is_valid_sheet_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+/// Parses the definition of a name (`B1`, `$C$2:$C$480` or a constant such as `0.07`).
+pub fn parse_name_definition(definition: &str, backend: &Backend) -> Result<Expr, ParseError> {
+    FormulaParser::new(definition, backend)?.parse_name_target()
+}
+/// Converts a leaf of the expression tree into a binary operation operand.
+fn leaf_operand(expr: &Expr) -> Option<Operand> {
+    match expr {
+        Expr::Value(value) => Some(Operand {
+            type_: OperandType::Number,
+            data: OperandData::Value(*value),
+            anchor: Anchor::default(),
+        }),
+        Expr::Cell(cell, anchor) => Some(Operand {
+            type_: OperandType::Cell,
+            data: OperandData::Cell(*cell),
+            anchor: *anchor,
+        }),
+        _ => None,
+    }
+}
+/// Stores an expression tree as a flat Function when it has one of the simple shapes
+/// (constant, binary op of two leaves, range function, sleep) and as an
+/// expression tree otherwise.
+pub fn lower_expression(expr: Expr) -> Function {
+    match &expr {
+        Expr::Value(value) => return Function::new_constant(*value),
+        Expr::Text(text) => return Function::new_literal(CellValue::Text(text.clone())),
+        Expr::Bool(value) => return Function::new_literal(CellValue::Bool(*value)),
+        Expr::Error(error) => return Function::new_literal(CellValue::Error(*error)),
+        // A bare reference stays a tree so text and booleans are copied as they are
+        Expr::Cell(..) => {}
+        // Comparisons are only evaluated as trees
+        Expr::BinaryOp(function_type, first, second)
+            if matches!(
+                function_type,
+                FunctionType::Plus
+                    | FunctionType::Minus
+                    | FunctionType::Multiply
+                    | FunctionType::Divide
+            ) =>
+        {
+            if let (Some(first), Some(second)) = (leaf_operand(first), leaf_operand(second)) {
+                return Function::new_binary_op(*function_type, BinaryOp { first, second });
+            }
+        }
+        Expr::Call(FunctionType::Sleep, args) => match args[0] {
+            Expr::Value(value) => return Function::new_sleep(value),
+            Expr::Cell(cell, anchor) => return Function::new_sleep_cell(cell, anchor),
+            _ => {}
+        },
+        // Other functions taking a range, such as CONCAT(A1:B1), stay trees
+        Expr::Call(function_type, args) if function_type.is_aggregate() => {
+            if let [Expr::Range(range)] = args.as_slice() {
+                return Function::new_range_function(*function_type, *range);
+            }
+            return Function::new_aggregate(*function_type, args.clone());
+        }
+        Expr::Call(..) => {}
+        // Names stay in the tree so they can be printed and rebound
+        Expr::Range(_) | Expr::Name(..) | Expr::Negate(_) | Expr::BinaryOp(..) => {}
+    }
+    Function::new_expression(expr)
+}
+/// Parses a function from a string and returns a Function struct.
+///
+/// Supports operator precedence (`*` and `/` bind tighter than `+` and `-`),
+/// parentheses, signed literals, unary minus and function calls nested inside expressions.
+///
+/// # Errors
+/// A `ParseError` with the byte offset and text of the offending token and the reason,
+/// such as "unknown function FOO" or "reference B1000 outside 999 rows".
+pub fn parse_expression(expression: &str, backend: &Backend) -> Result<Function, ParseError> {
+    // A whole date such as 2026-10-16 is a date, not a subtraction
+    if let Some(serial) = parse_date(expression.trim()) {
+        return Ok(Function::new_literal(CellValue::Date(serial)));
+    }
+    let expr = FormulaParser::new(expression, backend)?.parse()?;
+    Ok(lower_expression(expr))
+}
+#[cfg(feature = "cli")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Backend;
+    use crate::structs::{
+        Cell, Expr, Function, FunctionData, FunctionType, Operand, OperandData, OperandType,
+        RangeFunction, RangeSpan,
+    };
+
+    #[test]
+    fn test_parse_cell_reference_valid() {
+        let rows = 10;
+        let cols = 26;
+
+        assert_eq!(
+            parse_cell_reference("A1", rows, cols),
+            Some(Cell { row: 0, col: 0 })
+        );
+        assert_eq!(
+            parse_cell_reference("B2", rows, cols),
+            Some(Cell { row: 1, col: 1 })
+        );
+        assert_eq!(
+            parse_cell_reference("Z10", rows, cols),
+            Some(Cell { row: 9, col: 25 })
+        );
+    }
+
+    #[test]
+    fn test_parse_anchored_reference() {
+        let rows = 10;
+        let cols = 26;
+        let anchor = |row, col| Anchor { row, col };
+
+        assert_eq!(
+            parse_anchored_reference("B2", rows, cols),
+            Some((Cell { row: 1, col: 1 }, anchor(false, false)))
+        );
+        assert_eq!(
+            parse_anchored_reference("$B$2", rows, cols),
+            Some((Cell { row: 1, col: 1 }, anchor(true, true)))
+        );
+        assert_eq!(
+            parse_anchored_reference("B$2", rows, cols),
+            Some((Cell { row: 1, col: 1 }, anchor(true, false)))
+        );
+        assert_eq!(
+            parse_anchored_reference("$Z10", rows, cols),
+            Some((Cell { row: 9, col: 25 }, anchor(false, true)))
+        );
+
+        for invalid in ["$", "$$A1", "A$$1", "A1$", "$1", "A$", "$A$11"] {
+            assert_eq!(parse_anchored_reference(invalid, rows, cols), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        for valid in ["TAX_RATE", "SALES", "_TOTAL", "Q1_SALES", "A1B", "SUM"] {
+            assert!(is_valid_name(valid), "{}", valid);
+        }
+        for invalid in ["A1", "ZZ999", "TRUE", "FALSE", "1ST", "$A", "TAX RATE", "", "tax"] {
+            assert!(!is_valid_name(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_cell_reference_invalid() {
+        let rows = 10;
+        let cols = 10;
+
+        assert_eq!(parse_cell_reference("1A", rows, cols), None); // Invalid format
+        assert_eq!(parse_cell_reference("AA", rows, cols), None); // Missing row
+        assert_eq!(parse_cell_reference("A11", rows, cols), None); // Out of bounds
+        assert_eq!(parse_cell_reference("", rows, cols), None); // Empty string
+    }
+
+    #[test]
+    fn test_parse_binary_op_valid() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("A1*42", &backend).unwrap();
+        assert_eq!(function.type_, FunctionType::Multiply);
+        let FunctionData::BinaryOp(binary_op) = function.data else {
+            panic!("expected a binary op");
+        };
+        assert_eq!(
+            binary_op.first,
+            Operand {
+                type_: OperandType::Cell,
+                data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                anchor: Anchor::default(),
+            }
+        );
+        assert_eq!(
+            binary_op.second,
+            Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(42.0),
+                anchor: Anchor::default(),
+            }
+        );
+
+        let function = parse_expression("10/20", &backend).unwrap();
+        assert_eq!(function.type_, FunctionType::Divide);
+        let FunctionData::BinaryOp(binary_op) = function.data else {
+            panic!("expected a binary op");
+        };
+        assert_eq!(
+            binary_op.first,
+            Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(10.0),
+                anchor: Anchor::default(),
+            }
+        );
+        assert_eq!(
+            binary_op.second,
+            Operand {
+                type_: OperandType::Number,
+                data: OperandData::Value(20.0),
+                anchor: Anchor::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_binary_op_invalid() {
+        let backend = Backend::new(10, 10);
+
+        assert!(parse_expression("Invalid?+42", &backend).is_err());
+
+        assert!(parse_expression("A1+Invalid?", &backend).is_err());
+
+        assert!(parse_expression("A1+", &backend).is_err());
+
+        assert!(parse_expression("A1**2", &backend).is_err());
+
+        // Malformed numeric literals
+        assert!(parse_expression("A1+1.2.3", &backend).is_err());
+        assert!(parse_expression("A1+1e", &backend).is_err());
+    }
+
+    #[test]
+    fn test_parse_binary_op_negative_literal() {
+        let backend = Backend::new(10, 10);
+        let cell = Operand {
+            type_: OperandType::Cell,
+            data: OperandData::Cell(Cell { row: 0, col: 0 }),
+            anchor: Anchor::default(),
+        };
+        let number = |value: f64| Operand {
+            type_: OperandType::Number,
+            data: OperandData::Value(value),
+            anchor: Anchor::default(),
+        };
+
+        let function = parse_expression("A1*-2", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_binary_op(
+                FunctionType::Multiply,
+                BinaryOp {
+                    first: cell,
+                    second: number(-2.0),
+                }
+            )
+        );
+
+        let function = parse_expression("-5+A1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_binary_op(
+                FunctionType::Plus,
+                BinaryOp {
+                    first: number(-5.0),
+                    second: cell,
+                }
+            )
+        );
+
+        // Subtracting a negative number keeps both minus signs apart
+        let function = parse_expression("A1--1.5", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_binary_op(
+                FunctionType::Minus,
+                BinaryOp {
+                    first: cell,
+                    second: number(-1.5),
+                }
+            )
+        );
+
+        let function = parse_expression("+3-+A1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_binary_op(
+                FunctionType::Minus,
+                BinaryOp {
+                    first: number(3.0),
+                    second: cell,
+                }
+            )
+        );
+
+        let function = parse_expression("--4", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(4.0).data);
+    }
+
+    #[test]
+    fn test_parse_binary_op_negated_operands() {
+        let backend = Backend::new(10, 10);
+        let a1 = Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default());
+
+        let function = parse_expression("-A1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::Negate(Box::new(a1.clone())))
+        );
+
+        let function = parse_expression("B1*-A1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Multiply,
+                Box::new(Expr::Cell(Cell { row: 0, col: 1 }, Anchor::default())),
+                Box::new(Expr::Negate(Box::new(a1))),
+            ))
+        );
+
+        let function = parse_expression("-SUM(A1:A3)", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::Negate(Box::new(Expr::Call(
+                FunctionType::Sum,
+                vec![Expr::Range(RangeFunction {
+                    top_left: Cell { row: 0, col: 0 },
+                    bottom_right: Cell { row: 2, col: 0 },
+                    top_left_anchor: Anchor::default(),
+                    bottom_right_anchor: Anchor::default(),
+                    span: RangeSpan::Cells,
+                })],
+            ))))
+        );
+
+        // A sign needs something to apply to
+        for invalid in ["-", "A1*-", "A1+-*2", "SUM(-A1:A3)"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_op_literal_overflow() {
+        let backend = Backend::new(10, 10);
+
+        // Literals that do not fit in an f64 are rejected instead of becoming infinite
+        assert!(parse_expression("A1+1e400", &backend).is_err());
+
+        assert!(parse_expression("-1e309", &backend).is_err());
+
+        // The largest finite values are still accepted
+        let function = parse_expression("1.7976931348623157e308", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(f64::MAX).data);
+    }
+
+    #[test]
+    fn test_parse_range_function_valid() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("SUM(A1:B2)", &backend).unwrap();
+        assert_eq!(
+            function.data,
+            Function::new_range_function(
+                FunctionType::Sum,
+                RangeFunction {
+                    top_left: Cell { row: 0, col: 0 },
+                    bottom_right: Cell { row: 1, col: 1 },
+                    top_left_anchor: Anchor::default(),
+                    bottom_right_anchor: Anchor::default(),
+                    span: RangeSpan::Cells,
+                }
+            )
+            .data
+        );
+
+        let function = parse_expression("AVG(A1:A10)", &backend).unwrap();
+        assert_eq!(
+            function.data,
+            Function::new_range_function(
+                FunctionType::Avg,
+                RangeFunction {
+                    top_left: Cell { row: 0, col: 0 },
+                    bottom_right: Cell { row: 9, col: 0 },
+                    top_left_anchor: Anchor::default(),
+                    bottom_right_anchor: Anchor::default(),
+                    span: RangeSpan::Cells,
+                }
+            )
+            .data
+        );
+    }
+
+    #[test]
+    fn test_parse_range_function_invalid() {
+        let backend = Backend::new(10, 10);
+
+        assert!(parse_expression("SUM(A1:Invalid)", &backend).is_err());
+
+        assert!(parse_expression("SUM(A1:A11)", &backend).is_err());
+
+        assert!(parse_expression("SUM(A1:B1:C1)", &backend).is_err());
 
-    #[test]
-    fn test_parse_expression_constant() {
-        let backend = Backend::new(10, 10);
-
-        let (function, success) = parse_expression("42", &backend);
-        assert!(success);
-        assert_eq!(function.data, Function::new_constant(42).data);
+        assert!(parse_expression("SUM(B2:A1)", &backend).is_err());
 
-        let (function, success) = parse_expression("-42", &backend);
-        assert!(success);
-        assert_eq!(function.data, Function::new_constant(-42).data);
+        assert!(parse_expression("FOO(A1:A2)", &backend).is_err());
+
+        assert!(parse_expression("SUM(A1:A2", &backend).is_err());
+    }
+
+    #[test]
+    fn test_parse_expression_precedence() {
+        let backend = Backend::new(10, 10);
+        let a1 = Box::new(Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default()));
+        let b1 = Box::new(Expr::Cell(Cell { row: 0, col: 1 }, Anchor::default()));
+
+        let function = parse_expression("A1+B1*2", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Plus,
+                a1.clone(),
+                Box::new(Expr::BinaryOp(
+                    FunctionType::Multiply,
+                    b1.clone(),
+                    Box::new(Expr::Value(2.0)),
+                )),
+            ))
+        );
+
+        // Same precedence is left associative
+        let function = parse_expression("10-2-3", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Minus,
+                Box::new(Expr::BinaryOp(
+                    FunctionType::Minus,
+                    Box::new(Expr::Value(10.0)),
+                    Box::new(Expr::Value(2.0)),
+                )),
+                Box::new(Expr::Value(3.0)),
+            ))
+        );
+
+        let function = parse_expression("(A1+B1)/2", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Divide,
+                Box::new(Expr::BinaryOp(FunctionType::Plus, a1.clone(), b1.clone())),
+                Box::new(Expr::Value(2.0)),
+            ))
+        );
+
+        let function = parse_expression("-(A1)", &backend).unwrap();
+        assert_eq!(function, Function::new_expression(Expr::Negate(a1)));
+
+        assert!(parse_expression("(A1+B1", &backend).is_err());
+        assert!(parse_expression("A1+B1)", &backend).is_err());
+    }
+
+    #[test]
+    fn test_parse_expression_nested_function() {
+        let backend = Backend::new(10, 10);
+        let range = Expr::Range(RangeFunction {
+            top_left: Cell { row: 0, col: 0 },
+            bottom_right: Cell { row: 2, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        });
+
+        let function = parse_expression("SUM(A1:A3)+1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Plus,
+                Box::new(Expr::Call(FunctionType::Sum, vec![range.clone()])),
+                Box::new(Expr::Value(1.0)),
+            ))
+        );
+
+        let function = parse_expression("SLEEP(MAX(A1:A3))", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::Call(
+                FunctionType::Sleep,
+                vec![Expr::Call(FunctionType::Max, vec![range])],
+            ))
+        );
+
+        // A bare range is not a value
+        assert!(parse_expression("A1:A3", &backend).is_err());
+    }
+
+    #[test]
+    fn test_parse_expression_comparison_and_if() {
+        let backend = Backend::new(10, 10);
+        let a1 = Box::new(Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default()));
+
+        // Comparisons bind looser than arithmetic
+        let function = parse_expression("A1+1>=2*3", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::GreaterEqual,
+                Box::new(Expr::BinaryOp(
+                    FunctionType::Plus,
+                    a1.clone(),
+                    Box::new(Expr::Value(1.0)),
+                )),
+                Box::new(Expr::BinaryOp(
+                    FunctionType::Multiply,
+                    Box::new(Expr::Value(2.0)),
+                    Box::new(Expr::Value(3.0)),
+                )),
+            ))
+        );
+
+        let function = parse_expression("IF(A1<>0,\"yes\",NOT(TRUE))", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::Call(
+                FunctionType::If,
+                vec![
+                    Expr::BinaryOp(FunctionType::NotEqual, a1, Box::new(Expr::Value(0.0))),
+                    Expr::Text("yes".to_string()),
+                    Expr::Call(FunctionType::Not, vec![Expr::Bool(true)]),
+                ],
+            ))
+        );
+
+        for valid in ["A1=B1", "A1<B1", "A1<=B1", "A1>B1", "AND(A1,B1,C1)", "IFERROR(A1/B1,0)"] {
+            assert!(parse_expression(valid, &backend).is_ok(), "{}", valid);
+        }
+        // Wrong argument counts and dangling operators
+        for invalid in ["IF(A1)", "IF(A1,1,2,3)", "NOT(A1,B1)", "IFERROR(A1)", "AND()", "A1<", "A1=>B1"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_constant() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("42", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(42.0).data);
+
+        let function = parse_expression("-42", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(-42.0).data);
+    }
+
+    #[test]
+    fn test_parse_expression_decimal_constant() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("2.75", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(2.75).data);
+
+        let function = parse_expression("1e-3", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(0.001).data);
+
+        let function = parse_expression("-.5", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(-0.5).data);
+
+        let function = parse_expression("2.5E+2", &backend).unwrap();
+        assert_eq!(function.data, Function::new_constant(250.0).data);
+
+        assert!(parse_expression(".", &backend).is_err());
     }
 
     #[test]
     fn test_parse_expression_cell_reference() {
         let backend = Backend::new(10, 10);
 
-        let (function, success) = parse_expression("A1", &backend);
-        assert!(success);
+        let function = parse_expression("A1", &backend).unwrap();
+        assert_eq!(
+            function.data,
+            Function::new_expression(Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default())).data
+        );
+    }
+
+    #[test]
+    fn test_parse_expression_text_and_bool_literals() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("\"say \"\"hi\"\"\"", &backend).unwrap();
+        assert_eq!(
+            function.data,
+            Function::new_literal(CellValue::Text("say \"hi\"".to_string())).data
+        );
+
+        let function = parse_expression("TRUE", &backend).unwrap();
+        assert_eq!(
+            function.data,
+            Function::new_literal(CellValue::Bool(true)).data
+        );
+
+        assert!(parse_expression("\"unterminated", &backend).is_err());
+    }
+
+    #[test]
+    fn test_parse_expression_binary_op() {
+        let backend = Backend::new(10, 10);
+
+        let function = parse_expression("A1+42", &backend).unwrap();
         assert_eq!(
             function.data,
             Function::new_binary_op(
@@ -649,10 +1846,12 @@ mod tests {
                     first: Operand {
                         type_: OperandType::Cell,
                         data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                        anchor: Anchor::default(),
                     },
                     second: Operand {
-                        type_: OperandType::Int,
-                        data: OperandData::Value(0),
+                        type_: OperandType::Number,
+                        data: OperandData::Value(42.0),
+                        anchor: Anchor::default(),
                     },
                 }
             )
@@ -661,36 +1860,684 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_expression_binary_op() {
+    fn test_parse_expression_invalid() {
+        let backend = Backend::new(10, 10);
+
+        assert!(parse_expression("Invalid?", &backend).is_err());
+    }
+
+    /// Parses a formula into its expression tree without lowering it
+    fn parse_tree(expression: &str, backend: &Backend) -> Option<Expr> {
+        FormulaParser::new(expression, backend).ok()?.parse().ok()
+    }
+
+    #[test]
+    fn test_parse_expression_anchored_references() {
         let backend = Backend::new(10, 10);
 
-        let (function, success) = parse_expression("A1+42", &backend);
-        assert!(success);
+        let function = parse_expression("$A$1*B$2", &backend).unwrap();
         assert_eq!(
-            function.data,
+            function,
             Function::new_binary_op(
-                FunctionType::Plus,
+                FunctionType::Multiply,
                 BinaryOp {
                     first: Operand {
                         type_: OperandType::Cell,
                         data: OperandData::Cell(Cell { row: 0, col: 0 }),
+                        anchor: Anchor { row: true, col: true },
                     },
                     second: Operand {
-                        type_: OperandType::Int,
-                        data: OperandData::Value(42),
+                        type_: OperandType::Cell,
+                        data: OperandData::Cell(Cell { row: 1, col: 1 }),
+                        anchor: Anchor { row: true, col: false },
                     },
                 }
             )
-            .data
         );
+
+        let function = parse_expression("SUM($A1:B$3)", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_range_function(
+                FunctionType::Sum,
+                RangeFunction {
+                    top_left: Cell { row: 0, col: 0 },
+                    bottom_right: Cell { row: 2, col: 1 },
+                    top_left_anchor: Anchor { row: false, col: true },
+                    bottom_right_anchor: Anchor { row: true, col: false },
+                    span: RangeSpan::Cells,
+                }
+            )
+        );
+
+        for invalid in ["$SUM(A1:A2)", "A1+$", "$$A1", "A$$1"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
     }
 
     #[test]
-    fn test_parse_expression_invalid() {
+    fn test_expression_formula_text_round_trip() {
+        let backend = Backend::new(10, 10);
+
+        // Printed text is canonical: only needed parentheses, anchors kept
+        for (input, printed) in [
+            ("$A$1+B$2*$C3", "$A$1+B$2*$C3"),
+            ("(A1+B1)*(C1-D1)", "(A1+B1)*(C1-D1)"),
+            ("((A1))-(B1-C1)", "A1-(B1-C1)"),
+            ("A1-B1-C1", "A1-B1-C1"),
+            ("A1/(B1*C1)", "A1/(B1*C1)"),
+            ("-(A1+1)*-2", "-(A1+1)*-2"),
+            ("IF(A1>=0.5,\"say \"\"hi\"\"\",#REF!)", "IF(A1>=0.5,\"say \"\"hi\"\"\",#REF!)"),
+            ("AND(A1=B1,NOT(A1<>TRUE))", "AND(A1=B1,NOT(A1<>TRUE))"),
+            ("SUM($A1:B$3)+1e-7", "SUM($A1:B$3)+1e-7"),
+            ("(A1<B1)=FALSE", "A1<B1=FALSE"),
+            ("-(A1^2)", "-(A1^2)"),
+            ("(-A1)^2+(2^3)^2", "-A1^2+2^3^2"),
+            ("2^(3^2)*A1^-1", "2^(3^2)*A1^-1"),
+            ("ROUND(MOD(A1,3)/2,1)", "ROUND(MOD(A1,3)/2,1)"),
+            ("(A1&\"-\")&(B1+1)", "A1&\"-\"&B1+1"),
+            ("DATE(2026,10,16)-NOW()+TODAY()", "DATE(2026,10,16)-NOW()+TODAY()"),
+            ("A1&(B1&C1)=(D1&1)", "A1&(B1&C1)=D1&1"),
+        ] {
+            let expr = parse_tree(input, &backend).unwrap();
+            let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+            assert_eq!(expr.formula_text(&layout).to_string(), printed);
+            assert_eq!(parse_tree(printed, &backend), Some(expr));
+        }
+    }
+
+    #[test]
+    fn test_function_formula_string_round_trip() {
+        let mut backend = Backend::new(10, 10);
+        backend.add_sheet("Costs");
+        backend.define_name("RATE", "0.07").unwrap();
+        let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+
+        // Every shape of function prints as canonical text that parses back to it
+        for (input, printed) in [
+            ("42", "42"),
+            ("0.1", "0.1"),
+            ("1e-20", "1e-20"),
+            ("\"say \"\"hi\"\"\"", "\"say \"\"hi\"\"\""),
+            ("true", "TRUE"),
+            ("#div/0!", "#DIV/0!"),
+            ("2026-10-16", "2026-10-16"),
+            ("2026-10-16 14:30", "2026-10-16 14:30:00"),
+            ("a1 + $B$2", "=A1+$B$2"),
+            ("A1*2", "=A1*2"),
+            ("sum(A1:B2)", "=SUM(A1:B2)"),
+            ("MAX(A1:A3,C1,10)", "=MAX(A1:A3,C1,10)"),
+            ("SLEEP(2)", "=SLEEP(2)"),
+            ("SLEEP(A1)", "=SLEEP(A1)"),
+            ("-(A1+1)^2", "=-(A1+1)^2"),
+            ("IF(A1>0,\"yes\",\"no\")", "=IF(A1>0,\"yes\",\"no\")"),
+            ("costs!A1+SUM(Costs!B1:B3)", "=Costs!A1+SUM(Costs!B1:B3)"),
+            ("A1*RATE", "=A1*RATE"),
+            ("A1", "=A1"),
+        ] {
+            let function = parse_expression(input, &backend).unwrap();
+            let text = function.to_formula_string(&layout);
+            assert_eq!(text, printed, "{}", input);
+            let formula = text.strip_prefix('=').unwrap_or(&text);
+            assert_eq!(parse_expression(formula, &backend), Ok(function), "{}", input);
+        }
+        let empty = Function::new_literal(CellValue::Empty);
+        assert_eq!(empty.to_formula_string(&layout), "");
+    }
+
+    #[test]
+    fn test_parse_expression_defined_names() {
+        let mut backend = Backend::new(10, 10);
+        backend.define_name("TAX_RATE", "$B$1").unwrap();
+        backend.define_name("SALES", "C2:C5").unwrap();
+        let sales = RangeFunction {
+            top_left: Cell { row: 1, col: 2 },
+            bottom_right: Cell { row: 4, col: 2 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        };
+
+        let function = parse_expression("SUM(SALES)*TAX_RATE", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Multiply,
+                Box::new(Expr::Call(
+                    FunctionType::Sum,
+                    vec![Expr::Name("SALES".to_string(), Box::new(Expr::Range(sales)))],
+                )),
+                Box::new(Expr::Name(
+                    "TAX_RATE".to_string(),
+                    Box::new(Expr::Cell(Cell { row: 0, col: 1 }, Anchor { row: true, col: true })),
+                )),
+            ))
+        );
+
+        // A name that is not defined yet parses to #NAME?
+        let function = parse_expression("MISSING+1", &backend).unwrap();
+        assert_eq!(
+            function,
+            Function::new_expression(Expr::BinaryOp(
+                FunctionType::Plus,
+                Box::new(Expr::Name(
+                    "MISSING".to_string(),
+                    Box::new(Expr::Error(CellError::UnknownName)),
+                )),
+                Box::new(Expr::Value(1.0)),
+            ))
+        );
+
+        // Formula text keeps the names
+        let expr = parse_tree("SUM(SALES)*TAX_RATE", &backend).unwrap();
+        let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+        assert_eq!(expr.formula_text(&layout).to_string(), "SUM(SALES)*TAX_RATE");
+
+        // Out of range cell references are still errors, not names
+        for invalid in ["Z99+1", "SUM(SALES:C6)", "SUM(A1:SALES)"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+        assert!(parse_name_definition("A1+1", &backend).is_err());
+        assert_eq!(parse_name_definition("0.07", &backend), Ok(Expr::Value(0.07)));
+    }
+
+    #[test]
+    fn test_parse_expression_sheet_references() {
+        let mut backend = Backend::new(10, 10);
+        backend.add_sheet("Sheet2");
+
+        // Sheet2 starts at row 10 of the workbook
+        let expr = parse_tree("Sheet2!$B1+SUM(Sheet2!A1:B5)", &backend).unwrap();
+        assert_eq!(
+            expr,
+            Expr::BinaryOp(
+                FunctionType::Plus,
+                Box::new(Expr::Cell(Cell { row: 10, col: 1 }, Anchor { row: false, col: true })),
+                Box::new(Expr::Call(
+                    FunctionType::Sum,
+                    vec![Expr::Range(RangeFunction {
+                        top_left: Cell { row: 10, col: 0 },
+                        bottom_right: Cell { row: 14, col: 1 },
+                        top_left_anchor: Anchor::default(),
+                        bottom_right_anchor: Anchor::default(),
+                        span: RangeSpan::Cells,
+                    })],
+                )),
+            )
+        );
+
+        // Only references to other sheets are printed with the sheet name
+        let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+        assert_eq!(
+            expr.formula_text(&layout).to_string(),
+            "Sheet2!$B1+SUM(Sheet2!A1:B5)"
+        );
+        let layout = backend.sheet_layout(Cell { row: 10, col: 0 });
+        assert_eq!(expr.formula_text(&layout).to_string(), "$B1+SUM(A1:B5)");
+
+        backend.switch_sheet("Sheet2");
+        assert_eq!(
+            parse_tree("A3", &backend),
+            Some(Expr::Cell(Cell { row: 12, col: 0 }, Anchor::default()))
+        );
+        assert_eq!(
+            parse_tree("Sheet1!A3", &backend),
+            Some(Expr::Cell(Cell { row: 2, col: 0 }, Anchor::default()))
+        );
+
+        for invalid in ["Sheet3!A1", "Sheet2!K1", "Sheet2!A1:B11", "Sheet2!", "Sheet2!1", "Sheet2 !A1"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_conditional_aggregates() {
+        let mut backend = Backend::new(10, 10);
+        backend.define_name("PRICES", "B1:B5").unwrap();
+        let range = |top: usize, col: usize, bottom: usize| {
+            Expr::Range(RangeFunction {
+                top_left: Cell { row: top, col },
+                bottom_right: Cell { row: bottom, col },
+                top_left_anchor: Anchor::default(),
+                bottom_right_anchor: Anchor::default(),
+                span: RangeSpan::Cells,
+            })
+        };
+
+        assert_eq!(
+            parse_tree("SUMIF(A1:A5,\">10\",PRICES)", &backend),
+            Some(Expr::Call(
+                FunctionType::SumIf,
+                vec![
+                    range(0, 0, 4),
+                    Expr::Text(">10".to_string()),
+                    Expr::Name("PRICES".to_string(), Box::new(range(0, 1, 4))),
+                ],
+            ))
+        );
+        assert_eq!(
+            parse_tree("COUNTIF(A1:A5,C1)", &backend),
+            Some(Expr::Call(
+                FunctionType::CountIf,
+                vec![range(0, 0, 4), Expr::Cell(Cell { row: 0, col: 2 }, Anchor::default())],
+            ))
+        );
+        let function = parse_expression("COUNTA(A1:A5)", &backend).unwrap();
+        assert_eq!(function.type_, FunctionType::CountA);
+
+        for invalid in [
+            "COUNTIF(A1:A5)",
+            "COUNTIF(A1:A5,1,B1:B5)",
+            "SUMIF(A1:A5,1,B1:B5,C1:C5)",
+            "AVERAGEIF(1,2)",
+            "COUNT()",
+        ] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_statistical_functions() {
+        let backend = Backend::new(10, 10);
+        let function = parse_expression("STDEV.S(A1:A5)", &backend).unwrap();
+        assert_eq!(function.type_, FunctionType::StdevS);
+
+        let range = Expr::Range(RangeFunction {
+            top_left: Cell { row: 0, col: 0 },
+            bottom_right: Cell { row: 4, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        });
+        assert_eq!(
+            parse_tree("RANK(B1,A1:A5,1)", &backend),
+            Some(Expr::Call(
+                FunctionType::Rank,
+                vec![
+                    Expr::Cell(Cell { row: 0, col: 1 }, Anchor::default()),
+                    range.clone(),
+                    Expr::Value(1.0),
+                ],
+            ))
+        );
+        assert_eq!(
+            parse_tree("LARGE(A1:A5,2)", &backend),
+            Some(Expr::Call(FunctionType::Large, vec![range, Expr::Value(2.0)]))
+        );
+
+        for invalid in ["STDEV.X(A1:A5)", "PERCENTILE(A1:A5)", "RANK(A1:A5,1)", "MEDIAN(A1:A5,)", "A1.B1"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_lookup_functions() {
+        let backend = Backend::new(10, 10);
+        assert_eq!(
+            parse_tree("VLOOKUP(\"Pear\",A1:C4,2,FALSE)", &backend),
+            Some(Expr::Call(
+                FunctionType::VLookup,
+                vec![
+                    Expr::Text("Pear".to_string()),
+                    Expr::Range(RangeFunction {
+                        top_left: Cell { row: 0, col: 0 },
+                        bottom_right: Cell { row: 3, col: 2 },
+                        top_left_anchor: Anchor::default(),
+                        bottom_right_anchor: Anchor::default(),
+                        span: RangeSpan::Cells,
+                    }),
+                    Expr::Value(2.0),
+                    Expr::Bool(false),
+                ],
+            ))
+        );
+
+        for valid in ["HLOOKUP(1,A1:C2,2)", "INDEX(A1:C4,1)", "INDEX(A1:C4,B5+1,2)", "MATCH(B1,A1:A4)"] {
+            assert!(parse_expression(valid, &backend).is_ok(), "{}", valid);
+        }
+        for invalid in [
+            "VLOOKUP(1,A1:C4)",
+            "VLOOKUP(1,A1:C4,2,TRUE,1)",
+            "INDEX(A1:C4)",
+            "INDEX(1,1)",
+            "MATCH(1,2)",
+        ] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_aggregate_argument_lists() {
+        let backend = Backend::new(10, 10);
+        let cell = |row, col| Expr::Cell(Cell { row, col }, Anchor::default());
+        let range = Expr::Range(RangeFunction {
+            top_left: Cell { row: 0, col: 0 },
+            bottom_right: Cell { row: 4, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        });
+        let args = vec![
+            range.clone(),
+            cell(0, 2),
+            Expr::Value(10.0),
+            Expr::BinaryOp(FunctionType::Multiply, Box::new(cell(1, 3)), Box::new(Expr::Value(2.0))),
+        ];
+        assert_eq!(
+            parse_tree("SUM(A1:A5,C1,10,D2*2)", &backend),
+            Some(Expr::Call(FunctionType::Sum, args.clone()))
+        );
+        let function = parse_expression("SUM(A1:A5,C1,10,D2*2)", &backend).unwrap();
+        assert_eq!(function, Function::new_aggregate(FunctionType::Sum, args));
+
+        // A single range keeps the flat shape, a single cell is an argument list
+        let function = parse_expression("MAX(A1:A5)", &backend).unwrap();
+        assert!(matches!(function.data, FunctionData::RangeFunction(_)));
+        let function = parse_expression("COUNT(A1)", &backend).unwrap();
+        assert_eq!(function.data, FunctionData::Arguments(vec![cell(0, 0)]));
+
+        for invalid in ["SUM(A1:A5,)", "SUM(,A1)", "MIN(A1:A5 A6)", "MAX(A1:A5+1)"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_math_functions() {
+        let backend = Backend::new(10, 10);
+        let cell = Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default());
+        let power = |first, second| {
+            Expr::BinaryOp(FunctionType::Exponent, Box::new(first), Box::new(second))
+        };
+        // `^` binds tighter than `*`, is left associative and a leading minus comes first
+        assert_eq!(
+            parse_tree("2*A1^2", &backend),
+            Some(Expr::BinaryOp(
+                FunctionType::Multiply,
+                Box::new(Expr::Value(2.0)),
+                Box::new(power(cell.clone(), Expr::Value(2.0))),
+            ))
+        );
+        assert_eq!(
+            parse_tree("2^3^2", &backend),
+            Some(power(power(Expr::Value(2.0), Expr::Value(3.0)), Expr::Value(2.0)))
+        );
+        assert_eq!(
+            parse_tree("-A1^2", &backend),
+            Some(power(Expr::Negate(Box::new(cell.clone())), Expr::Value(2.0)))
+        );
+        assert_eq!(
+            parse_tree("ROUND(A1,-1)", &backend),
+            Some(Expr::Call(FunctionType::Round, vec![cell, Expr::Value(-1.0)]))
+        );
+
+        assert_eq!(parse_tree("RAND()", &backend), Some(Expr::Call(FunctionType::Rand, Vec::new())));
+        for invalid in [
+            "A1^",
+            "^2",
+            "SQRT(4,2)",
+            "MOD(4)",
+            "POWER(2,3,4)",
+            "ROUND()",
+            "ABS(A1:A2)",
+            "RAND(1)",
+            "RANDBETWEEN(1)",
+        ] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_text_functions() {
+        let backend = Backend::new(10, 10);
+        let cell = |row, col| Expr::Cell(Cell { row, col }, Anchor::default());
+        let binary = |function_type, first, second| {
+            Expr::BinaryOp(function_type, Box::new(first), Box::new(second))
+        };
+        // `&` binds looser than `+` and tighter than comparisons
+        assert_eq!(
+            parse_tree("A1&B1+1=C1", &backend),
+            Some(binary(
+                FunctionType::Equal,
+                binary(
+                    FunctionType::Concatenate,
+                    cell(0, 0),
+                    binary(FunctionType::Plus, cell(0, 1), Expr::Value(1.0)),
+                ),
+                cell(0, 2),
+            ))
+        );
+        let range = Expr::Range(RangeFunction {
+            top_left: Cell { row: 0, col: 0 },
+            bottom_right: Cell { row: 1, col: 0 },
+            top_left_anchor: Anchor::default(),
+            bottom_right_anchor: Anchor::default(),
+            span: RangeSpan::Cells,
+        });
+        assert_eq!(
+            parse_tree("CONCAT(A1:A2,\" \",B1)", &backend),
+            Some(Expr::Call(
+                FunctionType::Concat,
+                vec![range, Expr::Text(" ".to_string()), cell(0, 1)],
+            ))
+        );
+        assert_eq!(
+            parse_tree("MID(A1,2,3)", &backend),
+            Some(Expr::Call(
+                FunctionType::Mid,
+                vec![cell(0, 0), Expr::Value(2.0), Expr::Value(3.0)],
+            ))
+        );
+
+        for invalid in ["A1&", "&A1", "MID(A1,2)", "LEFT(A1,1,2)", "TEXT(A1)", "LEN(A1:A2)", "CONCAT()"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+        // Only aggregates over one range become range functions
+        let function = parse_expression("CONCAT(A1:B1)", &backend).unwrap();
+        assert!(matches!(function.data, FunctionData::Expression(_)));
+    }
+
+    #[test]
+    fn test_parse_date_literals() {
+        assert_eq!(parse_date("2026-10-16"), Some(46311.0));
+        assert_eq!(parse_date("1900-03-01"), Some(61.0));
+        assert_eq!(parse_date("2024-02-29 12:00"), Some(45351.5));
+        assert_eq!(parse_date("2024-02-29T06:00:00"), Some(45351.25));
+        for invalid in ["2026-1-16", "2025-02-29", "2026-13-01", "2026-10-00", "2026-10-16 24:00", "2026-10", "26-10-16"] {
+            assert_eq!(parse_date(invalid), None, "{}", invalid);
+        }
+
+        let backend = Backend::new(10, 10);
+        let function = parse_expression("2026-10-16", &backend).unwrap();
+        assert_eq!(function, Function::new_literal(CellValue::Date(46311.0)));
+        // Anything else stays arithmetic
+        let function = parse_expression("2026-13-16", &backend).unwrap();
+        assert_eq!(function.type_, FunctionType::Expression);
+
+        assert_eq!(
+            parse_tree("DATEDIF(A1,TODAY(),\"M\")", &backend),
+            Some(Expr::Call(
+                FunctionType::DateDif,
+                vec![
+                    Expr::Cell(Cell { row: 0, col: 0 }, Anchor::default()),
+                    Expr::Call(FunctionType::Today, Vec::new()),
+                    Expr::Text("M".to_string()),
+                ],
+            ))
+        );
+        for invalid in ["TODAY(1)", "NOW(", "DATE(2026,10)", "YEAR()", "DATEDIF(A1,A2)"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_case_insensitive() {
+        let mut backend = Backend::new(10, 10);
+        backend.add_sheet("Costs");
+        let pairs = [
+            ("sum(a1:b2)+max(A1,c3)", "SUM(A1:B2)+MAX(A1,C3)"),
+            ("if(true,$a$1,#n/a)", "IF(TRUE,$A$1,#N/A)"),
+            ("Stdev.S(a1:a3)*b$2", "STDEV.S(A1:A3)*B$2"),
+            ("costs!b2+COSTS!c3", "Costs!B2+Costs!C3"),
+            ("undefined_name", "UNDEFINED_NAME"),
+        ];
+        for (mixed, upper) in pairs {
+            let expected = parse_tree(upper, &backend);
+            assert!(expected.is_some(), "{}", upper);
+            assert_eq!(parse_tree(mixed, &backend), expected, "{}", mixed);
+        }
+        // Text keeps its case
+        assert_eq!(
+            parse_tree("\"abc\"&a1", &backend),
+            parse_tree("\"abc\"&A1", &backend)
+        );
+        assert_ne!(parse_tree("\"abc\"", &backend), parse_tree("\"ABC\"", &backend));
+
+        assert_eq!(parse_cell_reference("b7", 10, 10), Some(Cell { row: 6, col: 1 }));
+        assert_eq!(
+            parse_anchored_reference("$b$7", 10, 10),
+            Some((Cell { row: 6, col: 1 }, Anchor { row: true, col: true }))
+        );
+        assert_eq!(parse_cell_reference("b77", 10, 10), None);
+    }
+
+    #[test]
+    fn test_parse_whitespace() {
         let backend = Backend::new(10, 10);
+        let pairs = [
+            ("SUM( A1 : A3 ) + 2", "SUM(A1:A3)+2"),
+            ("\tIF( A1 >= 2 , \"x\" , B1 ^ 2 )\n", "IF(A1>=2,\"x\",B1^2)"),
+            ("- A1 * ( B1 - 3 )", "-A1*(B1-3)"),
+        ];
+        for (spaced, compact) in pairs {
+            assert_eq!(parse_tree(spaced, &backend), parse_tree(compact, &backend), "{}", spaced);
+        }
+        assert_eq!(
+            parse_tree("\" a  b \"", &backend),
+            Some(Expr::Text(" a  b ".to_string()))
+        );
+        for invalid in ["A 1", "1 2", "SU M(A1:A3)", "1. 5"] {
+            assert!(parse_expression(invalid, &backend).is_err(), "{}", invalid);
+        }
+        let function = parse_expression(" 2026-10-16 ", &backend).unwrap();
+        assert_eq!(function.data, FunctionData::Value(CellValue::Date(46311.0)));
+
+        let normalized = [
+            (" A1 = SUM( B1 : B3 ) ", "A1=SUM(B1:B3)"),
+            ("copy( A1 : B2 )", "copy(A1:B2)"),
+            ("define   TAX_RATE   =  B1", "define TAX_RATE=B1"),
+            ("A1 =  \"a  ( b \"  &  Costs ! B1", "A1=\"a  ( b \"&Costs!B1"),
+            ("A1 = 2026-10-16   14:30", "A1=2026-10-16 14:30"),
+            ("   ", ""),
+        ];
+        for (input, expected) in normalized {
+            assert_eq!(normalize_command(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut backend = Backend::new(10, 10);
+        backend.add_sheet("Costs");
+        let cases = [
+            ("FOO(A1)", 0, "FOO", "unknown function FOO"),
+            ("A1+B1000", 3, "B1000", "reference B1000 outside 10 rows"),
+            ("SUM(A1:K1)", 7, "K1", "reference K1 outside 10 columns"),
+            // References too long to count are outside the sheet, not names
+            ("ZZZZZZZZZZZZZZZZ1+1", 0, "ZZZZZZZZZZZZZZZZ1", "reference ZZZZZZZZZZZZZZZZ1 outside 10 columns"),
+            ("A99999999999999999999", 0, "A99999999999999999999", "reference A99999999999999999999 outside 10 rows"),
+            ("SUM(A1:$A$99999999999999999999)", 7, "$A$99999999999999999999", "reference $A$99999999999999999999 outside 10 rows"),
+            ("Costs!ZZZZZZZZZZZZZZZZ1", 6, "ZZZZZZZZZZZZZZZZ1", "reference ZZZZZZZZZZZZZZZZ1 outside 10 columns"),
+            ("A0*2", 0, "A0", "reference A0 outside 10 rows"),
+            ("SUM(A5:A1)", 4, "A5", "a range goes from its top left to its bottom right cell"),
+            ("COUNTIF(A1,1)", 8, "A1", "expected a range"),
+            ("ROUND()", 6, ")", "ROUND takes 1 to 2 arguments"),
+            ("IF(A1)", 5, ")", "IF takes 2 to 3 arguments"),
+            ("TODAY(1)", 7, ")", "TODAY takes no arguments"),
+            ("SQRT(1,2)", 8, ")", "SQRT takes 1 argument"),
+            ("A1+", 3, "", "unexpected end of formula"),
+            ("(A1", 3, "", "expected )"),
+            ("A1 B1", 3, "B1", "unexpected B1"),
+            ("A1 ? 2", 3, "?", "unexpected character ?"),
+            (".+1", 0, ".", "malformed number ."),
+            ("1e400", 0, "1e400", "number too large 1e400"),
+            ("\"abc", 0, "\"abc", "unterminated text \"abc"),
+            ("#OOPS!+1", 0, "#OOPS!", "unknown error #OOPS!"),
+            ("Nope!A1", 0, "Nope!", "unknown sheet Nope"),
+            ("Costs!+1", 6, "+", "expected a cell reference"),
+            ("STDEV.S+1", 0, "STDEV.S", "invalid name STDEV.S"),
+            // Offsets count bytes, the é takes two
+            ("\"é\"&FOO(1)", 5, "FOO", "unknown function FOO"),
+        ];
+        for (formula, offset, token, reason) in cases {
+            let expected = ParseError {
+                offset,
+                token: token.to_string(),
+                reason: reason.to_string(),
+            };
+            assert_eq!(parse_expression(formula, &backend), Err(expected), "{}", formula);
+        }
+        let error = parse_expression("A1+FOO(1)", &backend).unwrap_err();
+        assert_eq!(error.to_string(), "unknown function FOO at offset 3");
+    }
+
+    #[test]
+    fn test_parse_whole_column_and_row_ranges() {
+        // 10 rows, 5 columns
+        let mut backend = Backend::new(10, 5);
+        backend.add_sheet("Costs");
+        let anchor = |row, col| Anchor { row, col };
+
+        assert_eq!(
+            parse_expression("SUM(B:D)", &backend),
+            Ok(Function::new_range_function(
+                FunctionType::Sum,
+                RangeFunction {
+                    top_left: Cell { row: 0, col: 1 },
+                    bottom_right: Cell { row: 9, col: 3 },
+                    top_left_anchor: anchor(true, false),
+                    bottom_right_anchor: anchor(true, false),
+                    span: RangeSpan::Columns,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_expression("MAX(Costs!2:$3)", &backend),
+            Ok(Function::new_range_function(
+                FunctionType::Max,
+                RangeFunction {
+                    top_left: Cell { row: 11, col: 0 },
+                    bottom_right: Cell { row: 12, col: 4 },
+                    top_left_anchor: anchor(false, true),
+                    bottom_right_anchor: anchor(true, true),
+                    span: RangeSpan::Rows,
+                }
+            ))
+        );
+
+        let layout = backend.sheet_layout(Cell { row: 0, col: 0 });
+        for (input, printed) in [
+            ("sum(a:a)", "=SUM(A:A)"),
+            ("SUM($A:$C)", "=SUM($A:$C)"),
+            ("AVG(3:3)", "=AVG(3:3)"),
+            ("SUM(2:10)", "=SUM(2:10)"),
+            ("SUM($1:5)", "=SUM($1:5)"),
+            ("SUM(costs!B:B)", "=SUM(Costs!B:B)"),
+            ("SUM(A:A,1:1)+COUNTIF(E:E,\">1\")", "=SUM(A:A,1:1)+COUNTIF(E:E,\">1\")"),
+        ] {
+            let function = parse_expression(input, &backend).unwrap();
+            let text = function.to_formula_string(&layout);
+            assert_eq!(text, printed, "{}", input);
+            assert_eq!(parse_expression(&text[1..], &backend), Ok(function), "{}", input);
+        }
 
-        let (function, success) = parse_expression("Invalid", &backend);
-        assert!(!success);
-        assert_eq!(function.data, Function::new_constant(0).data);
+        let reason = |formula| parse_expression(formula, &backend).unwrap_err().reason;
+        assert_eq!(reason("SUM(A:F)"), "column F outside 5 columns");
+        assert_eq!(reason("SUM(0:2)"), "row 0 outside 10 rows");
+        assert_eq!(reason("SUM(2:11)"), "row 11 outside 10 rows");
+        assert_eq!(reason("SUM(1.5:2)"), "expected a row of 1 to 10");
+        assert_eq!(reason("SUM(A:3)"), "a range joins two cells, two columns or two rows");
+        assert_eq!(reason("SUM(A1:B)"), "a range joins two cells, two columns or two rows");
+        assert_eq!(reason("SUM(C:A)"), "a range goes from its top left to its bottom right cell");
+        assert_eq!(reason("SUM(4:2)"), "a range goes from its top left to its bottom right cell");
     }
 }
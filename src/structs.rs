@@ -1,6 +1,11 @@
 //! # Spreadsheet Structs Module
+use std::cmp::Ordering;
+use std::fmt;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 /// Represents a cell in a spreadsheet with row and column indices.
+/// Sheets are stacked in the workbook, so `row` counts the rows of the sheets before
+/// the one holding the cell.
 pub struct Cell {
     pub row: usize,
     pub col: usize,
@@ -8,31 +13,213 @@ pub struct Cell {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// CellError represents the possible errors that can occur in a cell.
+/// An error is passed on unchanged to every formula that reads the cell.
 pub enum CellError {
-    NoError,
-    DivideByZero,
-    DependencyError, // depends on cell which has div by zero
-    Overflow,
+    DivideByZero,      // #DIV/0!
+    InvalidReference,  // #REF! reference to a cell that does not exist
+    TypeMismatch,      // #VALUE! text used where a number is expected
+    UnknownName,       // #NAME? unknown function or name
+    CircularReference, // #CIRC! formula that refers back to itself
+    Num,               // #NUM! number argument out of the function's domain, such as SQRT(-1)
+    Overflow,          // #NUM! result too large to represent
+    NotAvailable,      // #N/A value that is not found, such as the number ranked by RANK
+}
+
+/// CellError methods
+impl CellError {
+    /// Every error kind, in the order they are listed above
+    pub const ALL: [CellError; 8] = [
+        CellError::DivideByZero,
+        CellError::InvalidReference,
+        CellError::TypeMismatch,
+        CellError::UnknownName,
+        CellError::CircularReference,
+        CellError::Num,
+        CellError::Overflow,
+        CellError::NotAvailable,
+    ];
+    /// Returns the code shown in the grid, such as `#DIV/0!`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CellError::DivideByZero => "#DIV/0!",
+            CellError::InvalidReference => "#REF!",
+            CellError::TypeMismatch => "#VALUE!",
+            CellError::UnknownName => "#NAME?",
+            CellError::CircularReference => "#CIRC!",
+            CellError::Num | CellError::Overflow => "#NUM!",
+            CellError::NotAvailable => "#N/A",
+        }
+    }
+    /// Returns the error whose code starts `text`, used to read error codes back from formulas
+    /// #NUM! is read back as `Num`, an overflow is not told apart once it is written down
+    pub fn from_code_prefix(text: &str) -> Option<CellError> {
+        CellError::ALL
+            .into_iter()
+            .find(|error| text.starts_with(error.code()))
+    }
+}
+
+/// Displays the error code.
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// CellValue represents the value held by a cell or produced by evaluating a formula.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellValue {
+    Number(f64),
+    /// Date serial: days since 1899-12-30 like in spreadsheets, the fraction is the time of day
+    Date(f64),
+    Text(String),
+    Bool(bool),
+    Empty,
+    Error(CellError),
+}
+
+/// CellValue methods
+impl CellValue {
+    /// Converts the value to a number for arithmetic.
+    /// Dates are their serial, booleans count as 1 and 0, empty cells as 0 and text is a
+    /// type mismatch.
+    pub fn as_number(&self) -> Result<f64, CellError> {
+        match self {
+            CellValue::Number(value) | CellValue::Date(value) => Ok(*value),
+            CellValue::Bool(value) => Ok(if *value { 1.0 } else { 0.0 }),
+            CellValue::Empty => Ok(0.0),
+            CellValue::Text(_) => Err(CellError::TypeMismatch),
+            CellValue::Error(error) => Err(*error),
+        }
+    }
+    /// Converts the value to a condition for IF, AND, OR and NOT.
+    /// Numbers are true when non-zero, empty cells are false and text is a type mismatch.
+    pub fn as_bool(&self) -> Result<bool, CellError> {
+        match self {
+            CellValue::Bool(value) => Ok(*value),
+            CellValue::Number(value) | CellValue::Date(value) => Ok(*value != 0.0),
+            CellValue::Empty => Ok(false),
+            CellValue::Text(_) => Err(CellError::TypeMismatch),
+            CellValue::Error(error) => Err(*error),
+        }
+    }
+    /// Converts the value to text for `&` and the text functions.
+    /// Numbers are written as shown in the grid, empty cells are empty text.
+    pub fn as_text(&self) -> Result<String, CellError> {
+        match self {
+            CellValue::Error(error) => Err(*error),
+            value => Ok(value.to_string()),
+        }
+    }
+    /// Returns true if the value should be rendered left-aligned (everything except numbers and dates)
+    pub fn is_left_aligned(&self) -> bool {
+        !matches!(self, CellValue::Number(_) | CellValue::Date(_))
+    }
+    /// Orders values for sorting: numbers and dates, then text, then booleans, then errors,
+    /// empty cells last.
+    pub fn sort_cmp(&self, other: &CellValue) -> Ordering {
+        fn rank(value: &CellValue) -> u8 {
+            match value {
+                CellValue::Number(_) | CellValue::Date(_) => 0,
+                CellValue::Text(_) => 1,
+                CellValue::Bool(_) => 2,
+                CellValue::Error(_) => 3,
+                CellValue::Empty => 4,
+            }
+        }
+        match (self, other) {
+            (
+                CellValue::Number(a) | CellValue::Date(a),
+                CellValue::Number(b) | CellValue::Date(b),
+            ) => a.total_cmp(b),
+            (CellValue::Text(a), CellValue::Text(b)) => a.to_lowercase().cmp(&b.to_lowercase()),
+            (CellValue::Bool(a), CellValue::Bool(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+}
+
+/// Converts the result of a numeric operation into a cell value.
+impl From<Result<f64, CellError>> for CellValue {
+    fn from(result: Result<f64, CellError>) -> Self {
+        match result {
+            Ok(value) => CellValue::Number(value),
+            Err(error) => CellValue::Error(error),
+        }
+    }
+}
+
+/// Displays the value as shown in the grid.
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellValue::Number(value) => write!(f, "{}", format_number(*value)),
+            CellValue::Date(serial) => write!(f, "{}", format_date(*serial)),
+            CellValue::Text(text) => write!(f, "{}", text),
+            CellValue::Bool(true) => write!(f, "TRUE"),
+            CellValue::Bool(false) => write!(f, "FALSE"),
+            CellValue::Empty => Ok(()),
+            CellValue::Error(error) => write!(f, "{}", error),
+        }
+    }
 }
 /// Represents the possible errors that can occur during expression parsing.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ExpressionError {
-    CouldNotParse,
+    CouldNotParse(ParseError),
     CircularDependency,
 }
 
-///Represents possible operand types: Cell or Int.
+/// Shows the reason as a message for the user.
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::CouldNotParse(error) => write!(f, "{}", error),
+            ExpressionError::CircularDependency => write!(f, "circular reference"),
+        }
+    }
+}
+
+/// Lets the commands that return boxed errors pass it on with `?`.
+impl std::error::Error for ExpressionError {}
+
+/// A formula that could not be parsed: where it went wrong, at which token and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the offending token in the formula, its length at the end
+    pub offset: usize,
+    /// Text of the offending token, empty at the end of the formula
+    pub token: String,
+    /// What is wrong, such as "unknown function FOO"
+    pub reason: String,
+}
+
+/// Shows the reason with the position, `unknown function FOO at offset 4`.
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.reason, self.offset)
+    }
+}
+
+///Represents possible operand types: Cell or Number.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OperandType {
     Cell,
-    Int,
+    Number,
 }
 
-/// OperandData represents the data contained in an operand, which can be either a Cell or an integer value.
+/// OperandData represents the data contained in an operand, which can be either a Cell or a numeric value.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OperandData {
     Cell(Cell),
-    Value(i32),
+    Value(f64),
+}
+/// Anchor records which parts of a cell reference are absolute (`$A$1`, `A$1`, `$A1`).
+/// Anchored parts keep pointing at the same row or column when a formula is copied.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Anchor {
+    pub row: bool,
+    pub col: bool,
 }
 /// Operand represents a single operand in an expression, stores it type and data.
 
@@ -40,6 +227,8 @@ pub enum OperandData {
 pub struct Operand {
     pub type_: OperandType,
     pub data: OperandData,
+    /// Anchoring of a cell operand, unused for numbers
+    pub anchor: Anchor,
 }
 /// BinaryOp represents a binary operation between two operands and stores them.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -53,6 +242,31 @@ pub struct BinaryOp {
 pub struct RangeFunction {
     pub top_left: Cell,
     pub bottom_right: Cell,
+    pub top_left_anchor: Anchor,
+    pub bottom_right_anchor: Anchor,
+    /// Whether the range was written between two cells or as whole columns or rows
+    pub span: RangeSpan,
+}
+/// RangeSpan tells how a range is written in a formula.
+/// Whole columns and rows cover the sheet from edge to edge, their other coordinate is
+/// stored anchored so it stays at the edges when the formula is copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RangeSpan {
+    /// Between two cells, A1:B5
+    #[default]
+    Cells,
+    /// Whole columns, A:A or B:D
+    Columns,
+    /// Whole rows, 3:3 or 2:10
+    Rows,
+}
+/// RangeFunction methods
+impl RangeFunction {
+    /// Returns true if `cell` lies inside the range
+    pub fn contains(&self, cell: Cell) -> bool {
+        (self.top_left.row..=self.bottom_right.row).contains(&cell.row)
+            && (self.top_left.col..=self.bottom_right.col).contains(&cell.col)
+    }
 }
 
 /// FunctionType represents the type of function being used in a cell, such as Min, Max, Avg, etc.
@@ -64,17 +278,561 @@ pub enum FunctionType {
     Avg,
     Sum,
     Stdev,
+    Count,
+    CountA,
+    CountBlank,
+    CountIf,
+    SumIf,
+    AverageIf,
+    Median,
+    Mode,
+    VarP,
+    VarS,
+    StdevP,
+    StdevS,
+    Percentile,
+    Quartile,
+    Large,
+    Small,
+    Rank,
+    VLookup,
+    HLookup,
+    Index,
+    Match,
+    Abs,
+    Mod,
+    Power,
+    Sqrt,
+    Round,
+    Floor,
+    Ceiling,
+    Sign,
+    Rand,
+    RandBetween,
+    Concat,
+    Len,
+    Left,
+    Right,
+    Mid,
+    Upper,
+    Lower,
+    Trim,
+    Text,
+    Value,
+    Today,
+    Now,
+    Date,
+    Year,
+    Month,
+    Day,
+    DateDif,
     Sleep,
     Plus, // Identity function can be written as A1+0
     Minus,
     Multiply,
     Divide,
+    Exponent,
+    Concatenate,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    If,
+    And,
+    Or,
+    Not,
+    IfError,
+    Expression,
+}
+/// FunctionType methods
+impl FunctionType {
+    /// Returns the operator symbol or function name used in formula text
+    pub fn name(&self) -> &'static str {
+        match self {
+            FunctionType::Constant | FunctionType::Expression => "",
+            FunctionType::Min => "MIN",
+            FunctionType::Max => "MAX",
+            FunctionType::Avg => "AVG",
+            FunctionType::Sum => "SUM",
+            FunctionType::Stdev => "STDEV",
+            FunctionType::Count => "COUNT",
+            FunctionType::CountA => "COUNTA",
+            FunctionType::CountBlank => "COUNTBLANK",
+            FunctionType::CountIf => "COUNTIF",
+            FunctionType::SumIf => "SUMIF",
+            FunctionType::AverageIf => "AVERAGEIF",
+            FunctionType::Median => "MEDIAN",
+            FunctionType::Mode => "MODE",
+            FunctionType::VarP => "VAR.P",
+            FunctionType::VarS => "VAR.S",
+            FunctionType::StdevP => "STDEV.P",
+            FunctionType::StdevS => "STDEV.S",
+            FunctionType::Percentile => "PERCENTILE",
+            FunctionType::Quartile => "QUARTILE",
+            FunctionType::Large => "LARGE",
+            FunctionType::Small => "SMALL",
+            FunctionType::Rank => "RANK",
+            FunctionType::VLookup => "VLOOKUP",
+            FunctionType::HLookup => "HLOOKUP",
+            FunctionType::Index => "INDEX",
+            FunctionType::Match => "MATCH",
+            FunctionType::Abs => "ABS",
+            FunctionType::Mod => "MOD",
+            FunctionType::Power => "POWER",
+            FunctionType::Sqrt => "SQRT",
+            FunctionType::Round => "ROUND",
+            FunctionType::Floor => "FLOOR",
+            FunctionType::Ceiling => "CEILING",
+            FunctionType::Sign => "SIGN",
+            FunctionType::Rand => "RAND",
+            FunctionType::RandBetween => "RANDBETWEEN",
+            FunctionType::Concat => "CONCAT",
+            FunctionType::Len => "LEN",
+            FunctionType::Left => "LEFT",
+            FunctionType::Right => "RIGHT",
+            FunctionType::Mid => "MID",
+            FunctionType::Upper => "UPPER",
+            FunctionType::Lower => "LOWER",
+            FunctionType::Trim => "TRIM",
+            FunctionType::Text => "TEXT",
+            FunctionType::Value => "VALUE",
+            FunctionType::Today => "TODAY",
+            FunctionType::Now => "NOW",
+            FunctionType::Date => "DATE",
+            FunctionType::Year => "YEAR",
+            FunctionType::Month => "MONTH",
+            FunctionType::Day => "DAY",
+            FunctionType::DateDif => "DATEDIF",
+            FunctionType::Sleep => "SLEEP",
+            FunctionType::Plus => "+",
+            FunctionType::Minus => "-",
+            FunctionType::Multiply => "*",
+            FunctionType::Divide => "/",
+            FunctionType::Exponent => "^",
+            FunctionType::Concatenate => "&",
+            FunctionType::Equal => "=",
+            FunctionType::NotEqual => "<>",
+            FunctionType::Less => "<",
+            FunctionType::LessEqual => "<=",
+            FunctionType::Greater => ">",
+            FunctionType::GreaterEqual => ">=",
+            FunctionType::If => "IF",
+            FunctionType::And => "AND",
+            FunctionType::Or => "OR",
+            FunctionType::Not => "NOT",
+            FunctionType::IfError => "IFERROR",
+        }
+    }
+    /// Returns true for the aggregates, which take any number of ranges, cells and values
+    pub fn is_aggregate(&self) -> bool {
+        matches!(
+            self,
+            FunctionType::Min
+                | FunctionType::Max
+                | FunctionType::Avg
+                | FunctionType::Sum
+                | FunctionType::Stdev
+                | FunctionType::Count
+                | FunctionType::CountA
+                | FunctionType::CountBlank
+                | FunctionType::Median
+                | FunctionType::Mode
+                | FunctionType::VarP
+                | FunctionType::VarS
+                | FunctionType::StdevP
+                | FunctionType::StdevS
+        )
+    }
+    /// Returns true for the volatile functions, whose value changes while their arguments
+    /// stay the same, so they are only refreshed by a recalculation
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            FunctionType::Today | FunctionType::Now | FunctionType::Rand | FunctionType::RandBetween
+        )
+    }
+}
+/// Expr represents a node of a parsed formula expression tree.
+///
+/// Formulas that do not fit one of the flat `FunctionData` shapes (for example `A1+B1*2`
+/// or `(A1+B1)/2`) are stored as a tree of `Expr` nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// Numeric literal
+    Value(f64),
+    /// Quoted string literal
+    Text(String),
+    /// TRUE or FALSE
+    Bool(bool),
+    /// Error literal such as #REF!
+    Error(CellError),
+    /// Reference to a single cell
+    Cell(Cell, Anchor),
+    /// Range of cells, only valid as the argument of a range function
+    Range(RangeFunction),
+    /// Defined name and the cell, range or constant it currently stands for,
+    /// #NAME? while the name is not defined
+    Name(String, Box<Expr>),
+    /// Unary minus
+    Negate(Box<Expr>),
+    /// Binary operation, the type is an arithmetic operator (Plus, Minus, Multiply, Divide)
+    /// or a comparison (Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual)
+    BinaryOp(FunctionType, Box<Expr>, Box<Expr>),
+    /// Function call such as SUM(A1:A3), SLEEP(B2) or IF(A1>0,B1,C1)
+    Call(FunctionType, Vec<Expr>),
 }
-/// FunctionData represents the data associated with a function, which can be a range of cells, a binary operation, sleep value or a constant value.
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Expr methods
+impl Expr {
+    /// Collects every cell this expression reads, single cells in `cells` and ranges, which
+    /// are not expanded, in `ranges`.
+    pub fn collect_references(&self, cells: &mut Vec<Cell>, ranges: &mut Vec<RangeFunction>) {
+        match self {
+            Expr::Value(_) | Expr::Text(_) | Expr::Bool(_) | Expr::Error(_) => {}
+            Expr::Cell(cell, _) => cells.push(*cell),
+            Expr::Range(range) => ranges.push(*range),
+            Expr::Name(_, target) => target.collect_references(cells, ranges),
+            Expr::Negate(inner) => inner.collect_references(cells, ranges),
+            Expr::BinaryOp(_, first, second) => {
+                first.collect_references(cells, ranges);
+                second.collect_references(cells, ranges);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_references(cells, ranges);
+                }
+            }
+        }
+    }
+    /// Returns true if the expression calls a volatile function such as NOW or RAND, whose
+    /// value changes while the cells it reads stay the same.
+    pub fn is_volatile(&self) -> bool {
+        match self {
+            Expr::Negate(inner) => inner.is_volatile(),
+            Expr::BinaryOp(_, first, second) => first.is_volatile() || second.is_volatile(),
+            Expr::Call(function_type, args) => {
+                function_type.is_volatile() || args.iter().any(Expr::is_volatile)
+            }
+            _ => false,
+        }
+    }
+    /// Collects every defined name this expression uses.
+    pub fn collect_names(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Name(name, _) => names.push(name.clone()),
+            Expr::Negate(inner) => inner.collect_names(names),
+            Expr::BinaryOp(_, first, second) => {
+                first.collect_names(names);
+                second.collect_names(names);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.collect_names(names);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Points every use of `name` at its new `target`.
+    pub fn rebind_name(&mut self, name: &str, target: &Expr) {
+        match self {
+            Expr::Name(used, current) if used == name => **current = target.clone(),
+            Expr::Negate(inner) => inner.rebind_name(name, target),
+            Expr::BinaryOp(_, first, second) => {
+                first.rebind_name(name, target);
+                second.rebind_name(name, target);
+            }
+            Expr::Call(_, args) => {
+                for arg in args {
+                    arg.rebind_name(name, target);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Returns what the node stands for, looking through defined names.
+    pub fn resolved(&self) -> &Expr {
+        match self {
+            Expr::Name(_, target) => target.resolved(),
+            _ => self,
+        }
+    }
+    /// Returns the cells of a range function argument, a single cell counts as a
+    /// one cell range.
+    pub fn as_range(&self) -> Option<RangeFunction> {
+        match self.resolved() {
+            Expr::Range(range) => Some(*range),
+            Expr::Cell(cell, anchor) => Some(RangeFunction {
+                top_left: *cell,
+                bottom_right: *cell,
+                top_left_anchor: *anchor,
+                bottom_right_anchor: *anchor,
+                span: RangeSpan::Cells,
+            }),
+            _ => None,
+        }
+    }
+    #[cfg(any(feature = "gui", test))]
+    /// Returns the expression as it would be after copying its cell `from` to `to`.
+    /// Relative parts of references move, anchored parts stay. References to the sheet of
+    /// `from` move to the sheet of `to`, references to other sheets stay on them.
+    /// References that would leave a sheet of `sheets.rows` x `cols` cells become #REF!.
+    pub fn relocate(&self, from: Cell, to: Cell, sheets: SheetRows, cols: usize) -> Expr {
+        let (from_sheet, to_sheet) = (sheets.sheet(from.row), sheets.sheet(to.row));
+        let row_offset = sheets.local_row(to.row) as isize - sheets.local_row(from.row) as isize;
+        let col_offset = to.col as isize - from.col as isize;
+        let shift = |cell: Cell, anchor: Anchor| -> Option<Cell> {
+            let (sheet, local_row) = (sheets.sheet(cell.row), sheets.local_row(cell.row));
+            let sheet = if sheet == from_sheet { to_sheet } else { sheet };
+            let row = if anchor.row { Some(local_row) } else { local_row.checked_add_signed(row_offset) };
+            let col = if anchor.col { Some(cell.col) } else { cell.col.checked_add_signed(col_offset) };
+            match (row, col) {
+                (Some(row), Some(col)) if row < sheets.rows && col < cols => Some(Cell {
+                    row: sheets.workbook_row(sheet, row),
+                    col,
+                }),
+                _ => None,
+            }
+        };
+        let invalid = Expr::Error(CellError::InvalidReference);
+        match self {
+            Expr::Cell(cell, anchor) => match shift(*cell, *anchor) {
+                Some(cell) => Expr::Cell(cell, *anchor),
+                None => invalid,
+            },
+            Expr::Range(range) => match (
+                shift(range.top_left, range.top_left_anchor),
+                shift(range.bottom_right, range.bottom_right_anchor),
+            ) {
+                // Anchoring only one corner can turn the range inside out
+                (Some(top_left), Some(bottom_right))
+                    if top_left.row <= bottom_right.row && top_left.col <= bottom_right.col =>
+                {
+                    Expr::Range(RangeFunction {
+                        top_left,
+                        bottom_right,
+                        ..*range
+                    })
+                }
+                _ => invalid,
+            },
+            Expr::Negate(inner) => {
+                Expr::Negate(Box::new(inner.relocate(from, to, sheets, cols)))
+            }
+            Expr::BinaryOp(function_type, first, second) => Expr::BinaryOp(
+                *function_type,
+                Box::new(first.relocate(from, to, sheets, cols)),
+                Box::new(second.relocate(from, to, sheets, cols)),
+            ),
+            Expr::Call(function_type, args) => Expr::Call(
+                *function_type,
+                args.iter()
+                    .map(|arg| arg.relocate(from, to, sheets, cols))
+                    .collect(),
+            ),
+            // Names always stand for the same cells wherever they are used
+            Expr::Value(_) | Expr::Text(_) | Expr::Bool(_) | Expr::Error(_) | Expr::Name(..) => {
+                self.clone()
+            }
+        }
+    }
+    /// Returns a printable view of the expression, as formula text that parses back to the
+    /// same tree with `$` anchors, sheet names and only the parentheses that are needed.
+    pub fn formula_text<'a>(&'a self, layout: &'a SheetLayout<'a>) -> FormulaText<'a> {
+        FormulaText { expr: self, layout }
+    }
+    /// Binding strength of the node when printed, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::BinaryOp(function_type, _, _) => match function_type {
+                FunctionType::Concatenate => 1,
+                FunctionType::Plus | FunctionType::Minus => 2,
+                FunctionType::Multiply | FunctionType::Divide => 3,
+                FunctionType::Exponent => 4,
+                _ => 0,
+            },
+            // A leading minus binds tighter than `^`, -2^2 is 4 like in spreadsheets
+            Expr::Negate(_) => 5,
+            _ => 6,
+        }
+    }
+}
+
+/// How the sheets of a workbook are stacked in one grid.
+///
+/// Sheet `i` holds the grid rows `i * rows..(i + 1) * rows`, every conversion between a
+/// workbook row and a sheet with a row on it goes through here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SheetRows {
+    /// Number of rows of every sheet
+    pub rows: usize,
+}
+
+/// SheetRows methods
+impl SheetRows {
+    /// Returns the sheet holding a workbook row
+    pub fn sheet(&self, row: usize) -> usize {
+        row / self.rows
+    }
+    /// Returns the row on its own sheet of a workbook row
+    pub fn local_row(&self, row: usize) -> usize {
+        row % self.rows
+    }
+    /// Returns the workbook row of row `local_row` on `sheet`
+    pub fn workbook_row(&self, sheet: usize, local_row: usize) -> usize {
+        sheet * self.rows + local_row
+    }
+    /// Returns the workbook rows of a sheet
+    pub fn span(&self, sheet: usize) -> std::ops::Range<usize> {
+        self.workbook_row(sheet, 0)..self.workbook_row(sheet + 1, 0)
+    }
+}
+
+/// Sheet names and size of a workbook.
+///
+/// References store workbook rows (see `SheetRows`), printing them needs the sheet they
+/// are on and its name.
+#[derive(Debug, Clone, Copy)]
+pub struct SheetLayout<'a> {
+    /// Sheet names in tab order
+    pub names: &'a [String],
+    /// How the sheets share the rows of the grid
+    pub sheets: SheetRows,
+    /// Sheet holding the formula, references to its own cells are printed without a sheet name
+    pub home: usize,
+}
+
+/// SheetLayout methods
+impl SheetLayout<'_> {
+    /// Returns the `Sheet2!` prefix for a reference to a cell on another sheet than `home`
+    fn sheet_prefix(&self, cell: Cell) -> String {
+        let sheet = self.sheets.sheet(cell.row);
+        if sheet == self.home {
+            String::new()
+        } else {
+            format!("{}!", self.names[sheet])
+        }
+    }
+    /// Returns the name of a reference as it reads on its own sheet
+    fn local_name(&self, cell: Cell, anchor: Anchor) -> String {
+        let local = Cell {
+            row: self.sheets.local_row(cell.row),
+            col: cell.col,
+        };
+        reference_name(local, anchor)
+    }
+    /// Returns the name of one end of a range: a cell, a column (`$B`) or a row (`3`)
+    fn range_end_name(&self, range: &RangeFunction, cell: Cell, anchor: Anchor) -> String {
+        let dollar = |anchored: bool| if anchored { "$" } else { "" };
+        match range.span {
+            RangeSpan::Cells => self.local_name(cell, anchor),
+            RangeSpan::Columns => format!("{}{}", dollar(anchor.col), column_name(cell.col)),
+            RangeSpan::Rows => format!("{}{}", dollar(anchor.row), self.sheets.local_row(cell.row) + 1),
+        }
+    }
+}
+
+/// Expression printed as the formula text of a cell on `layout.home`
+pub struct FormulaText<'a> {
+    expr: &'a Expr,
+    layout: &'a SheetLayout<'a>,
+}
+
+impl fmt::Display for FormulaText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layout = self.layout;
+        match self.expr {
+            Expr::Value(value) => write!(f, "{}", formula_number(*value)),
+            Expr::Text(text) => write!(f, "\"{}\"", text.replace('"', "\"\"")),
+            Expr::Bool(true) => write!(f, "TRUE"),
+            Expr::Bool(false) => write!(f, "FALSE"),
+            Expr::Error(error) => write!(f, "{}", error),
+            Expr::Name(name, _) => write!(f, "{}", name),
+            Expr::Cell(cell, anchor) => write!(
+                f,
+                "{}{}",
+                layout.sheet_prefix(*cell),
+                layout.local_name(*cell, *anchor)
+            ),
+            Expr::Range(range) => write!(
+                f,
+                "{}{}:{}",
+                layout.sheet_prefix(range.top_left),
+                layout.range_end_name(range, range.top_left, range.top_left_anchor),
+                layout.range_end_name(range, range.bottom_right, range.bottom_right_anchor)
+            ),
+            Expr::Negate(inner) => {
+                if inner.precedence() < self.expr.precedence() {
+                    write!(f, "-({})", inner.formula_text(layout))
+                } else {
+                    write!(f, "-{}", inner.formula_text(layout))
+                }
+            }
+            Expr::BinaryOp(function_type, first, second) => {
+                let precedence = self.expr.precedence();
+                // Operators are left associative, so only the right side needs
+                // parentheses at equal precedence
+                if first.precedence() < precedence {
+                    write!(f, "({})", first.formula_text(layout))?;
+                } else {
+                    write!(f, "{}", first.formula_text(layout))?;
+                }
+                write!(f, "{}", function_type.name())?;
+                if second.precedence() <= precedence {
+                    write!(f, "({})", second.formula_text(layout))
+                } else {
+                    write!(f, "{}", second.formula_text(layout))
+                }
+            }
+            Expr::Call(function_type, args) => {
+                write!(f, "{}(", function_type.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", arg.formula_text(layout))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+/// Formats a number so it parses back to exactly the same value.
+fn formula_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value)
+    } else {
+        // Debug output is the shortest text that round-trips, with an exponent when needed
+        format!("{:?}", value)
+    }
+}
+/// Returns the name of a cell reference with its anchors, such as `B$3`.
+pub fn reference_name(cell: Cell, anchor: Anchor) -> String {
+    format!(
+        "{}{}{}{}",
+        if anchor.col { "$" } else { "" },
+        column_name(cell.col),
+        if anchor.row { "$" } else { "" },
+        cell.row + 1
+    )
+}
+/// Returns the letters of a column, such as `AB` for column 27.
+fn column_name(col: usize) -> String {
+    let mut column = String::new();
+    let mut num = col + 1;
+    while num > 0 {
+        column.insert(0, (b'A' + ((num - 1) % 26) as u8) as char);
+        num = (num - 1) / 26;
+    }
+    column
+}
+/// FunctionData represents the data associated with a function, which can be a range of cells, a binary operation, sleep value, a constant value, an expression tree or an argument list.
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FunctionData {
-    /// Used for MinFunction, MaxFunction, AvgFunction, SumFunction, StdevFunction
+    /// Used for MinFunction, MaxFunction, AvgFunction, SumFunction, StdevFunction, the COUNT functions and the statistics of a single range
     RangeFunction(RangeFunction),
 
     /// Used for PlusOp, MinusOp, MultiplyOp, DivideOp
@@ -84,10 +842,51 @@ pub enum FunctionData {
     SleepValue(Operand),
 
     /// Used for Constant
-    Value(i32),
+    Value(CellValue),
+
+    /// Used for Expression
+    Expression(Box<Expr>),
+
+    /// Used for the aggregates over several arguments, such as SUM(A1:A5, C1, 10)
+    Arguments(Vec<Expr>),
+}
+/// FunctionData methods
+impl FunctionData {
+    /// Collects the cells the function reads, single cells in `cells` and ranges in `ranges`
+    pub fn collect_references(&self, cells: &mut Vec<Cell>, ranges: &mut Vec<RangeFunction>) {
+        match self {
+            FunctionData::RangeFunction(range) => ranges.push(*range),
+            FunctionData::BinaryOp(bin_op) => {
+                for operand in [&bin_op.first, &bin_op.second] {
+                    if let OperandData::Cell(cell) = operand.data {
+                        cells.push(cell);
+                    }
+                }
+            }
+            FunctionData::SleepValue(operand) => {
+                if let OperandData::Cell(cell) = operand.data {
+                    cells.push(cell);
+                }
+            }
+            FunctionData::Value(_) => {}
+            FunctionData::Expression(_) | FunctionData::Arguments(_) => {
+                for expr in self.trees() {
+                    expr.collect_references(cells, ranges);
+                }
+            }
+        }
+    }
+    /// Returns the expression trees held by the data, the flat shapes hold none
+    pub fn trees(&self) -> &[Expr] {
+        match self {
+            FunctionData::Expression(expr) => std::slice::from_ref(&**expr),
+            FunctionData::Arguments(args) => args,
+            _ => &[],
+        }
+    }
 }
 /// Function represents a function in a cell, stores its type and data.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub type_: FunctionType,
     pub data: FunctionData,
@@ -97,20 +896,22 @@ pub struct Function {
 impl Function {
     ///Creates a new range Function instance with the given type and data.
     pub fn new_range_function(type_: FunctionType, range: RangeFunction) -> Self {
-        assert!(matches!(
-            type_,
-            FunctionType::Min
-                | FunctionType::Max
-                | FunctionType::Avg
-                | FunctionType::Sum
-                | FunctionType::Stdev
-        ));
+        assert!(type_.is_aggregate());
 
         Function {
             type_,
             data: FunctionData::RangeFunction(range),
         }
     }
+    ///Creates a new aggregate Function instance over a list of arguments.
+    pub fn new_aggregate(type_: FunctionType, args: Vec<Expr>) -> Self {
+        assert!(type_.is_aggregate());
+
+        Function {
+            type_,
+            data: FunctionData::Arguments(args),
+        }
+    }
     ///Creates a new binary Function instance with the given type and data.
     pub fn new_binary_op(type_: FunctionType, op: BinaryOp) -> Self {
         assert!(matches!(
@@ -127,40 +928,181 @@ impl Function {
         }
     }
     /// Creates a new constant Function instance with the given type and data.
-    pub fn new_constant(value: i32) -> Self {
+    pub fn new_constant(value: f64) -> Self {
+        Function::new_literal(CellValue::Number(value))
+    }
+    /// Creates a new constant Function instance holding any kind of value (text, boolean, empty).
+    pub fn new_literal(value: CellValue) -> Self {
         Function {
             type_: FunctionType::Constant,
             data: FunctionData::Value(value),
         }
     }
+    /// Creates a new expression tree Function instance with the given root node.
+    pub fn new_expression(expr: Expr) -> Self {
+        Function {
+            type_: FunctionType::Expression,
+            data: FunctionData::Expression(Box::new(expr)),
+        }
+    }
     /// Creates a new sleep Function(for value) instance with the given type and data.
-    pub fn new_sleep(value: i32) -> Self {
+    pub fn new_sleep(value: f64) -> Self {
         Function {
             type_: FunctionType::Sleep,
             data: FunctionData::SleepValue(Operand {
-                type_: OperandType::Int,
+                type_: OperandType::Number,
                 data: OperandData::Value(value),
+                anchor: Anchor::default(),
             }),
         }
     }
     /// Creates a new sleep(for cell reference )function instance with the given type and data.
-    pub fn new_sleep_cell(cell: Cell) -> Self {
+    pub fn new_sleep_cell(cell: Cell, anchor: Anchor) -> Self {
         Function {
             type_: FunctionType::Sleep,
             data: FunctionData::SleepValue(Operand {
                 type_: OperandType::Cell,
                 data: OperandData::Cell(cell),
+                anchor,
             }),
         }
     }
+    /// Returns the formula as an expression tree, flat shapes are expanded into their nodes.
+    /// An empty cell has no formula and gives None.
+    pub fn to_expr(&self) -> Option<Expr> {
+        let leaf = |operand: &Operand| match operand.data {
+            OperandData::Cell(cell) => Expr::Cell(cell, operand.anchor),
+            OperandData::Value(value) => Expr::Value(value),
+        };
+        Some(match &self.data {
+            FunctionData::RangeFunction(range) => {
+                Expr::Call(self.type_, vec![Expr::Range(*range)])
+            }
+            FunctionData::BinaryOp(bin_op) => Expr::BinaryOp(
+                self.type_,
+                Box::new(leaf(&bin_op.first)),
+                Box::new(leaf(&bin_op.second)),
+            ),
+            FunctionData::SleepValue(operand) => Expr::Call(FunctionType::Sleep, vec![leaf(operand)]),
+            FunctionData::Value(value) => match value {
+                CellValue::Number(value) => Expr::Value(*value),
+                CellValue::Date(serial) => {
+                    // Written back as DATE(year,month,day) plus the time of day
+                    let (year, month, day) = serial_date(serial.floor() as i64);
+                    let args = [year as f64, month as f64, day as f64];
+                    let date = Expr::Call(FunctionType::Date, args.map(Expr::Value).to_vec());
+                    if serial.fract() == 0.0 {
+                        date
+                    } else {
+                        Expr::BinaryOp(
+                            FunctionType::Plus,
+                            Box::new(date),
+                            Box::new(Expr::Value(serial.fract())),
+                        )
+                    }
+                }
+                CellValue::Text(text) => Expr::Text(text.clone()),
+                CellValue::Bool(value) => Expr::Bool(*value),
+                CellValue::Error(error) => Expr::Error(*error),
+                CellValue::Empty => return None,
+            },
+            FunctionData::Expression(expr) => (**expr).clone(),
+            FunctionData::Arguments(args) => Expr::Call(self.type_, args.clone()),
+        })
+    }
+    /// Returns the canonical text of the function in a cell on `layout.home`, as shown in
+    /// the formula bar and written to saved files.
+    /// Formulas start with `=`, constants are written as they are typed (`42`, `"text"`,
+    /// `TRUE`, `2026-10-16`) and an empty cell gives empty text. The text parses back to
+    /// the same function.
+    pub fn to_formula_string(&self, layout: &SheetLayout) -> String {
+        if let FunctionData::Value(CellValue::Date(serial)) = self.data {
+            return format_date(serial);
+        }
+        let Some(expr) = self.to_expr() else {
+            return String::new();
+        };
+        let text = expr.formula_text(layout).to_string();
+        if self.type_ == FunctionType::Constant {
+            text
+        } else {
+            format!("={}", text)
+        }
+    }
+}
+/// Returns the serial of a date, the number of days since 1899-12-30.
+/// From 1900-03-01 on this is the serial spreadsheets use.
+pub fn date_serial(year: i64, month: u32, day: u32) -> i64 {
+    // Days from civil, counting years from March so the leap day ends a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 days from 0000-03-01 to 1970-01-01, which is serial 25569
+    era * 146097 + day_of_era - 719468 + 25569
+}
+/// Returns the year, month and day of a date serial, the inverse of `date_serial`.
+pub fn serial_date(serial: i64) -> (i64, u32, u32) {
+    let days = serial - 25569 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+/// Formats a date serial as `2026-10-16`, with the time as `2026-10-16 14:30:00` when it
+/// is not midnight.
+pub fn format_date(serial: f64) -> String {
+    let mut days = serial.floor();
+    let mut seconds = ((serial - days) * 86400.0).round() as i64;
+    if seconds == 86400 {
+        days += 1.0;
+        seconds = 0;
+    }
+    let (year, month, day) = serial_date(days as i64);
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    if seconds == 0 {
+        return date;
+    }
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        date,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+/// Formats a numeric cell value for display.
+///
+/// Whole numbers are printed without a decimal point, other values are rounded to at
+/// most 6 decimals and very large or very small magnitudes use scientific notation.
+pub fn format_number(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude == 0.0 {
+        return "0".to_string();
+    }
+    if !(1e-6..1e15).contains(&magnitude) {
+        return format!("{:e}", value);
+    }
+    if value.fract() == 0.0 {
+        return format!("{}", value);
+    }
+    let rounded = format!("{:.6}", value);
+    rounded.trim_end_matches('0').trim_end_matches('.').to_string()
 }
-/// CellData represents the data associated with a cell in a spreadsheet, including its value, dependents, function, error state, and dirty parents count.
+/// CellData represents the data associated with a cell in a spreadsheet, including its value (or error), dependents, function, and dirty parents count.
 #[derive(Debug, Clone)]
 pub struct CellData {
-    pub value: i32,
+    pub value: CellValue,
     pub dependents: Vec<(i32, i32)>,
     pub function: Function,
-    pub error: CellError,
     pub dirty_parents: i32,
 }
 /// CellData methods
@@ -168,13 +1110,12 @@ impl Default for CellData {
     /// Creates a new CellData instance with default values.
     fn default() -> Self {
         CellData {
-            value: 0,
+            value: CellValue::Empty,
             dependents: Vec::new(),
             function: Function {
                 type_: FunctionType::Constant,
-                data: FunctionData::Value(0),
+                data: FunctionData::Value(CellValue::Empty),
             },
-            error: CellError::NoError,
             dirty_parents: 0,
         }
     }